@@ -0,0 +1,10 @@
+CREATE TABLE user_group_points (
+  user_id INTEGER NOT NULL REFERENCES users,
+  group_id INTEGER NOT NULL REFERENCES groups,
+
+  winner INTEGER NOT NULL,
+  runnerup INTEGER NOT NULL,
+  total INTEGER NOT NULL,
+
+  PRIMARY KEY (user_id, group_id)
+);
@@ -0,0 +1,5 @@
+ALTER TABLE users
+  ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Until now the first user was the only one allowed to enter results
+UPDATE users SET is_admin = TRUE WHERE user_id = 1;
@@ -0,0 +1,2 @@
+DROP TABLE league_memberships;
+DROP TABLE leagues;
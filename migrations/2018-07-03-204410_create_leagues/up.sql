@@ -0,0 +1,19 @@
+CREATE TABLE leagues (
+  league_id INTEGER GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+  name VARCHAR NOT NULL,
+  invite_code VARCHAR NOT NULL,
+  created_by INTEGER NOT NULL REFERENCES users(user_id),
+
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+  UNIQUE (invite_code)
+);
+
+CREATE TABLE league_memberships (
+  league_id INTEGER NOT NULL REFERENCES leagues ON DELETE CASCADE,
+  user_id INTEGER NOT NULL REFERENCES users,
+
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+  PRIMARY KEY (league_id, user_id)
+);
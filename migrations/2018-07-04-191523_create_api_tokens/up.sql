@@ -0,0 +1,12 @@
+CREATE TABLE api_tokens (
+  api_token_id INTEGER GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+  user_id INTEGER NOT NULL REFERENCES users ON DELETE CASCADE,
+  name VARCHAR NOT NULL,
+  -- Only a SHA-256 hash of the token is stored, the token itself is shown once
+  token_hash VARCHAR NOT NULL,
+
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  last_used_at TIMESTAMP,
+
+  UNIQUE (token_hash)
+);
@@ -0,0 +1,12 @@
+CREATE TABLE password_reset_tokens (
+  password_reset_token_id INTEGER GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+  user_id INTEGER NOT NULL REFERENCES users ON DELETE CASCADE,
+  -- Only a SHA-256 hash of the token is stored, the token itself is mailed to the user
+  token_hash VARCHAR NOT NULL,
+
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  expires_at TIMESTAMP NOT NULL,
+  used_at TIMESTAMP,
+
+  UNIQUE (token_hash)
+);
@@ -0,0 +1,12 @@
+CREATE TABLE login_attempts (
+  login_attempt_id INTEGER GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+  -- What was entered as username, which doesn't need to be an existing account
+  login VARCHAR NOT NULL,
+  ip_address VARCHAR NOT NULL,
+  successful BOOLEAN NOT NULL,
+
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX login_attempts_login ON login_attempts (login, created_at);
+CREATE INDEX login_attempts_ip_address ON login_attempts (ip_address, created_at);
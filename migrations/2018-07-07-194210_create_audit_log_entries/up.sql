@@ -0,0 +1,34 @@
+-- Append-only history of changes to predictions, favourites and match outcomes, the triggers
+-- below reject every UPDATE, DELETE and TRUNCATE. For the same reason users that appear in the
+-- history can't be deleted, instead of the history being changed along with them.
+CREATE TABLE audit_log_entries (
+  audit_log_entry_id INTEGER GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+  -- Who made the change, NULL when it was made by an import or another background job
+  actor_id INTEGER REFERENCES users,
+  -- Whose prediction or favourite changed, NULL for match outcomes
+  user_id INTEGER REFERENCES users,
+  match_id INTEGER,
+  -- 'match_prediction', 'favourite' or 'match_outcome'
+  subject VARCHAR NOT NULL,
+  -- How the change was made, e.g. 'manual', 'lucky', 'api' or 'admin'
+  source VARCHAR NOT NULL,
+  old_value JSONB,
+  new_value JSONB,
+
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX audit_log_entries_user_id ON audit_log_entries (user_id, created_at);
+CREATE INDEX audit_log_entries_match_id ON audit_log_entries (match_id, created_at);
+
+CREATE FUNCTION audit_log_entries_are_append_only() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_log_entries can not be changed';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_log_entries_append_only BEFORE UPDATE OR DELETE ON audit_log_entries
+    FOR EACH ROW EXECUTE PROCEDURE audit_log_entries_are_append_only();
+
+CREATE TRIGGER audit_log_entries_no_truncate BEFORE TRUNCATE ON audit_log_entries
+    FOR EACH STATEMENT EXECUTE PROCEDURE audit_log_entries_are_append_only();
@@ -0,0 +1,48 @@
+-- The favourites phase during which the matches of the stage are played, and whether the stage
+-- decides the champion, so no code has to know the stage ids of a particular tournament
+ALTER TABLE stages
+  ADD COLUMN phase SMALLINT NOT NULL DEFAULT 0,
+  ADD COLUMN is_final BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- The stages of the 2018 World Cup: group round, round of 16, quarter-finals, semi-finals,
+-- final and third place playoff
+UPDATE stages SET phase = 1 WHERE stage_id IN (2, 3);
+UPDATE stages SET phase = 2 WHERE stage_id IN (4, 5, 6);
+UPDATE stages SET is_final = TRUE WHERE stage_id = 5;
+
+-- Whether a match is a knock-out match no longer has to be guessed from its participants
+CREATE OR REPLACE VIEW full_match_infos
+AS SELECT
+  matches.match_id as match_id,
+  matches.location_id as location_id,
+  matches.time as time,
+
+  home_participant.group_id as home_group_id,
+  home_participant.group_drawn_place as home_group_drawn_place,
+  home_participant.previous_match_id as home_previous_match_id,
+  home_participant.result as home_previous_match_result,
+
+  home_country.name as home_country_name,
+  home_country.flag as home_country_flag,
+
+  away_participant.group_id as away_group_id,
+  away_participant.group_drawn_place as away_group_drawn_place,
+  away_participant.previous_match_id as away_previous_match_id,
+  away_participant.result as away_previous_match_result,
+
+  away_country.name as away_country_name,
+  away_country.flag as away_country_flag,
+
+  stages.stage_type = 'knockout' as is_knockout
+FROM
+  matches
+  INNER JOIN match_participants as home_participant
+    ON matches.home_participant_id = home_participant.match_participant_id
+  LEFT OUTER JOIN countries as home_country
+    ON home_participant.country_id = home_country.country_id
+  INNER JOIN match_participants as away_participant
+    ON matches.away_participant_id = away_participant.match_participant_id
+  LEFT OUTER JOIN countries as away_country
+    ON away_participant.country_id = away_country.country_id
+  INNER JOIN stages
+    ON matches.stage_id = stages.stage_id;
@@ -0,0 +1,74 @@
+DROP VIEW full_match_infos;
+
+CREATE VIEW full_match_infos
+AS SELECT
+  matches.match_id as match_id,
+  matches.location_id as location_id,
+  matches.time as time,
+
+  home_participant.group_id as home_group_id,
+  home_participant.group_drawn_place as home_group_drawn_place,
+  home_participant.previous_match_id as home_previous_match_id,
+  home_participant.result as home_previous_match_result,
+
+  home_country.name as home_country_name,
+  home_country.flag as home_country_flag,
+
+  away_participant.group_id as away_group_id,
+  away_participant.group_drawn_place as away_group_drawn_place,
+  away_participant.previous_match_id as away_previous_match_id,
+  away_participant.result as away_previous_match_result,
+
+  away_country.name as away_country_name,
+  away_country.flag as away_country_flag,
+
+  stages.stage_type = 'knockout' as is_knockout
+FROM
+  matches
+  INNER JOIN match_participants as home_participant
+    ON matches.home_participant_id = home_participant.match_participant_id
+  LEFT OUTER JOIN countries as home_country
+    ON home_participant.country_id = home_country.country_id
+  INNER JOIN match_participants as away_participant
+    ON matches.away_participant_id = away_participant.match_participant_id
+  LEFT OUTER JOIN countries as away_country
+    ON away_participant.country_id = away_country.country_id
+  INNER JOIN stages
+    ON matches.stage_id = stages.stage_id;
+
+DROP TABLE tournament_scores;
+
+ALTER TABLE users DROP COLUMN tournament_id;
+
+ALTER TABLE countries
+  ADD COLUMN qualified_for_knockout BOOLEAN NOT NULL DEFAULT FALSE;
+UPDATE countries SET qualified_for_knockout = TRUE
+  WHERE country_id IN (
+    SELECT country_id FROM group_memberships WHERE qualified_for_knockout
+  );
+ALTER TABLE group_memberships DROP COLUMN qualified_for_knockout;
+
+-- Only the favourites of one tournament fit the old keys
+DELETE FROM favourites
+  WHERE tournament_id <> (SELECT min(tournament_id) FROM tournaments);
+ALTER TABLE favourites
+  DROP CONSTRAINT favourites_pkey,
+  DROP CONSTRAINT favourites_user_id_country_id_key,
+  DROP CONSTRAINT favourites_user_id_phase_choice_key,
+  DROP COLUMN tournament_id,
+  ADD PRIMARY KEY (user_id, choice),
+  ADD CONSTRAINT favourites_user_id_country_id_key UNIQUE (user_id, phase, country_id),
+  ADD CONSTRAINT favourites_user_id_phase_choice_key UNIQUE (user_id, phase, choice);
+
+ALTER TABLE matches
+  ALTER COLUMN match_id DROP IDENTITY,
+  DROP COLUMN match_number,
+  DROP COLUMN tournament_id;
+
+ALTER TABLE groups DROP COLUMN tournament_id;
+
+ALTER TABLE stages
+  DROP COLUMN stage_number,
+  DROP COLUMN tournament_id;
+
+DROP TABLE tournaments;
@@ -0,0 +1,118 @@
+-- Stages, groups, matches and favourites belong to a tournament, so the pool can run every
+-- World Cup and Euro with the same accounts and keep the history of the previous ones
+CREATE TABLE tournaments (
+  tournament_id INTEGER GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+  name VARCHAR NOT NULL UNIQUE,
+  -- Archived tournaments are only shown when a user switches to them
+  is_archived BOOLEAN NOT NULL DEFAULT FALSE,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Everything so far belongs to the 2018 World Cup
+INSERT INTO tournaments (name) VALUES ('World Cup 2018');
+
+ALTER TABLE stages
+  ADD COLUMN tournament_id INTEGER REFERENCES tournaments,
+  ADD COLUMN stage_number INTEGER;
+UPDATE stages SET tournament_id = (SELECT min(tournament_id) FROM tournaments),
+                  stage_number = stage_id;
+ALTER TABLE stages
+  ALTER COLUMN tournament_id SET NOT NULL,
+  ALTER COLUMN stage_number SET NOT NULL,
+  ADD UNIQUE (tournament_id, stage_number);
+SELECT setval(pg_get_serial_sequence('stages', 'stage_id'), max(stage_id)) FROM stages;
+
+ALTER TABLE groups ADD COLUMN tournament_id INTEGER REFERENCES tournaments;
+UPDATE groups SET tournament_id = (SELECT min(tournament_id) FROM tournaments);
+ALTER TABLE groups
+  ALTER COLUMN tournament_id SET NOT NULL,
+  ADD UNIQUE (tournament_id, name);
+
+-- Match ids used to be the match numbers of the schedule, which start at 1 for every
+-- tournament, so the number moves to its own column and the id is generated
+ALTER TABLE matches
+  ADD COLUMN tournament_id INTEGER REFERENCES tournaments,
+  ADD COLUMN match_number INTEGER;
+UPDATE matches SET tournament_id = (SELECT min(tournament_id) FROM tournaments),
+                   match_number = match_id;
+ALTER TABLE matches
+  ALTER COLUMN tournament_id SET NOT NULL,
+  ALTER COLUMN match_number SET NOT NULL,
+  ALTER COLUMN match_id ADD GENERATED BY DEFAULT AS IDENTITY,
+  ADD UNIQUE (tournament_id, match_number);
+SELECT setval(pg_get_serial_sequence('matches', 'match_id'), max(match_id)) FROM matches;
+
+ALTER TABLE favourites ADD COLUMN tournament_id INTEGER REFERENCES tournaments;
+UPDATE favourites SET tournament_id = (SELECT min(tournament_id) FROM tournaments);
+ALTER TABLE favourites
+  ALTER COLUMN tournament_id SET NOT NULL,
+  DROP CONSTRAINT favourites_pkey,
+  DROP CONSTRAINT favourites_user_id_country_id_key,
+  DROP CONSTRAINT favourites_user_id_phase_choice_key,
+  ADD PRIMARY KEY (user_id, tournament_id, choice),
+  ADD CONSTRAINT favourites_user_id_country_id_key
+    UNIQUE (user_id, tournament_id, phase, country_id),
+  ADD CONSTRAINT favourites_user_id_phase_choice_key
+    UNIQUE (user_id, tournament_id, phase, choice);
+
+-- Whether a country made it out of its group depends on the tournament
+ALTER TABLE group_memberships
+  ADD COLUMN qualified_for_knockout BOOLEAN NOT NULL DEFAULT FALSE;
+UPDATE group_memberships SET qualified_for_knockout = countries.qualified_for_knockout
+  FROM countries
+  WHERE group_memberships.country_id = countries.country_id;
+ALTER TABLE countries DROP COLUMN qualified_for_knockout;
+
+-- The tournament a user is looking at, NULL for the current one
+ALTER TABLE users ADD COLUMN tournament_id INTEGER REFERENCES tournaments ON DELETE SET NULL;
+
+-- `users.score` keeps the all-time total, this is the score per tournament
+CREATE TABLE tournament_scores (
+  tournament_id INTEGER NOT NULL REFERENCES tournaments,
+  user_id INTEGER NOT NULL REFERENCES users,
+  score INTEGER NOT NULL,
+
+  PRIMARY KEY (tournament_id, user_id)
+);
+
+INSERT INTO tournament_scores (tournament_id, user_id, score)
+  SELECT (SELECT min(tournament_id) FROM tournaments), user_id, score FROM users;
+
+CREATE OR REPLACE VIEW full_match_infos
+AS SELECT
+  matches.match_id as match_id,
+  matches.location_id as location_id,
+  matches.time as time,
+
+  home_participant.group_id as home_group_id,
+  home_participant.group_drawn_place as home_group_drawn_place,
+  home_participant.previous_match_id as home_previous_match_id,
+  home_participant.result as home_previous_match_result,
+
+  home_country.name as home_country_name,
+  home_country.flag as home_country_flag,
+
+  away_participant.group_id as away_group_id,
+  away_participant.group_drawn_place as away_group_drawn_place,
+  away_participant.previous_match_id as away_previous_match_id,
+  away_participant.result as away_previous_match_result,
+
+  away_country.name as away_country_name,
+  away_country.flag as away_country_flag,
+
+  stages.stage_type = 'knockout' as is_knockout,
+
+  matches.tournament_id as tournament_id,
+  matches.match_number as match_number
+FROM
+  matches
+  INNER JOIN match_participants as home_participant
+    ON matches.home_participant_id = home_participant.match_participant_id
+  LEFT OUTER JOIN countries as home_country
+    ON home_participant.country_id = home_country.country_id
+  INNER JOIN match_participants as away_participant
+    ON matches.away_participant_id = away_participant.match_participant_id
+  LEFT OUTER JOIN countries as away_country
+    ON away_participant.country_id = away_country.country_id
+  INNER JOIN stages
+    ON matches.stage_id = stages.stage_id;
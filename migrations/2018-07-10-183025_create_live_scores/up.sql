@@ -0,0 +1,13 @@
+-- The score of matches that are being played, as reported by the live score provider. The final
+-- outcome goes to match_outcomes, these are only shown until then.
+CREATE TABLE live_scores (
+  match_id INTEGER PRIMARY KEY REFERENCES matches,
+  home_score SMALLINT NOT NULL,
+  away_score SMALLINT NOT NULL,
+  -- The minute of play, when the provider reports it
+  minute SMALLINT,
+
+  updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+SELECT diesel_manage_updated_at('live_scores');
@@ -0,0 +1,6 @@
+-- The matches for which the reminder to predict was posted to Slack, so it is posted only once
+CREATE TABLE slack_reminders (
+  match_id INTEGER PRIMARY KEY REFERENCES matches,
+
+  sent_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
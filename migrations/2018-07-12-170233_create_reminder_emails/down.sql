@@ -0,0 +1,5 @@
+DROP TABLE unsubscribe_tokens;
+DROP TABLE reminder_emails;
+
+ALTER TABLE users
+  DROP COLUMN wants_reminder_emails;
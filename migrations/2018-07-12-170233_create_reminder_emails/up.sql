@@ -0,0 +1,26 @@
+-- Users that turn it on in their settings get an email when they forgot to predict a match that
+-- kicks off soon
+ALTER TABLE users
+  ADD COLUMN wants_reminder_emails BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- The matches every user was reminded of, so every match is mentioned only once
+CREATE TABLE reminder_emails (
+  user_id INTEGER NOT NULL REFERENCES users ON DELETE CASCADE,
+  match_id INTEGER NOT NULL REFERENCES matches,
+
+  sent_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+  PRIMARY KEY (user_id, match_id)
+);
+
+-- Every reminder email has its own unsubscribe link, which works without logging in
+CREATE TABLE unsubscribe_tokens (
+  unsubscribe_token_id INTEGER GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+  user_id INTEGER NOT NULL REFERENCES users ON DELETE CASCADE,
+  -- Only a SHA-256 hash of the token is stored, the token itself is mailed to the user
+  token_hash VARCHAR NOT NULL,
+
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+  UNIQUE (token_hash)
+);
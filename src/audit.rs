@@ -0,0 +1,82 @@
+//! Predictions, favourites and match outcomes are changed in place, so every change is also
+//! appended to `audit_log_entries`, to be able to tell who changed what and when.
+
+use diesel::{self, prelude::*};
+use models::{Favourite, MatchOutcome, MatchPrediction, NewAuditLogEntry};
+use serde_json::{self, Value};
+
+pub const MATCH_PREDICTION: &str = "match_prediction";
+pub const FAVOURITE: &str = "favourite";
+pub const MATCH_OUTCOME: &str = "match_outcome";
+
+/// The values that are recorded in the audit log, without bookkeeping like `updated_at`
+pub trait Audited {
+    fn audit_value(&self) -> Value;
+}
+
+impl Audited for MatchPrediction {
+    fn audit_value(&self) -> Value {
+        json!({
+            "home_score": self.home_score,
+            "away_score": self.away_score,
+            "time_of_first_goal": self.time_of_first_goal,
+            "home_penalties": self.home_penalties,
+            "away_penalties": self.away_penalties,
+            "duration": self.duration,
+        })
+    }
+}
+
+impl Audited for Favourite {
+    fn audit_value(&self) -> Value {
+        json!({
+            "phase": self.phase,
+            "choice": self.choice,
+            "country_id": self.country_id,
+        })
+    }
+}
+
+impl Audited for MatchOutcome {
+    fn audit_value(&self) -> Value {
+        serde_json::to_value(self).unwrap_or(Value::Null)
+    }
+}
+
+pub struct Change<'a, T: 'a> {
+    pub subject: &'static str,
+    /// The user that made the change, `None` for imports and other background jobs
+    pub actor_id: Option<i32>,
+    pub user_id: Option<i32>,
+    pub match_id: Option<i32>,
+    pub source: &'a str,
+    pub before: Option<&'a T>,
+    pub after: Option<&'a T>,
+}
+
+/// Append the change to the audit log, unless nothing actually changed. Call this within the
+/// transaction that makes the change, so the change and its history are stored together.
+pub fn record<T: Audited>(conn: &PgConnection, change: &Change<T>) -> QueryResult<()> {
+    use schema::audit_log_entries;
+
+    let old_value = change.before.map(Audited::audit_value);
+    let new_value = change.after.map(Audited::audit_value);
+
+    if old_value == new_value {
+        return Ok(());
+    }
+
+    diesel::insert_into(audit_log_entries::table)
+        .values(&NewAuditLogEntry {
+            actor_id: change.actor_id,
+            user_id: change.user_id,
+            match_id: change.match_id,
+            subject: change.subject,
+            source: change.source,
+            old_value,
+            new_value,
+        })
+        .execute(conn)?;
+
+    Ok(())
+}
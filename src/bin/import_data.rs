@@ -1,6 +1,7 @@
 extern crate csv;
 extern crate diesel;
 extern crate dotenv;
+extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate chrono;
@@ -8,17 +9,23 @@ extern crate failure;
 
 extern crate wk_predictions;
 
-use diesel::insert_into;
+use chrono::{DateTime, Utc};
 use diesel::prelude::*;
 use dotenv::dotenv;
+use serde::de::DeserializeOwned;
 
 use std::env;
-use std::error::Error;
 use std::path::Path;
+use std::process;
 
-use std::collections::HashMap;
+use wk_predictions::schema::StageType;
+use wk_predictions::tournament::{
+    CountryDefinition, GroupDefinition, ImportOptions, LocationDefinition, MatchDefinition,
+    ParticipantDefinition, StageDefinition, Tournament,
+};
+use wk_predictions::web::tournaments::current_tournament;
 
-use wk_predictions::{models::*, schema};
+const USAGE: &str = "Usage: import_data [--dry-run] [--force]";
 
 #[derive(Deserialize, Debug)]
 struct CountryRow {
@@ -27,196 +34,33 @@ struct CountryRow {
     seeding_pot: String,
 }
 
-fn import_countries(conn: &PgConnection) -> Result<Vec<Country>, Box<Error>> {
-    use schema::countries;
-
-    let mut result = Vec::new();
-
-    let mut rdr = csv::Reader::from_path(Path::new("data/countries.csv"))?;
-    for row in rdr.deserialize::<CountryRow>() {
-        let record = row?;
-        println!("{:?}", record);
-        let inserted = insert_into(countries::table)
-            .values((
-                countries::name.eq(record.name),
-                countries::flag.eq(record.flag),
-                countries::seeding_pot.eq(record.seeding_pot),
-            ))
-            .returning(countries::all_columns)
-            .get_result(conn)?;
-        println!("{:?}", inserted);
-        result.push(inserted);
-    }
-
-    Ok(result)
-}
-
 #[derive(Deserialize, Debug)]
 struct GroupRow {
-    id: u64,
     name: String,
 }
 
-fn import_groups(conn: &PgConnection) -> Result<Vec<Group>, Box<Error>> {
-    use schema::groups;
-
-    let mut result = Vec::new();
-
-    let mut rdr = csv::Reader::from_path(Path::new("data/groups.csv"))?;
-    for row in rdr.deserialize::<GroupRow>() {
-        let record = row?;
-        println!("{:?}", record);
-        let inserted = insert_into(groups::table)
-            .values((groups::name.eq(record.name),))
-            .returning((groups::group_id, groups::name))
-            .get_result(conn)?;
-        println!("{:?}", inserted);
-        result.push(inserted);
-    }
-
-    Ok(result)
-}
-
 #[derive(Deserialize, Debug)]
 struct GroupMembershipRow {
     country: String,
     group: String,
-    drawn_place: String,
-}
-
-fn import_group_memberships(conn: &PgConnection) -> Result<Vec<GroupMembership>, Box<Error>> {
-    use diesel::dsl::sql;
-    use schema::{countries, group_memberships, groups};
-
-    let mut result = Vec::new();
-
-    let mut rdr = csv::Reader::from_path(Path::new("data/group_memberships.csv"))?;
-    for row in rdr.deserialize::<GroupMembershipRow>() {
-        let record = row?;
-        println!("{:?}", record);
-        let new_groups = groups::table
-            .filter(groups::name.eq(record.group))
-            .inner_join(countries::table.on(countries::name.eq(record.country)));
-
-        let inserted = insert_into(group_memberships::table)
-            .values(new_groups.select((
-                groups::group_id,
-                countries::country_id,
-                sql(&record.drawn_place),
-                sql(&record.drawn_place),
-            )))
-            .into_columns((
-                group_memberships::group_id,
-                group_memberships::country_id,
-                group_memberships::drawn_place,
-                group_memberships::current_position,
-            ))
-            .returning((
-                group_memberships::country_id,
-                group_memberships::group_id,
-                group_memberships::drawn_place,
-                group_memberships::current_position,
-            ))
-            .get_result(conn)?;
-        println!("{:?}", inserted);
-        result.push(inserted);
-    }
-
-    Ok(result)
+    drawn_place: i32,
 }
 
 #[derive(Deserialize, Debug)]
 struct LocationRow {
     city: String,
     stadium: String,
-    capacity: i32,
-}
-
-fn import_locations(conn: &PgConnection) -> Result<Vec<Location>, Box<Error>> {
-    use schema::locations;
-
-    let mut result = Vec::new();
-
-    let mut rdr = csv::Reader::from_path(Path::new("data/locations.csv"))?;
-    for row in rdr.deserialize::<LocationRow>() {
-        let record = row?;
-        println!("{:?}", record);
-
-        let inserted = insert_into(locations::table)
-            .values((
-                locations::city.eq(record.city),
-                locations::stadium.eq(record.stadium),
-            ))
-            .returning((locations::location_id, locations::city, locations::stadium))
-            .get_result(conn)?;
-        println!("{:?}", inserted);
-        result.push(inserted);
-    }
-
-    Ok(result)
 }
 
 #[derive(Deserialize, Debug)]
 struct StageRow {
     stage_id: i32,
     parent_stage_id: Option<i32>,
-    stage_type: String,
+    stage_type: StageType,
     description: String,
-}
-
-#[derive(Debug)]
-struct StageTypeParseError;
-
-impl std::fmt::Display for StageTypeParseError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "StageTypeParseError")
-    }
-}
-
-impl Error for StageTypeParseError {
-    fn description(&self) -> &str {
-        "Failed to convert stage type"
-    }
-
-    fn cause(&self) -> Option<&Error> {
-        None
-    }
-}
-
-fn import_stages(conn: &PgConnection) -> Result<Vec<Stage>, Box<Error>> {
-    use schema::{stages, StageType};
-
-    let mut result = Vec::new();
-
-    let mut rdr = csv::Reader::from_path(Path::new("data/stages.csv"))?;
-    for row in rdr.deserialize::<StageRow>() {
-        let record = row?;
-        println!("{:?}", record);
-
-        let stage_type = match record.stage_type.as_str() {
-            "group" => StageType::Group,
-            "knockout" => StageType::Knockout,
-            _ => return Err(Box::new(StageTypeParseError)),
-        };
-        let inserted = insert_into(stages::table)
-            .values((
-                stages::stage_id.eq(record.stage_id),
-                stages::parent_stage_id.eq(record.parent_stage_id),
-                stages::stage_type.eq(stage_type),
-                stages::description.eq(record.description),
-            ))
-            .returning((
-                stages::stage_id,
-                stages::parent_stage_id,
-                stages::stage_type,
-                stages::description,
-            ))
-            .get_result(conn)?;
-        println!("{:?}", inserted);
-        result.push(inserted);
-    }
-
-    Ok(result)
+    phase: i16,
+    #[serde(default)]
+    is_final: bool,
 }
 
 #[derive(Deserialize, Debug)]
@@ -226,166 +70,154 @@ struct MatchRow {
     home_team: String,
     away_team: String,
     stadium: String,
-    city: String,
-}
-
-fn insert_participant(
-    team: String,
-    teams_by_country_name: &HashMap<String, Country>,
-    cache: &mut HashMap<String, MatchParticipant>,
-    conn: &PgConnection,
-) -> Result<MatchParticipant, failure::Error> {
-    let existing = cache.contains_key(&team);
-    if existing {
-        Ok(cache.get(&team).unwrap().clone())
-    } else {
-        let (country_group_id, drawn_place) = {
-            use schema::group_memberships::dsl::*;
-            group_memberships
-                .filter(country_id.eq(teams_by_country_name.get(&team).unwrap().country_id))
-                .select((group_id, drawn_place))
-                .first::<(i32, i16)>(conn)?
-        };
-
-        let inserted_participant: MatchParticipant = {
-            use schema::match_participants::{all_columns, dsl::*};
-
-            insert_into(match_participants)
-                .values((
-                    stage_id.eq(1),
-                    country_id.eq(teams_by_country_name.get(&team).unwrap().country_id),
-                    group_id.eq(country_group_id),
-                    group_drawn_place.eq(i32::from(drawn_place)),
-                ))
-                .returning(all_columns)
-                .get_result(conn)?
-        };
-        println!(
-            "Inserted new participant {} {:?}",
-            team, &inserted_participant
-        );
-        cache.insert(team, inserted_participant.clone());
-        Ok(inserted_participant)
-    }
 }
 
-fn import_matches(
-    locations: Vec<Location>,
-    countries: Vec<Country>,
-    conn: &PgConnection,
-) -> Result<(Vec<Match>, Vec<(MatchParticipant, MatchParticipant)>), Box<Error>> {
-    use chrono::DateTime;
-    use schema::matches;
-
-    let mut locations_by_city_and_stadium = HashMap::with_capacity(locations.len());
-    for location in locations {
-        locations_by_city_and_stadium
-            .insert((location.city.clone(), location.stadium.clone()), location);
+fn read_rows<T: DeserializeOwned>(path: &str) -> Result<Vec<T>, failure::Error> {
+    let mut rdr = csv::Reader::from_path(Path::new(path))?;
+    let mut rows = Vec::new();
+    for row in rdr.deserialize() {
+        rows.push(row?);
     }
 
-    let mut teams_by_country_name = HashMap::with_capacity(countries.len());
-    let mut participants_by_team_name = HashMap::with_capacity(countries.len());
-    for country in countries {
-        teams_by_country_name.insert(country.name.clone(), country);
-    }
+    Ok(rows)
+}
+
+fn group_participant(
+    team: &str,
+    memberships: &[GroupMembershipRow],
+) -> Result<ParticipantDefinition, failure::Error> {
+    let membership = memberships
+        .iter()
+        .find(|membership| membership.country == team)
+        .ok_or_else(|| failure::err_msg(format!("{} is not drawn into a group", team)))?;
+
+    Ok(ParticipantDefinition {
+        group: Some(membership.group.clone()),
+        drawn_place: Some(membership.drawn_place),
+        ..Default::default()
+    })
+}
+
+/// The CSV files describe the countries, the draw, the stadiums, the stages and the matches of
+/// the group stage. The knockout matches are kept as they are in the database.
+fn tournament_from_csv(existing: Tournament) -> Result<Tournament, failure::Error> {
+    let countries = read_rows::<CountryRow>("data/countries.csv")?
+        .into_iter()
+        .map(|row| CountryDefinition {
+            name: row.name,
+            flag: row.flag,
+            seeding_pot: row.seeding_pot,
+        })
+        .collect();
+
+    let mut memberships = read_rows::<GroupMembershipRow>("data/group_memberships.csv")?;
+    memberships.sort_by_key(|membership| membership.drawn_place);
+    let groups = read_rows::<GroupRow>("data/groups.csv")?
+        .into_iter()
+        .map(|row| GroupDefinition {
+            members: memberships
+                .iter()
+                .filter(|membership| membership.group == row.name)
+                .map(|membership| membership.country.clone())
+                .collect(),
+            name: row.name,
+            // The CSV files don't say who went through, so that is left as it is
+            qualified: None,
+        })
+        .collect();
+
+    let locations = read_rows::<LocationRow>("data/locations.csv")?
+        .into_iter()
+        .map(|row| LocationDefinition {
+            city: row.city,
+            stadium: row.stadium,
+        })
+        .collect();
+
+    let stages = read_rows::<StageRow>("data/stages.csv")?
+        .into_iter()
+        .map(|row| StageDefinition {
+            id: row.stage_id,
+            parent: row.parent_stage_id,
+            stage_type: row.stage_type,
+            description: row.description,
+            phase: row.phase,
+            is_final: row.is_final,
+        })
+        .collect::<Vec<_>>();
+    let group_stage = stages
+        .iter()
+        .find(|stage| stage.stage_type == StageType::Group)
+        .map(|stage| stage.id)
+        .ok_or_else(|| failure::err_msg("data/stages.csv has no group stage"))?;
 
-    let mut participants = Vec::new();
     let mut matches = Vec::new();
-
-    let mut rdr = csv::Reader::from_path(Path::new("data/matches.csv"))?;
-    for row in rdr.deserialize::<MatchRow>() {
-        let record = row?;
-        println!("{:?}", record);
-
-        let home_participant = insert_participant(
-            record.home_team,
-            &teams_by_country_name,
-            &mut participants_by_team_name,
-            conn,
-        )?;
-        let away_participant = insert_participant(
-            record.away_team,
-            &teams_by_country_name,
-            &mut participants_by_team_name,
-            conn,
-        )?;
-
-        let time = DateTime::parse_from_str(&record.time, "%d %B %Y %R %:z")?;
-
-        let inserted_match = insert_into(matches::table)
-            .values((
-                matches::match_id.eq(record.match_id),
-                matches::stage_id.eq(1),
-                matches::time.eq(time),
-                matches::location_id
-                    .eq(locations_by_city_and_stadium[&(record.city, record.stadium)].location_id),
-                matches::home_participant_id.eq(home_participant.match_participant_id),
-                matches::away_participant_id.eq(away_participant.match_participant_id),
-            ))
-            .returning(matches::all_columns)
-            .get_result(conn)?;
-
-        println!("{:?}", inserted_match);
-        matches.push(inserted_match);
-        participants.push((home_participant.clone(), away_participant.clone()))
+    for row in read_rows::<MatchRow>("data/matches.csv")? {
+        let time = DateTime::parse_from_str(&row.time, "%d %B %Y %R %:z")?;
+        matches.push(MatchDefinition {
+            id: row.match_id,
+            stage: group_stage,
+            time: time.with_timezone(&Utc),
+            stadium: row.stadium,
+            home: group_participant(&row.home_team, &memberships)?,
+            away: group_participant(&row.away_team, &memberships)?,
+        });
     }
-
-    Ok((matches, participants))
-}
-
-fn main() {
+    matches.extend(
+        existing
+            .matches
+            .into_iter()
+            .filter(|game| game.stage != group_stage),
+    );
+
+    Ok(Tournament {
+        name: existing.name,
+        archived: existing.archived,
+        countries,
+        groups,
+        locations,
+        stages,
+        matches,
+    })
+}
+
+fn run(options: ImportOptions) -> Result<(), failure::Error> {
     dotenv().ok();
 
     let database_url: String = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
     env::remove_var("DATABASE_URL"); // Likely contains username/password
 
-    let db_connection = PgConnection::establish(&database_url).unwrap();
-
-    diesel::delete(schema::match_predictions::table)
-        .execute(&db_connection)
-        .expect("Clearing the match predictions table failed");
-    diesel::delete(schema::group_predictions::table)
-        .execute(&db_connection)
-        .expect("Clearing the group predictions table failed");
-    diesel::delete(schema::favourites::table)
-        .execute(&db_connection)
-        .expect("Clearing the favourites table failed");
+    let db_connection = PgConnection::establish(&database_url)?;
 
-    diesel::delete(schema::match_outcomes::table)
-        .execute(&db_connection)
-        .expect("Clearing the match outcomes table failed");
-    diesel::delete(schema::matches::table)
-        .execute(&db_connection)
-        .expect("Clearing the matches table failed");
-    // Note that in theory we can't clear all matches and match participants in one go, as they
-    // depend on each other, in practice this script wont run after I've added match participants
-    // that point to actual matches
-    // Also I'm probably going to reset the database and add ON DELETE
-    diesel::delete(schema::match_participants::table)
-        .execute(&db_connection)
-        .expect("Clearing the match_participants table failed");
+    let current = current_tournament(&db_connection)?;
+    let tournament =
+        tournament_from_csv(Tournament::export(&db_connection, current.tournament_id)?)?;
+    let changes = tournament.import(&db_connection, options)?;
+    for change in &changes {
+        println!("{}", change);
+    }
+    if options.dry_run {
+        println!("Dry run, nothing was changed");
+    }
 
-    diesel::delete(schema::group_memberships::table)
-        .execute(&db_connection)
-        .expect("Clearing the group memberships table failed");
-    diesel::delete(schema::groups::table)
-        .execute(&db_connection)
-        .expect("Clearing the groups table failed");
+    Ok(())
+}
 
-    diesel::delete(schema::locations::table)
-        .execute(&db_connection)
-        .expect("Clearing the locations table failed");
-    diesel::delete(schema::stages::table)
-        .execute(&db_connection)
-        .expect("Clearing the stages table failed");
-    diesel::delete(schema::countries::table)
-        .execute(&db_connection)
-        .expect("Clearing the countries table failed");
+fn main() {
+    let mut options = ImportOptions::default();
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--dry-run" => options.dry_run = true,
+            "--force" => options.force = true,
+            _ => {
+                eprintln!("{}", USAGE);
+                process::exit(2);
+            }
+        }
+    }
 
-    let locations = import_locations(&db_connection).expect("Import location table failed");
-    import_stages(&db_connection).expect("Import stages table failed");
-    let countries = import_countries(&db_connection).expect("Import Countries table failed");
-    import_groups(&db_connection).expect("Import Groups table failed");
-    import_group_memberships(&db_connection).expect("Import Group Memberships table failed");
-    import_matches(locations, countries, &db_connection).expect("Import Matches table failed");
+    if let Err(error) = run(options) {
+        eprintln!("{}", error);
+        process::exit(1);
+    }
 }
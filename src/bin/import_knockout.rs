@@ -8,17 +8,22 @@ extern crate failure;
 
 extern crate wk_predictions;
 
-use diesel::insert_into;
+use chrono::{DateTime, Utc};
 use diesel::prelude::*;
 use dotenv::dotenv;
 
+use std::collections::HashSet;
 use std::env;
-use std::error::Error;
 use std::path::Path;
+use std::process;
 
-use std::collections::HashMap;
+use wk_predictions::schema::StageType;
+use wk_predictions::tournament::{
+    ImportOptions, MatchDefinition, ParticipantDefinition, Tournament,
+};
+use wk_predictions::web::tournaments::current_tournament;
 
-use wk_predictions::{models::*, schema};
+const USAGE: &str = "Usage: import_knockout [--dry-run] [--force]";
 
 #[derive(Deserialize, Debug)]
 struct MatchRow {
@@ -34,134 +39,125 @@ struct MatchRow {
     stadium: String,
 }
 
-fn insert_participant(
-    stage: i32,
+fn participant(
     team: String,
     condition: String,
     source: String,
-    teams_by_country_name: &HashMap<String, Country>,
-    groups_by_name: &HashMap<String, Group>,
-    conn: &PgConnection,
-) -> Result<MatchParticipant, failure::Error> {
-    let country = if stage == 2 {
-        Some(teams_by_country_name[&team].country_id)
-    } else {
-        None
+    country_names: &HashSet<&str>,
+) -> Result<ParticipantDefinition, failure::Error> {
+    // Countries coming out of a group or match are filled in once it is finished, but they can
+    // still be given up front
+    let country = match team.trim() {
+        "" => None,
+        name if country_names.contains(name) => Some(name.to_string()),
+        name => Err(failure::err_msg(format!(
+            "{} is not a country of the tournament",
+            name
+        )))?,
     };
     let (previous_match, group) = match source.parse::<i32>() {
         Ok(match_id) => (Some(match_id), None),
-        Err(_) => (None, Some(groups_by_name[&source].group_id)),
+        Err(_) => (None, Some(source)),
     };
 
-    let inserted_participant: MatchParticipant = {
-        use schema::match_participants::{all_columns, dsl::*};
-
-        insert_into(match_participants)
-            .values((
-                stage_id.eq(stage),
-                country_id.eq(country),
-                group_id.eq(group),
-                previous_match_id.eq(previous_match),
-                result.eq(condition),
-            ))
-            .returning(all_columns)
-            .get_result(conn)?
-    };
-    Ok(inserted_participant)
+    Ok(ParticipantDefinition {
+        country,
+        group,
+        drawn_place: None,
+        previous_match,
+        result: Some(condition),
+    })
 }
 
-fn import_matches(
-    locations: Vec<Location>,
-    countries: Vec<Country>,
-    groups: Vec<Group>,
-    conn: &PgConnection,
-) -> Result<(Vec<Match>, Vec<(MatchParticipant, MatchParticipant)>), failure::Error> {
-    use chrono::DateTime;
-    use schema::matches;
-
-    let mut locations_by_stadium = HashMap::with_capacity(locations.len());
-    for location in locations {
-        locations_by_stadium.insert(location.stadium.clone(), location);
-    }
-
-    let mut teams_by_country_name = HashMap::with_capacity(countries.len());
-    for country in countries {
-        teams_by_country_name.insert(country.name.clone(), country);
-    }
-
-    let mut groups_by_name = HashMap::with_capacity(groups.len());
-    for group in groups {
-        groups_by_name.insert(group.name.clone(), group);
-    }
+/// `data/knockout-phase.csv` describes the matches of the knockout stages, everything else is
+/// kept as it is in the database
+fn tournament_from_csv(mut tournament: Tournament) -> Result<Tournament, failure::Error> {
+    let knockout_stages = tournament
+        .stages
+        .iter()
+        .filter(|stage| stage.stage_type == StageType::Knockout)
+        .map(|stage| stage.id)
+        .collect::<HashSet<_>>();
 
-    let mut participants = Vec::new();
     let mut matches = Vec::new();
-
-    let mut rdr = csv::Reader::from_path(Path::new("data/knockout-phase.csv"))?;
-    for row in rdr.deserialize::<MatchRow>() {
-        println!("{:?}", row);
-        let record = row?;
-
-        let home_participant = insert_participant(
-            record.stage_id,
-            record.home_country,
-            record.home_condition,
-            record.home_source,
-            &teams_by_country_name,
-            &groups_by_name,
-            conn,
-        )?;
-        let away_participant = insert_participant(
-            record.stage_id,
-            record.away_country,
-            record.away_condition,
-            record.away_source,
-            &teams_by_country_name,
-            &groups_by_name,
-            conn,
-        )?;
-
-        let time = DateTime::parse_from_str(&record.time, "%d/%m/%Y %H:%M%z")?;
-        println!("Managed to parse date");
-        let location_id = locations_by_stadium[&record.stadium].location_id;
-        println!("Managed to find location");
-
-        let inserted_match = insert_into(matches::table)
-            .values((
-                matches::match_id.eq(record.match_id),
-                matches::stage_id.eq(record.stage_id),
-                matches::time.eq(time),
-                matches::location_id.eq(location_id),
-                matches::home_participant_id.eq(home_participant.match_participant_id),
-                matches::away_participant_id.eq(away_participant.match_participant_id),
-            ))
-            .returning(matches::all_columns)
-            .get_result(conn)?;
-
-        println!("{:?}", inserted_match);
-        matches.push(inserted_match);
-        participants.push((home_participant.clone(), away_participant.clone()))
+    {
+        let country_names = tournament
+            .countries
+            .iter()
+            .map(|country| country.name.as_str())
+            .collect::<HashSet<_>>();
+
+        let mut rdr = csv::Reader::from_path(Path::new("data/knockout-phase.csv"))?;
+        for row in rdr.deserialize::<MatchRow>() {
+            let record = row?;
+            let time = DateTime::parse_from_str(&record.time, "%d/%m/%Y %H:%M%z")?;
+
+            matches.push(MatchDefinition {
+                id: record.match_id,
+                stage: record.stage_id,
+                time: time.with_timezone(&Utc),
+                stadium: record.stadium,
+                home: participant(
+                    record.home_country,
+                    record.home_condition,
+                    record.home_source,
+                    &country_names,
+                )?,
+                away: participant(
+                    record.away_country,
+                    record.away_condition,
+                    record.away_source,
+                    &country_names,
+                )?,
+            });
+        }
     }
 
-    Ok((matches, participants))
-}
-
-fn do_import(db_connection: &PgConnection) -> Result<(), failure::Error> {
-    let locations = schema::locations::table.load(db_connection)?;
-    let countries = schema::countries::table.load(db_connection)?;
-    let groups = schema::groups::table.load(db_connection)?;
+    tournament
+        .matches
+        .retain(|game| !knockout_stages.contains(&game.stage));
+    tournament.matches.extend(matches);
 
-    db_connection.transaction(|| import_matches(locations, countries, groups, db_connection))?;
-    Ok(())
+    Ok(tournament)
 }
 
-fn main() {
+fn run(options: ImportOptions) -> Result<(), failure::Error> {
     dotenv().ok();
 
     let database_url: String = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
     env::remove_var("DATABASE_URL"); // Likely contains username/password
 
-    let db_connection = PgConnection::establish(&database_url).unwrap();
+    let db_connection = PgConnection::establish(&database_url)?;
 
-    do_import(&db_connection);
+    let current = current_tournament(&db_connection)?;
+    let tournament =
+        tournament_from_csv(Tournament::export(&db_connection, current.tournament_id)?)?;
+    let changes = tournament.import(&db_connection, options)?;
+    for change in &changes {
+        println!("{}", change);
+    }
+    if options.dry_run {
+        println!("Dry run, nothing was changed");
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let mut options = ImportOptions::default();
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--dry-run" => options.dry_run = true,
+            "--force" => options.force = true,
+            _ => {
+                eprintln!("{}", USAGE);
+                process::exit(2);
+            }
+        }
+    }
+
+    if let Err(error) = run(options) {
+        eprintln!("{}", error);
+        process::exit(1);
+    }
 }
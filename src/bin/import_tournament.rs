@@ -0,0 +1,107 @@
+extern crate diesel;
+extern crate dotenv;
+extern crate failure;
+
+extern crate wk_predictions;
+
+use diesel::prelude::*;
+use dotenv::dotenv;
+
+use std::env;
+use std::path::Path;
+use std::process;
+
+use wk_predictions::schema::tournaments;
+use wk_predictions::tournament::{ImportOptions, Tournament};
+use wk_predictions::web::tournaments::current_tournament;
+
+const USAGE: &str = "\
+Usage: import_tournament [--dry-run] [--force] <tournament.json|tournament.yaml>
+       import_tournament --check <tournament.json|tournament.yaml>
+       import_tournament --export [<tournament name>] <tournament.json|tournament.yaml>";
+
+fn connect() -> Result<PgConnection, failure::Error> {
+    dotenv().ok();
+
+    let database_url: String = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    env::remove_var("DATABASE_URL"); // Likely contains username/password
+
+    Ok(PgConnection::establish(&database_url)?)
+}
+
+fn run(args: &[String]) -> Result<(), failure::Error> {
+    match args {
+        [flag, path] if flag == "--check" => {
+            let problems = Tournament::from_path(Path::new(path))?.problems();
+            if problems.is_empty() {
+                println!("{} is a valid tournament", path);
+            } else {
+                for problem in &problems {
+                    println!("{}", problem);
+                }
+                process::exit(1);
+            }
+        }
+        [flag, path] if flag == "--export" => {
+            let conn = connect()?;
+            let current = current_tournament(&conn)?;
+            Tournament::export(&conn, current.tournament_id)?.to_path(Path::new(path))?;
+            println!("Exported {} to {}", current.name, path);
+        }
+        [flag, name, path] if flag == "--export" => {
+            let conn = connect()?;
+            let tournament_id = tournaments::table
+                .filter(tournaments::name.eq(name))
+                .select(tournaments::tournament_id)
+                .first::<i32>(&conn)
+                .optional()?
+                .ok_or_else(|| failure::err_msg(format!("There is no tournament {}", name)))?;
+            Tournament::export(&conn, tournament_id)?.to_path(Path::new(path))?;
+            println!("Exported {} to {}", name, path);
+        }
+        _ => match args.split_last() {
+            Some((path, flags)) if !path.starts_with("--") => {
+                let mut options = ImportOptions::default();
+                for flag in flags {
+                    match flag.as_str() {
+                        "--dry-run" => options.dry_run = true,
+                        "--force" => options.force = true,
+                        _ => usage(),
+                    }
+                }
+
+                let tournament = Tournament::from_path(Path::new(path))?;
+                let changes = tournament.import(&connect()?, options)?;
+                print_changes(&changes, options);
+            }
+            _ => usage(),
+        },
+    }
+
+    Ok(())
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+fn print_changes(changes: &[String], options: ImportOptions) {
+    for change in changes {
+        println!("{}", change);
+    }
+    match (changes.is_empty(), options.dry_run) {
+        (true, _) => println!("The database already matches the tournament"),
+        (false, true) => println!("Dry run, nothing was changed"),
+        (false, false) => println!("Imported {} changes", changes.len()),
+    }
+}
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+
+    if let Err(error) = run(&args) {
+        eprintln!("{}", error);
+        process::exit(1);
+    }
+}
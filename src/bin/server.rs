@@ -6,13 +6,22 @@ extern crate env_logger;
 extern crate futures;
 
 extern crate wk_predictions;
+use wk_predictions::live::{self, LiveScores};
+use wk_predictions::lock::LockPolicy;
+use wk_predictions::mailer::mailer_from_env;
+use wk_predictions::notifications::{self, Notifications, Reminders};
+use wk_predictions::reminders::EmailReminders;
+use wk_predictions::scores::ScoringRules;
+use wk_predictions::web::validation::RegistrationPolicy;
 use wk_predictions::web::{
-    admin, app_state, app_state::AppState, auth, dashboard, favourites, match_predictions, rules,
-    scores,
+    admin, api, app_state, app_state::AppState, auth, dashboard, favourites, group_predictions,
+    groups, leagues, match_predictions, password_reset, profiles, rules, scores, settings,
+    tournaments, unsubscribe,
 };
 
 use dotenv::dotenv;
 use std::env;
+use std::net::IpAddr;
 
 use actix::prelude::*;
 use actix_web::{
@@ -35,14 +44,66 @@ fn main() {
     let bind_url = env::var("BIND_URL").unwrap_or_else(|_| "127.0.0.1".to_owned());
     let bind_port = env::var("BIND_PORT").unwrap_or_else(|_| "8080".to_owned());
     let url = format!("{}:{}", bind_url, bind_port);
+    let base_url = env::var("BASE_URL").unwrap_or_else(|_| format!("http://{}", url));
+
+    let scoring_rules = match env::var("SCORING_RULES") {
+        Ok(path) => ScoringRules::from_file(&path).expect("SCORING_RULES couldn't be loaded"),
+        Err(_) => ScoringRules::default(),
+    };
+
+    let registration_policy = RegistrationPolicy::from_env();
+    // The reverse proxy in front of the server, the client addresses it forwards are trusted
+    let trusted_proxy = env::var("TRUSTED_PROXY").ok().map(|address| {
+        address
+            .parse::<IpAddr>()
+            .expect("TRUSTED_PROXY must be an IP address")
+    });
+    let lock_policy = LockPolicy::from_env();
 
     let sys = actix::System::new("diesel-example");
 
     // Start 3 parallel db executors
-    let addr = SyncArbiter::start(3, move || app_state::establish_connection(&database_url));
+    let db_scoring_rules = scoring_rules.clone();
+    let mailer = mailer_from_env();
+    let addr = SyncArbiter::start(3, move || {
+        app_state::establish_connection(
+            &database_url,
+            &db_scoring_rules,
+            lock_policy,
+            &mailer,
+            &base_url,
+        )
+    });
+
+    let notifier = Notifications::new(notifications::sink_from_env()).start();
+    Reminders::new(
+        addr.clone(),
+        notifier.clone(),
+        notifications::reminder_hours_from_env("SLACK_REMINDER_HOURS", 3),
+    ).start();
+    EmailReminders::new(
+        addr.clone(),
+        notifications::reminder_hours_from_env("REMINDER_EMAIL_HOURS", 24),
+    ).start();
+
+    if let Some(provider) = live::provider_from_env() {
+        LiveScores::new(
+            provider,
+            addr.clone(),
+            notifier.clone(),
+            live::interval_from_env(),
+        ).start();
+    }
 
     server::new(move || {
-        App::with_state(AppState { db: addr.clone() })
+        App::with_state(AppState {
+            db: addr.clone(),
+            scoring_rules: scoring_rules.clone(),
+            registration_policy: registration_policy.clone(),
+            redirect_key: cookie_secret.clone().into_bytes(),
+            notifications: notifier.clone(),
+            trusted_proxy,
+        })
             .middleware(Logger::default())
             .middleware(IdentityService::new(
                 CookieIdentityPolicy::new(&cookie_secret.clone().into_bytes())
@@ -55,7 +116,7 @@ fn main() {
             )
             .resource("/login", |r| {
                 r.name("login");
-                r.get().f(|_req| auth::login());
+                r.get().with(auth::login);
                 r.post().with_config(auth::perform_login, |cfg| {
                     cfg.0.limit(4096);
                 });
@@ -64,11 +125,23 @@ fn main() {
                 r.get().with(auth::perform_logout);
             })
             .resource("/register", |r| {
-                r.get().f(|_req| auth::register());
+                r.get().with(auth::register);
                 r.post().with_config(auth::perform_registration, |cfg| {
                     cfg.0.limit(4096);
                 })
             })
+            .resource("/password_reset", |r| {
+                r.get().f(|_req| password_reset::new());
+                r.post().with(password_reset::create);
+            })
+            .resource("/password_reset/{token}", |r| {
+                r.get().with(password_reset::edit);
+                r.post().with(password_reset::update);
+            })
+            .resource("/reminders/unsubscribe/{token}", |r| {
+                r.get().with(unsubscribe::show);
+                r.post().with(unsubscribe::unsubscribe);
+            })
             .resource("/", |r| r.get().with(dashboard::index))
             .resource("/index.html", |r| r.get().with(dashboard::index))
             .resource("/match/{id}/prediction", |r| {
@@ -80,6 +153,8 @@ fn main() {
             })
             .resource("/favourites", |r| {
                 r.get().with(favourites::edit);
+            })
+            .resource("/favourites/{phase}", |r| {
                 r.post().with(favourites::update);
             })
             .resource("/predictions", |r| {
@@ -92,11 +167,82 @@ fn main() {
             .resource("/scores", |r| {
                 r.get().with(scores::index);
             })
+            .resource("/groups", |r| {
+                r.get().with(groups::index);
+            })
+            .resource("/groups/predictions", |r| {
+                r.get().with(group_predictions::edit);
+                r.post().with(group_predictions::update);
+            })
+            .resource("/leagues", |r| {
+                r.get().with(leagues::index);
+                r.post().with(leagues::create);
+            })
+            .resource("/leagues/join", |r| {
+                r.post().with(leagues::join);
+            })
+            .resource("/leagues/{id}/leave", |r| {
+                r.post().with(leagues::leave);
+            })
+            .resource("/tournaments", |r| {
+                r.get().with(tournaments::index);
+            })
+            .resource("/tournaments/{id}", |r| {
+                r.post().with(tournaments::select);
+            })
             .resource("/predictions/lucky", |r| {
                 r.post().with(match_predictions::very_lucky);
             })
+            .resource("/users/{id}", |r| {
+                r.get().with(profiles::show);
+            })
             .resource("/rules", |r| {
-                r.get().f(|_req| rules::show());
+                r.get().with(rules::show);
+            })
+            .resource("/settings", |r| {
+                r.get().with(settings::index);
+            })
+            .resource("/settings/account", |r| {
+                r.post().with(settings::update_account);
+            })
+            .resource("/settings/reminders", |r| {
+                r.post().with(settings::update_reminders);
+            })
+            .resource("/settings/password", |r| {
+                r.post().with(settings::change_password);
+            })
+            .resource("/settings/tokens", |r| {
+                r.post().with(settings::create_token);
+            })
+            .resource("/settings/tokens/{id}/revoke", |r| {
+                r.post().with(settings::revoke_token);
+            })
+            .resource("/api/v1/matches", |r| {
+                r.get().with(api::matches);
+            })
+            .resource("/api/v1/matches/{id}", |r| {
+                r.get().with(api::show_match);
+            })
+            .resource("/api/v1/matches/{id}/prediction", |r| {
+                r.get().with(api::show_prediction);
+                r.put().with(api::update_prediction);
+            })
+            .resource("/api/v1/predictions", |r| {
+                r.get().with(api::predictions);
+                r.put().with(api::update_predictions);
+            })
+            .resource("/api/v1/favourites", |r| {
+                r.get().with(api::favourites);
+                r.put().with(api::update_favourites);
+            })
+            .resource("/api/v1/outcomes", |r| {
+                r.get().with(api::outcomes);
+            })
+            .resource("/api/v1/scores", |r| {
+                r.get().with(api::scores);
+            })
+            .resource("/api/v1/scores/history", |r| {
+                r.get().with(api::score_history);
             })
             .resource("/admin/matches", |r| {
                 r.get().with(admin::match_outcomes::index);
@@ -108,6 +254,15 @@ fn main() {
             .resource("/admin/scores", |r| {
                 r.post().with(admin::scores::recalculate);
             })
+            .resource("/admin/audit_log", |r| {
+                r.get().with(admin::audit_log::index);
+            })
+            .resource("/admin/users", |r| {
+                r.get().with(admin::users::index);
+            })
+            .resource("/admin/users/{id}", |r| {
+                r.post().with(admin::users::update);
+            })
     }).bind(&url)
         .unwrap()
         .start();
@@ -5,6 +5,8 @@ extern crate diesel_derive_enum;
 #[macro_use]
 extern crate serde_derive;
 #[macro_use]
+extern crate serde_json;
+#[macro_use]
 extern crate lazy_static;
 #[macro_use]
 extern crate tera;
@@ -15,10 +17,26 @@ extern crate bcrypt;
 extern crate chrono;
 extern crate failure;
 extern crate futures;
+extern crate hmac;
+extern crate lettre;
+extern crate lettre_email;
 extern crate rand;
+extern crate serde_yaml;
+extern crate sha2;
+extern crate toml;
+extern crate url;
 
+pub mod audit;
+pub mod live;
+pub mod lock;
+pub mod mailer;
 pub mod models;
+pub mod notifications;
+pub mod ranking;
+pub mod reminders;
 pub mod schema;
 pub mod scores;
+pub mod standings;
 pub mod templates;
+pub mod tournament;
 pub mod web;
@@ -0,0 +1,392 @@
+//! Scores from an external results feed.
+//!
+//! The `LiveScores` actor polls a `ScoreProvider` every few minutes. While a match is played its
+//! score is kept in `live_scores`, and once the provider reports it as finished the outcome is
+//! stored the same way as when an admin enters it. Outcomes that are already known are left
+//! alone, so a correction by an admin is never overwritten by the feed.
+//!
+//! `LIVE_SCORES_URL` polls a JSON feed over HTTP, `LIVE_SCORES_FILE` reads the same JSON from a
+//! file (handy for testing), and `LIVE_SCORES_INTERVAL_SECONDS` sets how often (60 by default).
+
+use models::{MatchOutcome, Stage};
+use notifications::{Notification, Notifications};
+use web::admin::match_outcomes::save_match_outcome;
+use web::app_state::DbExecutor;
+use web::tournaments::current_tournament;
+
+use actix::prelude::*;
+use actix_web::{client, HttpMessage};
+use diesel::{self, prelude::*};
+use failure;
+use futures::{future, Future};
+use serde_json;
+use std::fs::File;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::{env, error::Error as StdError, fmt};
+
+/// Feeds are limited to a few hundred kB, a whole tournament is far smaller than that
+const MAX_FEED_SIZE: usize = 512 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchStatus {
+    Scheduled,
+    InPlay,
+    Finished,
+}
+
+/// A match as reported by the provider, identified by its number in the schedule of the current
+/// tournament
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReportedScore {
+    pub match_number: i32,
+    pub status: MatchStatus,
+    pub home_score: i16,
+    pub away_score: i16,
+    /// The minute of play, while the match is in play
+    #[serde(default)]
+    pub minute: Option<i16>,
+    /// Needed once the match is finished
+    #[serde(default)]
+    pub time_of_first_goal: Option<i16>,
+    #[serde(default)]
+    pub home_penalties: Option<i32>,
+    #[serde(default)]
+    pub away_penalties: Option<i32>,
+    /// 90 or 120, needed once a knock-out match is finished
+    #[serde(default)]
+    pub duration: Option<i32>,
+}
+
+#[derive(Debug)]
+pub struct IncompleteScore {
+    pub match_number: i32,
+    pub problem: &'static str,
+}
+
+impl fmt::Display for IncompleteScore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Match {}: {}", self.match_number, self.problem)
+    }
+}
+
+impl StdError for IncompleteScore {
+    fn description(&self) -> &str {
+        "The provider reported an incomplete outcome"
+    }
+}
+
+impl ReportedScore {
+    fn incomplete(&self, problem: &'static str) -> IncompleteScore {
+        IncompleteScore {
+            match_number: self.match_number,
+            problem,
+        }
+    }
+
+    /// The outcome of the finished match, with the same rules as the admin form: group matches
+    /// have neither penalties nor a duration, knock-out matches need a duration
+    pub fn to_match_outcome(
+        &self,
+        match_id: i32,
+        stage: &Stage,
+    ) -> Result<MatchOutcome, IncompleteScore> {
+        let time_of_first_goal = self
+            .time_of_first_goal
+            .ok_or_else(|| self.incomplete("the time of the first goal is missing"))?;
+
+        let (home_penalties, away_penalties, duration) = if stage.is_knockout() {
+            let duration = match self.duration {
+                Some(duration @ 90) | Some(duration @ 120) => duration,
+                Some(_) => Err(self.incomplete("the duration must be 90 or 120"))?,
+                None => Err(self.incomplete("the duration is missing"))?,
+            };
+            match (self.home_penalties, self.away_penalties) {
+                (Some(home), Some(away)) if home == away => {
+                    Err(self.incomplete("the penalties can't end in a tie"))?
+                }
+                (Some(home), Some(away)) => (Some(home), Some(away), Some(duration)),
+                (None, None) if self.home_score == self.away_score => {
+                    Err(self.incomplete("a tie needs penalties"))?
+                }
+                (None, None) => (None, None, Some(duration)),
+                _ => Err(self.incomplete("the penalties of one of the countries are missing"))?,
+            }
+        } else {
+            (None, None, None)
+        };
+
+        Ok(MatchOutcome {
+            match_id,
+
+            home_score: self.home_score,
+            away_score: self.away_score,
+            time_of_first_goal,
+
+            home_penalties,
+            away_penalties,
+            duration,
+        })
+    }
+}
+
+/// Where the live scores come from
+pub trait ScoreProvider {
+    /// The current state of the matches the provider knows about
+    fn fetch(&self) -> Box<Future<Item = Vec<ReportedScore>, Error = failure::Error>>;
+}
+
+/// Fetches a JSON array of `ReportedScore`s over HTTP
+pub struct HttpProvider {
+    url: String,
+}
+
+impl HttpProvider {
+    pub fn new(url: &str) -> HttpProvider {
+        HttpProvider {
+            url: url.to_string(),
+        }
+    }
+}
+
+impl ScoreProvider for HttpProvider {
+    fn fetch(&self) -> Box<Future<Item = Vec<ReportedScore>, Error = failure::Error>> {
+        let request = match client::get(&self.url)
+            .header("Accept", "application/json")
+            .finish()
+        {
+            Ok(request) => request,
+            Err(error) => return Box::new(future::err(failure::err_msg(error.to_string()))),
+        };
+
+        Box::new(
+            request
+                .send()
+                .map_err(failure::Error::from)
+                .and_then(|response| {
+                    response
+                        .json::<Vec<ReportedScore>>()
+                        .limit(MAX_FEED_SIZE)
+                        .map_err(failure::Error::from)
+                }),
+        )
+    }
+}
+
+/// Reads the same JSON as `HttpProvider` from a file, which is read again on every poll
+pub struct FileProvider {
+    path: PathBuf,
+}
+
+impl FileProvider {
+    pub fn new<P: Into<PathBuf>>(path: P) -> FileProvider {
+        FileProvider { path: path.into() }
+    }
+
+    fn read(&self) -> Result<Vec<ReportedScore>, failure::Error> {
+        Ok(serde_json::from_reader(File::open(&self.path)?)?)
+    }
+}
+
+impl ScoreProvider for FileProvider {
+    fn fetch(&self) -> Box<Future<Item = Vec<ReportedScore>, Error = failure::Error>> {
+        Box::new(future::result(self.read()))
+    }
+}
+
+/// The provider configured by `LIVE_SCORES_URL` or `LIVE_SCORES_FILE`, `None` when live scores
+/// are not used
+pub fn provider_from_env() -> Option<Box<ScoreProvider>> {
+    if let Ok(url) = env::var("LIVE_SCORES_URL") {
+        Some(Box::new(HttpProvider::new(&url)))
+    } else if let Ok(path) = env::var("LIVE_SCORES_FILE") {
+        Some(Box::new(FileProvider::new(path)))
+    } else {
+        None
+    }
+}
+
+pub fn interval_from_env() -> Duration {
+    Duration::from_secs(
+        env::var("LIVE_SCORES_INTERVAL_SECONDS")
+            .map(|seconds| {
+                seconds
+                    .parse()
+                    .expect("LIVE_SCORES_INTERVAL_SECONDS must be a number of seconds")
+            })
+            .unwrap_or(60),
+    )
+}
+
+/// Store what the provider reported, returns what happened worth logging and the results to
+/// post to Slack
+pub struct StoreLiveScores {
+    pub scores: Vec<ReportedScore>,
+}
+
+impl Message for StoreLiveScores {
+    type Result = Result<(Vec<String>, Vec<Notification>), failure::Error>;
+}
+
+fn outcome_known(conn: &PgConnection, match_id: i32) -> QueryResult<bool> {
+    use schema::match_outcomes;
+
+    Ok(match_outcomes::table
+        .find(match_id)
+        .select(match_outcomes::match_id)
+        .first::<i32>(conn)
+        .optional()?
+        .is_some())
+}
+
+impl Handler<StoreLiveScores> for DbExecutor {
+    type Result = Result<(Vec<String>, Vec<Notification>), failure::Error>;
+
+    fn handle(&mut self, msg: StoreLiveScores, _: &mut Self::Context) -> Self::Result {
+        use diesel::pg::upsert::excluded;
+        use schema::{live_scores, matches, stages};
+
+        let tournament = current_tournament(&self.connection)?;
+        let mut messages = Vec::new();
+        let mut results = Vec::new();
+
+        for score in msg.scores {
+            let found = matches::table
+                .inner_join(stages::table)
+                .filter(matches::tournament_id.eq(tournament.tournament_id))
+                .filter(matches::match_number.eq(score.match_number))
+                .select((matches::match_id, stages::all_columns))
+                .first::<(i32, Stage)>(&self.connection)
+                .optional()?;
+            let (match_id, stage) = match found {
+                Some(found) => found,
+                None => {
+                    messages.push(format!("Match {} is not in the schedule", score.match_number));
+                    continue;
+                }
+            };
+
+            if outcome_known(&self.connection, match_id)? {
+                continue;
+            }
+
+            match score.status {
+                MatchStatus::Scheduled => {}
+                MatchStatus::InPlay => {
+                    diesel::insert_into(live_scores::table)
+                        .values((
+                            live_scores::match_id.eq(match_id),
+                            live_scores::home_score.eq(score.home_score),
+                            live_scores::away_score.eq(score.away_score),
+                            live_scores::minute.eq(score.minute),
+                        ))
+                        .on_conflict(live_scores::match_id)
+                        .do_update()
+                        .set((
+                            live_scores::home_score.eq(excluded(live_scores::home_score)),
+                            live_scores::away_score.eq(excluded(live_scores::away_score)),
+                            live_scores::minute.eq(excluded(live_scores::minute)),
+                        ))
+                        .execute(&self.connection)?;
+                }
+                MatchStatus::Finished => {
+                    let outcome = match score.to_match_outcome(match_id, &stage) {
+                        Ok(outcome) => outcome,
+                        Err(incomplete) => {
+                            messages.push(incomplete.to_string());
+                            continue;
+                        }
+                    };
+
+                    // An admin may have entered the outcome since the check above, so check again
+                    // with the match locked the way `save_match_outcome` locks it
+                    let conn = &self.connection;
+                    let report = conn.transaction::<_, failure::Error, _>(|| {
+                        matches::table
+                            .find(match_id)
+                            .select(matches::match_id)
+                            .for_update()
+                            .first::<i32>(conn)?;
+                        if outcome_known(conn, match_id)? {
+                            return Ok(None);
+                        }
+
+                        let report =
+                            save_match_outcome(conn, &self.scoring_rules, &outcome, None, "live")?;
+                        Ok(Some(report))
+                    })?;
+                    let report = match report {
+                        Some(report) => report,
+                        None => continue,
+                    };
+                    results.push(report.notification());
+                    diesel::delete(live_scores::table.find(match_id)).execute(&self.connection)?;
+                    messages.push(format!(
+                        "Match {} finished {} - {}",
+                        score.match_number, outcome.home_score, outcome.away_score
+                    ));
+                }
+            }
+        }
+
+        Ok((messages, results))
+    }
+}
+
+/// Polls the provider and hands the scores to the DB executors
+pub struct LiveScores {
+    provider: Box<ScoreProvider>,
+    db: Addr<DbExecutor>,
+    notifications: Addr<Notifications>,
+    interval: Duration,
+}
+
+impl LiveScores {
+    pub fn new(
+        provider: Box<ScoreProvider>,
+        db: Addr<DbExecutor>,
+        notifications: Addr<Notifications>,
+        interval: Duration,
+    ) -> LiveScores {
+        LiveScores {
+            provider,
+            db,
+            notifications,
+            interval,
+        }
+    }
+
+    fn poll(&self) {
+        let db = self.db.clone();
+        let notifications = self.notifications.clone();
+
+        Arbiter::spawn(
+            self.provider
+                .fetch()
+                .and_then(move |scores| {
+                    db.send(StoreLiveScores { scores })
+                        .map_err(failure::Error::from)
+                        .and_then(|result| result)
+                })
+                .map(move |(messages, results)| {
+                    for message in messages {
+                        println!("Live scores: {}", message);
+                    }
+                    for result in results {
+                        notifications.do_send(result);
+                    }
+                })
+                .map_err(|error| println!("Live scores: {}", error)),
+        );
+    }
+}
+
+impl Actor for LiveScores {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.poll();
+        ctx.run_interval(self.interval, |live_scores, _| live_scores.poll());
+    }
+}
@@ -0,0 +1,139 @@
+//! When predictions for a match can no longer be changed.
+//!
+//! A match locks at kickoff, or `PREDICTION_LOCK_MINUTES` minutes before it when that is set.
+//! Every path that writes predictions (the forms, "I feel lucky" and the API) checks the matches
+//! it writes through `LockPolicy`, so the rule is enforced on the server in one place.
+
+use chrono::{DateTime, Duration, Utc};
+use diesel::prelude::*;
+use failure;
+use std::{env, error::Error as StdError, fmt};
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LockPolicy {
+    pub minutes_before_kickoff: i64,
+}
+
+impl LockPolicy {
+    pub fn from_env() -> LockPolicy {
+        LockPolicy {
+            minutes_before_kickoff: env::var("PREDICTION_LOCK_MINUTES")
+                .map(|minutes| {
+                    minutes
+                        .parse()
+                        .expect("PREDICTION_LOCK_MINUTES must be a number of minutes")
+                })
+                .unwrap_or(0),
+        }
+    }
+
+    fn offset(&self) -> Duration {
+        Duration::minutes(self.minutes_before_kickoff)
+    }
+
+    pub fn locks_at(&self, kickoff: DateTime<Utc>) -> DateTime<Utc> {
+        kickoff - self.offset()
+    }
+
+    pub fn is_locked(&self, kickoff: DateTime<Utc>) -> bool {
+        self.locks_at(kickoff) <= Utc::now()
+    }
+
+    /// Matches that kick off after this moment can still be predicted, for use in queries
+    pub fn open_kickoffs_after(&self) -> DateTime<Utc> {
+        Utc::now() + self.offset()
+    }
+
+    /// A rejection for every match in `match_ids` that can't be predicted (anymore)
+    pub fn rejections(
+        &self,
+        conn: &PgConnection,
+        match_ids: &[i32],
+    ) -> QueryResult<Vec<Rejection>> {
+        use schema::matches::dsl::*;
+
+        let kickoffs = matches
+            .filter(match_id.eq_any(match_ids))
+            .select((match_id, time))
+            .load::<(i32, DateTime<Utc>)>(conn)?;
+
+        Ok(match_ids
+            .iter()
+            .filter_map(|&id| match kickoffs.iter().find(|(other_id, _)| *other_id == id) {
+                Some(&(_, kickoff)) if self.is_locked(kickoff) => Some(Rejection {
+                    match_id: id,
+                    reason: RejectionReason::Locked,
+                    locked_at: Some(self.locks_at(kickoff)),
+                }),
+                Some(_) => None,
+                None => Some(Rejection {
+                    match_id: id,
+                    reason: RejectionReason::UnknownMatch,
+                    locked_at: None,
+                }),
+            })
+            .collect())
+    }
+
+    /// Fails with `PredictionsLocked` when any of the matches can't be predicted, call this in
+    /// the same transaction as the write
+    pub fn ensure_open(
+        &self,
+        conn: &PgConnection,
+        match_ids: &[i32],
+    ) -> Result<(), failure::Error> {
+        let rejections = self.rejections(conn, match_ids)?;
+
+        if rejections.is_empty() {
+            Ok(())
+        } else {
+            Err(PredictionsLocked { rejections })?
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectionReason {
+    Locked,
+    UnknownMatch,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Rejection {
+    pub match_id: i32,
+    pub reason: RejectionReason,
+    pub locked_at: Option<DateTime<Utc>>,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.reason {
+            RejectionReason::Locked => write!(f, "match {} is locked", self.match_id),
+            RejectionReason::UnknownMatch => write!(f, "match {} doesn't exist", self.match_id),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PredictionsLocked {
+    pub rejections: Vec<Rejection>,
+}
+
+impl fmt::Display for PredictionsLocked {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reasons = self
+            .rejections
+            .iter()
+            .map(|rejection| rejection.to_string())
+            .collect::<Vec<_>>();
+
+        write!(f, "Too late to predict: {}", reasons.join(", "))
+    }
+}
+
+impl StdError for PredictionsLocked {
+    fn description(&self) -> &str {
+        "Too late to predict"
+    }
+}
@@ -0,0 +1,131 @@
+use chrono::Utc;
+use failure;
+use lettre::smtp::authentication::Credentials;
+use lettre::{EmailTransport, SmtpTransport};
+use lettre_email::EmailBuilder;
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Delivers emails, implementations should be cheap to share between the DB executors
+pub trait Mailer: Send + Sync {
+    fn send(&self, email: &Email) -> Result<(), failure::Error>;
+}
+
+/// Prints the emails to stdout instead of delivering them, for development
+pub struct LogMailer;
+
+impl Mailer for LogMailer {
+    fn send(&self, email: &Email) -> Result<(), failure::Error> {
+        println!(
+            "Email to {}\nSubject: {}\n\n{}",
+            email.to, email.subject, email.body
+        );
+
+        Ok(())
+    }
+}
+
+/// Writes every email to a separate file in the given directory
+pub struct FileMailer {
+    directory: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new<P: Into<PathBuf>>(directory: P) -> FileMailer {
+        FileMailer {
+            directory: directory.into(),
+        }
+    }
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, email: &Email) -> Result<(), failure::Error> {
+        fs::create_dir_all(&self.directory)?;
+
+        let file_name = format!(
+            "{}-{}.eml",
+            Utc::now().format("%Y%m%d%H%M%S%.f"),
+            email.to.replace(|c: char| !c.is_alphanumeric(), "_")
+        );
+        let mut file = fs::File::create(self.directory.join(file_name))?;
+        write!(
+            file,
+            "To: {}\nSubject: {}\n\n{}\n",
+            email.to, email.subject, email.body
+        )?;
+
+        Ok(())
+    }
+}
+
+/// Delivers the emails to an SMTP server, over STARTTLS on the submission port
+pub struct SmtpMailer {
+    host: String,
+    credentials: Option<Credentials>,
+    from: String,
+}
+
+impl SmtpMailer {
+    pub fn new(host: &str, credentials: Option<Credentials>, from: &str) -> SmtpMailer {
+        SmtpMailer {
+            host: host.to_string(),
+            credentials,
+            from: from.to_string(),
+        }
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, email: &Email) -> Result<(), failure::Error> {
+        let message = EmailBuilder::new()
+            .to(email.to.as_str())
+            .from(self.from.as_str())
+            .subject(email.subject.as_str())
+            .text(email.body.as_str())
+            .build()
+            .map_err(|error| failure::err_msg(error.to_string()))?;
+
+        // A connection per email, there are only a handful of them at a time
+        let mut builder = SmtpTransport::simple_builder(&self.host)
+            .map_err(|error| failure::err_msg(error.to_string()))?;
+        if let Some(ref credentials) = self.credentials {
+            builder = builder.credentials(credentials.clone());
+        }
+        builder
+            .build()
+            .send(&message)
+            .map_err(|error| failure::err_msg(error.to_string()))?;
+
+        Ok(())
+    }
+}
+
+/// Deliver the emails to `SMTP_HOST` when it is set (with `SMTP_USERNAME`, `SMTP_PASSWORD` and
+/// `MAIL_FROM`), dump them in `MAIL_DIRECTORY` when that is set, log them otherwise
+pub fn mailer_from_env() -> Arc<Mailer> {
+    if let Ok(host) = env::var("SMTP_HOST") {
+        let credentials = match (env::var("SMTP_USERNAME"), env::var("SMTP_PASSWORD")) {
+            (Ok(username), Ok(password)) => Some(Credentials::new(username, password)),
+            _ => None,
+        };
+        env::remove_var("SMTP_PASSWORD");
+        let from = env::var("MAIL_FROM").expect("MAIL_FROM must be set when SMTP_HOST is");
+
+        return Arc::new(SmtpMailer::new(&host, credentials, &from));
+    }
+
+    match env::var("MAIL_DIRECTORY") {
+        Ok(directory) => Arc::new(FileMailer::new(directory)),
+        Err(_) => Arc::new(LogMailer),
+    }
+}
@@ -2,6 +2,7 @@ use super::schema::*;
 use bcrypt::{hash, DEFAULT_COST};
 use chrono::prelude::*;
 use diesel::prelude::*;
+use serde_json::Value;
 
 #[derive(Queryable, Identifiable, Clone, Debug, Serialize, Deserialize)]
 #[primary_key(user_id)]
@@ -11,10 +12,32 @@ pub struct User {
     pub login: String,
     pub email: String,
     pub score: i32,
+    #[serde(skip_serializing)]
     pub encrypted_password: String,
     pub slack_handle: Option<String>,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
+    pub is_admin: bool,
+    /// The tournament the user is looking at, `None` for the current one
+    pub tournament_id: Option<i32>,
+    /// Whether the user gets an email about matches they forgot to predict
+    pub wants_reminder_emails: bool,
+}
+
+/// What everyone may see of a user, e.g. next to their predictions
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PublicUser {
+    pub user_id: i32,
+    pub display_name: Option<String>,
+}
+
+impl From<User> for PublicUser {
+    fn from(user: User) -> PublicUser {
+        PublicUser {
+            user_id: user.user_id,
+            display_name: user.display_name,
+        }
+    }
 }
 
 pub struct NewUser<'a> {
@@ -57,7 +80,7 @@ impl<'a> Insertable<users::table> for NewUser<'a> {
     }
 }
 
-#[derive(Queryable, Identifiable, Debug, Serialize, Deserialize)]
+#[derive(Queryable, Identifiable, Debug, Serialize, Deserialize, Clone)]
 #[primary_key(country_id)]
 #[table_name = "countries"]
 pub struct Country {
@@ -65,15 +88,15 @@ pub struct Country {
     pub name: String,
     pub flag: String,
     pub seeding_pot: String,
-    pub qualified_for_knockout: bool,
 }
 
-#[derive(Queryable, Identifiable, Debug, Serialize, Deserialize)]
+#[derive(Queryable, Identifiable, Debug, Serialize, Deserialize, Clone)]
 #[primary_key(group_id)]
 #[table_name = "groups"]
 pub struct Group {
     pub group_id: i32,
     pub name: String,
+    pub tournament_id: i32,
 }
 
 #[derive(Queryable, Identifiable, Debug)]
@@ -84,6 +107,31 @@ pub struct GroupMembership {
     pub group_id: i32,
     pub drawn_place: i16,
     pub current_position: i16,
+    pub qualified_for_knockout: bool,
+}
+
+#[derive(Queryable, Identifiable, Associations, Debug, Serialize, Deserialize, Clone)]
+#[belongs_to(User)]
+#[primary_key(group_id, user_id)]
+pub struct GroupPrediction {
+    pub group_id: i32,
+    pub user_id: i32,
+
+    pub winner_id: i32,
+    pub runnerup_id: i32,
+
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Debug, Insertable, AsChangeset)]
+#[table_name = "group_predictions"]
+pub struct UpdatedGroupPrediction {
+    pub group_id: i32,
+    pub user_id: i32,
+
+    pub winner_id: i32,
+    pub runnerup_id: i32,
 }
 
 #[derive(Queryable, Identifiable, Debug, Serialize, Deserialize)]
@@ -95,13 +143,27 @@ pub struct Location {
     // pub capacity: i32, // not yet
 }
 
-#[derive(Queryable, Identifiable, Debug)]
+#[derive(Queryable, Identifiable, Debug, Clone)]
 #[primary_key(stage_id)]
 pub struct Stage {
     pub stage_id: i32,
     pub parent_stage_id: Option<i32>,
     pub stage_type: StageType,
     pub description: String,
+    /// The favourites phase during which the matches of this stage are played
+    pub phase: i16,
+    /// Whether the winner of this stage is the champion of the tournament
+    pub is_final: bool,
+    pub tournament_id: i32,
+    /// The number of the stage in the definition of its tournament
+    pub stage_number: i32,
+}
+
+impl Stage {
+    /// Knock-out matches can't end in a tie, so they have a duration and maybe penalties
+    pub fn is_knockout(&self) -> bool {
+        self.stage_type == StageType::Knockout
+    }
 }
 
 #[derive(Queryable, Identifiable, Debug, Serialize, Deserialize, Clone)]
@@ -129,11 +191,14 @@ pub struct Match {
     pub home_participant_id: i32,
     pub away_participant_id: i32,
     pub time: DateTime<Utc>,
+    pub tournament_id: i32,
+    /// The number of the match in the schedule of its tournament
+    pub match_number: i32,
 }
 
 pub struct MatchWithParticipants {
     pub match_id: i32,
-    pub stage_id: i32,
+    pub stage: Stage,
     pub home_participant: MatchParticipant,
     pub away_participant: MatchParticipant,
     pub time: DateTime<Utc>,
@@ -214,6 +279,18 @@ impl MatchOutcome {
     }
 }
 
+/// The score of a match that is still being played, see `live`
+#[derive(Queryable, Identifiable, Debug, Serialize, Deserialize, Clone)]
+#[primary_key(match_id)]
+#[table_name = "live_scores"]
+pub struct LiveScore {
+    pub match_id: i32,
+    pub home_score: i16,
+    pub away_score: i16,
+    pub minute: Option<i16>,
+    pub updated_at: NaiveDateTime,
+}
+
 // I should consider adding a view according to this data
 #[derive(Debug, Clone, Serialize, Deserialize, Queryable, QueryableByName)]
 #[table_name = "full_match_infos"]
@@ -237,11 +314,16 @@ pub struct MatchWithAllInfo {
 
     pub away_country_name: Option<String>,
     pub away_country_flag: Option<String>,
+
+    pub is_knockout: bool,
+
+    pub tournament_id: i32,
+    pub match_number: i32,
 }
 
 #[derive(Debug, Clone, Associations, Serialize, Deserialize, Queryable, Identifiable)]
 #[belongs_to(User)]
-#[primary_key(user_id, choice)]
+#[primary_key(user_id, tournament_id, choice)]
 pub struct Favourite {
     pub user_id: i32,
     pub country_id: Option<i32>,
@@ -250,6 +332,7 @@ pub struct Favourite {
     pub updated_at: NaiveDateTime,
     pub phase: i16,
     pub source: String,
+    pub tournament_id: i32,
 }
 
 #[derive(Insertable, AsChangeset)]
@@ -259,6 +342,8 @@ pub struct UpdatedFavourite {
     pub country_id: Option<i32>,
     pub choice: i16,
     pub phase: i16,
+    pub source: String,
+    pub tournament_id: i32,
 }
 
 #[derive(Serialize, Deserialize, Debug, Queryable, Insertable, AsChangeset)]
@@ -271,3 +356,123 @@ pub struct UserMatchPoints {
     pub time_of_first_goal: i32,
     pub total: i32,
 }
+
+#[derive(Queryable, Identifiable, Debug, Serialize, Deserialize, Clone)]
+#[primary_key(tournament_id)]
+pub struct Tournament {
+    pub tournament_id: i32,
+    pub name: String,
+    pub is_archived: bool,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Identifiable, Debug, Serialize, Deserialize, Clone)]
+#[primary_key(league_id)]
+pub struct League {
+    pub league_id: i32,
+    pub name: String,
+    pub invite_code: String,
+    pub created_by: i32,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "leagues"]
+pub struct NewLeague<'a> {
+    pub name: &'a str,
+    pub invite_code: &'a str,
+    pub created_by: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Queryable, Insertable, AsChangeset)]
+#[table_name = "user_group_points"]
+pub struct UserGroupPoints {
+    pub user_id: i32,
+    pub group_id: i32,
+    pub winner: i32,
+    pub runnerup: i32,
+    pub total: i32,
+}
+
+#[derive(Queryable, Identifiable, Associations, Debug, Serialize, Clone)]
+#[belongs_to(User)]
+#[primary_key(api_token_id)]
+pub struct ApiToken {
+    pub api_token_id: i32,
+    pub user_id: i32,
+    pub name: String,
+    #[serde(skip_serializing)]
+    pub token_hash: String,
+    pub created_at: NaiveDateTime,
+    pub last_used_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "api_tokens"]
+pub struct NewApiToken<'a> {
+    pub user_id: i32,
+    pub name: &'a str,
+    pub token_hash: &'a str,
+}
+
+#[derive(Queryable, Identifiable, Associations, Debug)]
+#[belongs_to(User)]
+#[primary_key(password_reset_token_id)]
+pub struct PasswordResetToken {
+    pub password_reset_token_id: i32,
+    pub user_id: i32,
+    pub token_hash: String,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub used_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "password_reset_tokens"]
+pub struct NewPasswordResetToken<'a> {
+    pub user_id: i32,
+    pub token_hash: &'a str,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "unsubscribe_tokens"]
+pub struct NewUnsubscribeToken<'a> {
+    pub user_id: i32,
+    pub token_hash: &'a str,
+}
+
+#[derive(Insertable)]
+#[table_name = "login_attempts"]
+pub struct NewLoginAttempt<'a> {
+    pub login: &'a str,
+    pub ip_address: &'a str,
+    pub successful: bool,
+}
+
+#[derive(Queryable, Identifiable, Debug, Serialize, Clone)]
+#[table_name = "audit_log_entries"]
+#[primary_key(audit_log_entry_id)]
+pub struct AuditLogEntry {
+    pub audit_log_entry_id: i32,
+    pub actor_id: Option<i32>,
+    pub user_id: Option<i32>,
+    pub match_id: Option<i32>,
+    pub subject: String,
+    pub source: String,
+    pub old_value: Option<Value>,
+    pub new_value: Option<Value>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "audit_log_entries"]
+pub struct NewAuditLogEntry<'a> {
+    pub actor_id: Option<i32>,
+    pub user_id: Option<i32>,
+    pub match_id: Option<i32>,
+    pub subject: &'a str,
+    pub source: &'a str,
+    pub old_value: Option<Value>,
+    pub new_value: Option<Value>,
+}
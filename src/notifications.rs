@@ -0,0 +1,517 @@
+//! Messages to a Slack channel, or anything else that accepts incoming webhooks: the result of
+//! every match with the points it brought and the movement on the leaderboard, and a reminder a
+//! few hours before kickoff for everyone who didn't predict the match yet. Users are mentioned by
+//! their `slack_handle`.
+//!
+//! `SLACK_WEBHOOK_URL` is the webhook to post to, without it the messages are logged.
+//! `SLACK_REMINDER_HOURS` is how long before kickoff the reminder is sent (3 by default).
+
+use models::{MatchOutcome, MatchWithAllInfo, User};
+use ranking::{rank, tiebreak_stats, Position, Tiebreaker};
+use web::app_state::DbExecutor;
+use web::tournaments::current_tournament;
+
+use actix::prelude::*;
+use actix_web::client;
+use chrono::{Duration, Utc};
+use diesel::{self, prelude::*};
+use failure;
+use futures::{future, Future};
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time;
+
+/// How often is checked whether reminders are due
+const REMINDER_CHECK_SECONDS: u64 = 300;
+
+/// Number of users shown in the leaderboard of a result
+const LEADERBOARD_SIZE: usize = 10;
+
+#[derive(Clone, Debug)]
+pub struct Notification {
+    pub text: String,
+}
+
+impl Message for Notification {
+    type Result = Result<(), failure::Error>;
+}
+
+/// Delivers notifications
+pub trait NotificationSink {
+    fn post(&self, notification: &Notification) -> Box<Future<Item = (), Error = failure::Error>>;
+}
+
+/// Posts to a Slack incoming webhook
+pub struct WebhookSink {
+    url: String,
+}
+
+impl WebhookSink {
+    pub fn new(url: &str) -> WebhookSink {
+        WebhookSink {
+            url: url.to_string(),
+        }
+    }
+}
+
+impl NotificationSink for WebhookSink {
+    fn post(&self, notification: &Notification) -> Box<Future<Item = (), Error = failure::Error>> {
+        // link_names turns the @handles into mentions
+        let request = match client::post(&self.url).json(json!({
+            "text": notification.text,
+            "link_names": 1,
+        })) {
+            Ok(request) => request,
+            Err(error) => return Box::new(future::err(failure::err_msg(error.to_string()))),
+        };
+
+        Box::new(
+            request
+                .send()
+                .map_err(failure::Error::from)
+                .and_then(|response| {
+                    if response.status().is_success() {
+                        Ok(())
+                    } else {
+                        Err(failure::err_msg(format!(
+                            "The webhook answered {}",
+                            response.status()
+                        )))
+                    }
+                }),
+        )
+    }
+}
+
+/// Prints the notifications to stdout, for development
+pub struct LogSink;
+
+impl NotificationSink for LogSink {
+    fn post(&self, notification: &Notification) -> Box<Future<Item = (), Error = failure::Error>> {
+        println!("Notification:\n{}", notification.text);
+
+        Box::new(future::ok(()))
+    }
+}
+
+/// Keeps the notifications in memory, for tests
+#[derive(Clone, Default)]
+pub struct MemorySink {
+    pub sent: Arc<Mutex<Vec<String>>>,
+}
+
+impl NotificationSink for MemorySink {
+    fn post(&self, notification: &Notification) -> Box<Future<Item = (), Error = failure::Error>> {
+        match self.sent.lock() {
+            Ok(mut sent) => {
+                sent.push(notification.text.clone());
+                Box::new(future::ok(()))
+            }
+            Err(_) => Box::new(future::err(failure::err_msg("The memory sink is poisoned"))),
+        }
+    }
+}
+
+/// Post to `SLACK_WEBHOOK_URL` when it is set, log the notifications otherwise
+pub fn sink_from_env() -> Box<NotificationSink> {
+    match env::var("SLACK_WEBHOOK_URL") {
+        Ok(url) => Box::new(WebhookSink::new(&url)),
+        Err(_) => Box::new(LogSink),
+    }
+}
+
+/// How long before kickoff reminders are sent, the number of hours in `var` or `default`
+pub fn reminder_hours_from_env(var: &str, default: i64) -> i64 {
+    env::var(var)
+        .map(|hours| {
+            hours
+                .parse()
+                .unwrap_or_else(|_| panic!("{} must be a number of hours", var))
+        })
+        .unwrap_or(default)
+}
+
+/// Sends the notifications it receives to its sink. The DB executors have no event loop to do
+/// that themselves, so they return their notifications to whoever sent them the message. The
+/// answer says whether the post worked, see `Reminders`.
+pub struct Notifications {
+    sink: Box<NotificationSink>,
+}
+
+impl Notifications {
+    pub fn new(sink: Box<NotificationSink>) -> Notifications {
+        Notifications { sink }
+    }
+}
+
+impl Actor for Notifications {
+    type Context = Context<Self>;
+}
+
+impl Handler<Notification> for Notifications {
+    type Result = ResponseFuture<(), failure::Error>;
+
+    fn handle(&mut self, msg: Notification, _: &mut Self::Context) -> Self::Result {
+        Box::new(self.sink.post(&msg).map_err(|error| {
+            println!("Notification failed: {}", error);
+            error
+        }))
+    }
+}
+
+/// How users are called in notifications
+pub fn mention(user: &User) -> String {
+    match user.slack_handle {
+        Some(ref handle) if !handle.is_empty() => format!("@{}", handle),
+        _ => user
+            .display_name
+            .clone()
+            .unwrap_or_else(|| user.login.clone()),
+    }
+}
+
+/// The countries of a match with their flags, "?" for the ones that aren't known yet
+pub fn describe_match(game: &MatchWithAllInfo) -> String {
+    format!(
+        "{} {} - {} {}",
+        game.home_country_name.as_ref().map_or("?", String::as_str),
+        game.home_country_flag.as_ref().map_or("", String::as_str),
+        game.away_country_flag.as_ref().map_or("", String::as_str),
+        game.away_country_name.as_ref().map_or("?", String::as_str),
+    )
+}
+
+/// The score of every user in a tournament in the order of the leaderboard
+pub fn tournament_scores(
+    conn: &PgConnection,
+    for_tournament_id: i32,
+    tiebreakers: &[Tiebreaker],
+) -> QueryResult<Vec<(Position, (User, i32))>> {
+    use schema::{tournament_scores, users};
+
+    let scores = users::table
+        .inner_join(tournament_scores::table)
+        .filter(tournament_scores::tournament_id.eq(for_tournament_id))
+        .select((users::all_columns, tournament_scores::score))
+        .load::<(User, i32)>(conn)?;
+    let stats = tiebreak_stats(conn, for_tournament_id, None)?;
+
+    Ok(rank(
+        scores,
+        |&(ref user, score)| (user.user_id, i64::from(score)),
+        &stats,
+        tiebreakers,
+    ))
+}
+
+/// A user on the leaderboard after a result
+pub struct RankedScore {
+    pub user: User,
+    /// What the match brought
+    pub points: i32,
+    pub score: i32,
+    pub position: Position,
+    /// `None` for users that weren't on the leaderboard yet
+    pub previous_rank: Option<usize>,
+}
+
+/// What a match outcome changed, see `web::admin::match_outcomes::save_match_outcome`
+pub struct OutcomeReport {
+    pub game: MatchWithAllInfo,
+    pub home_score: i16,
+    pub away_score: i16,
+    /// Sorted by rank
+    pub leaderboard: Vec<RankedScore>,
+}
+
+impl OutcomeReport {
+    /// `before` and `after` are the `tournament_scores` around storing the outcome, `points`
+    /// what the match brought every user
+    pub fn new(
+        game: MatchWithAllInfo,
+        outcome: &MatchOutcome,
+        before: &[(Position, (User, i32))],
+        after: Vec<(Position, (User, i32))>,
+        points: &HashMap<i32, i32>,
+    ) -> OutcomeReport {
+        let previous_ranks = before
+            .iter()
+            .map(|&(position, (ref user, _))| (user.user_id, position.rank))
+            .collect::<HashMap<_, _>>();
+
+        OutcomeReport {
+            game,
+            home_score: outcome.home_score,
+            away_score: outcome.away_score,
+            leaderboard: after
+                .into_iter()
+                .map(|(position, (user, score))| RankedScore {
+                    points: points.get(&user.user_id).cloned().unwrap_or(0),
+                    position,
+                    previous_rank: previous_ranks.get(&user.user_id).cloned(),
+                    user,
+                    score,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn notification(&self) -> Notification {
+        let mut scorers = self
+            .leaderboard
+            .iter()
+            .filter(|entry| entry.points > 0)
+            .collect::<Vec<_>>();
+        scorers.sort_by(|a, b| b.points.cmp(&a.points));
+
+        let points = if scorers.is_empty() {
+            "Nobody scored points".to_string()
+        } else {
+            scorers
+                .iter()
+                .map(|entry| format!("{} {}", mention(&entry.user), entry.points))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        let leaderboard = self
+            .leaderboard
+            .iter()
+            .take(LEADERBOARD_SIZE)
+            .map(|entry| {
+                let current = entry.position.rank;
+                let movement = match entry.previous_rank {
+                    Some(previous) if previous > current => format!(" (▲{})", previous - current),
+                    Some(previous) if previous < current => format!(" (▼{})", current - previous),
+                    _ => String::new(),
+                };
+                format!(
+                    "{}{}. {} {}{}",
+                    if entry.position.shared { "T-" } else { "" },
+                    current,
+                    mention(&entry.user),
+                    entry.score,
+                    movement
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        Notification {
+            text: format!(
+                "Final score: {} ({} - {})\nPoints: {}\n{}",
+                describe_match(&self.game),
+                self.home_score,
+                self.away_score,
+                points,
+                leaderboard
+            ),
+        }
+    }
+}
+
+/// A reminder for a match, which is only recorded once it was posted
+pub struct Reminder {
+    pub match_id: i32,
+    pub notification: Notification,
+}
+
+/// Reminders for the matches of the current tournament that kick off within `hours`, for those
+/// that weren't reminded of yet
+pub struct FetchReminders {
+    pub hours: i64,
+}
+
+impl Message for FetchReminders {
+    type Result = Result<Vec<Reminder>, failure::Error>;
+}
+
+impl Handler<FetchReminders> for DbExecutor {
+    type Result = Result<Vec<Reminder>, failure::Error>;
+
+    fn handle(&mut self, msg: FetchReminders, _: &mut Self::Context) -> Self::Result {
+        use schema::{full_match_infos, match_predictions, slack_reminders, users};
+
+        let tournament = current_tournament(&self.connection)?;
+        let now = Utc::now();
+
+        self.connection.transaction(|| {
+            let reminded = slack_reminders::table.select(slack_reminders::match_id);
+            let due = full_match_infos::table
+                .filter(full_match_infos::tournament_id.eq(tournament.tournament_id))
+                .filter(full_match_infos::time.gt(self.lock_policy.open_kickoffs_after()))
+                .filter(full_match_infos::time.le(now + Duration::hours(msg.hours)))
+                .filter(full_match_infos::match_id.ne_all(reminded))
+                .order(full_match_infos::time)
+                .load::<MatchWithAllInfo>(&self.connection)?;
+
+            let mut reminders = Vec::new();
+            for game in due {
+                let predicted = match_predictions::table
+                    .filter(match_predictions::match_id.eq(game.match_id))
+                    .select(match_predictions::user_id);
+                let forgotten = users::table
+                    .filter(users::user_id.ne_all(predicted))
+                    .order(users::user_id)
+                    .load::<User>(&self.connection)?;
+                if forgotten.is_empty() {
+                    // Nothing to post, so there is nothing that can fail either
+                    record_reminder(&self.connection, game.match_id)?;
+                    continue;
+                }
+
+                reminders.push(Reminder {
+                    match_id: game.match_id,
+                    notification: Notification {
+                        text: format!(
+                            "{} kicks off at {} UTC, still to predict: {}",
+                            describe_match(&game),
+                            game.time.format("%H:%M"),
+                            forgotten
+                                .iter()
+                                .map(mention)
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        ),
+                    },
+                });
+            }
+
+            Ok(reminders)
+        })
+    }
+}
+
+fn record_reminder(conn: &PgConnection, for_match_id: i32) -> QueryResult<()> {
+    use schema::slack_reminders;
+
+    diesel::insert_into(slack_reminders::table)
+        .values(slack_reminders::match_id.eq(for_match_id))
+        .on_conflict_do_nothing()
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Remember that the reminder of a match was posted, so it isn't posted again
+pub struct RecordReminder {
+    pub match_id: i32,
+}
+
+impl Message for RecordReminder {
+    type Result = Result<(), failure::Error>;
+}
+
+impl Handler<RecordReminder> for DbExecutor {
+    type Result = Result<(), failure::Error>;
+
+    fn handle(&mut self, msg: RecordReminder, _: &mut Self::Context) -> Self::Result {
+        Ok(record_reminder(&self.connection, msg.match_id)?)
+    }
+}
+
+/// Checks every few minutes whether reminders are due
+pub struct Reminders {
+    db: Addr<DbExecutor>,
+    notifications: Addr<Notifications>,
+    hours: i64,
+}
+
+impl Reminders {
+    pub fn new(db: Addr<DbExecutor>, notifications: Addr<Notifications>, hours: i64) -> Reminders {
+        Reminders {
+            db,
+            notifications,
+            hours,
+        }
+    }
+
+    fn check(&self) {
+        let db = self.db.clone();
+        let notifications = self.notifications.clone();
+
+        Arbiter::spawn(
+            self.db
+                .send(FetchReminders { hours: self.hours })
+                .map_err(failure::Error::from)
+                .and_then(|result| result)
+                .and_then(move |reminders| {
+                    future::join_all(reminders.into_iter().map(move |reminder| {
+                        let db = db.clone();
+                        let match_id = reminder.match_id;
+
+                        notifications
+                            .send(reminder.notification)
+                            .map_err(failure::Error::from)
+                            .and_then(|result| result)
+                            .and_then(move |()| {
+                                db.send(RecordReminder { match_id })
+                                    .map_err(failure::Error::from)
+                                    .and_then(|result| result)
+                            })
+                            // Without a record the reminder is posted again on the next check
+                            .or_else(|error| -> Result<(), failure::Error> {
+                                println!("Reminder failed: {}", error);
+                                Ok(())
+                            })
+                    }))
+                })
+                .map(|_| ())
+                .map_err(|error| println!("Reminders failed: {}", error)),
+        );
+    }
+}
+
+impl Actor for Reminders {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.check();
+        ctx.run_interval(
+            time::Duration::from_secs(REMINDER_CHECK_SECONDS),
+            |reminders, _| reminders.check(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fails every post, like a webhook that is down
+    struct FailingSink;
+
+    impl NotificationSink for FailingSink {
+        fn post(&self, _: &Notification) -> Box<Future<Item = (), Error = failure::Error>> {
+            Box::new(future::err(failure::err_msg("The webhook is down")))
+        }
+    }
+
+    /// Has `Notifications` post a reminder to `sink`
+    fn post_reminder(sink: Box<NotificationSink>) -> Result<(), failure::Error> {
+        let mut system = System::new("notifications");
+        let notifications = Notifications::new(sink).start();
+
+        system.block_on(notifications.send(Notification {
+            text: "Netherlands - Spain kicks off at 19:00 UTC, still to predict: @jan".to_string(),
+        }))?
+    }
+
+    #[test]
+    fn posted_reminder_reaches_the_sink() {
+        let sink = MemorySink::default();
+
+        post_reminder(Box::new(sink.clone())).unwrap();
+
+        assert_eq!(
+            *sink.sent.lock().unwrap(),
+            vec!["Netherlands - Spain kicks off at 19:00 UTC, still to predict: @jan".to_string()]
+        );
+    }
+
+    #[test]
+    fn failed_post_is_reported() {
+        assert!(post_reminder(Box::new(FailingSink)).is_err());
+    }
+}
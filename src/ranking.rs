@@ -0,0 +1,204 @@
+//! The order of the leaderboard. Users with the same score are ordered by the `tiebreakers` of
+//! the scoring rules, users that are still level after all of them share their position, which
+//! is shown as e.g. "T-3".
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/// A way to order users with the same score, the first one that differs decides
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Tiebreaker {
+    /// The most predictions with the exact score
+    ExactScores,
+    /// The most points for predictions (without the time of first goal)
+    PredictionPoints,
+    /// The most points for the time of first goal
+    TimeOfFirstGoalPoints,
+    /// The earliest registration
+    Registration,
+}
+
+impl Tiebreaker {
+    /// How the rules page explains the tiebreaker
+    pub fn description(&self) -> &'static str {
+        match *self {
+            Tiebreaker::ExactScores => "the most predictions with the exact score",
+            Tiebreaker::PredictionPoints => "the most points for predictions",
+            Tiebreaker::TimeOfFirstGoalPoints => "the most points for the time of first goal",
+            Tiebreaker::Registration => "the earliest registration",
+        }
+    }
+}
+
+/// Without `Registration`, which hardly ever leaves two users level, so positions can be shared
+pub fn default_tiebreakers() -> Vec<Tiebreaker> {
+    vec![
+        Tiebreaker::ExactScores,
+        Tiebreaker::PredictionPoints,
+        Tiebreaker::TimeOfFirstGoalPoints,
+    ]
+}
+
+mod tiebreak_stats {
+    use chrono::NaiveDateTime;
+    use diesel::sql_types::*;
+
+    #[derive(QueryableByName)]
+    pub struct TiebreakRow {
+        #[sql_type = "Integer"]
+        pub user_id: i32,
+        #[sql_type = "Timestamp"]
+        pub registered_at: NaiveDateTime,
+        #[sql_type = "BigInt"]
+        pub exact_scores: i64,
+        #[sql_type = "BigInt"]
+        pub prediction_points: i64,
+        #[sql_type = "BigInt"]
+        pub time_of_first_goal_points: i64,
+    }
+}
+
+/// Everything the tiebreakers look at for one user
+#[derive(Clone, Debug)]
+pub struct TiebreakStats {
+    pub exact_scores: i64,
+    pub prediction_points: i64,
+    pub time_of_first_goal_points: i64,
+    pub registered_at: NaiveDateTime,
+}
+
+/// The tiebreak stats of every user for a tournament, counting only the matches up to `up_to`
+/// when it is given (like the leaderboard of `/scores?up_to=`)
+pub fn tiebreak_stats(
+    conn: &PgConnection,
+    for_tournament_id: i32,
+    up_to: Option<DateTime<Utc>>,
+) -> QueryResult<HashMap<i32, TiebreakStats>> {
+    use diesel::sql_query;
+    use diesel::sql_types::{Integer, Nullable, Timestamptz};
+
+    let rows = sql_query(
+        "
+        SELECT users.user_id,
+               users.created_at AS registered_at,
+               coalesce(exact.exact_scores, 0) AS exact_scores,
+               coalesce(points.prediction, 0) AS prediction_points,
+               coalesce(points.time_of_first_goal, 0) AS time_of_first_goal_points
+        FROM users
+             LEFT JOIN (
+                 SELECT user_match_points.user_id,
+                        sum(user_match_points.prediction) AS prediction,
+                        sum(user_match_points.time_of_first_goal) AS time_of_first_goal
+                 FROM user_match_points
+                      INNER JOIN matches ON user_match_points.match_id = matches.match_id
+                 WHERE matches.tournament_id = $1
+                   AND ($2::timestamptz IS NULL OR matches.time <= $2)
+                 GROUP BY user_match_points.user_id
+             ) AS points ON users.user_id = points.user_id
+             LEFT JOIN (
+                 SELECT match_predictions.user_id, count(*) AS exact_scores
+                 FROM match_predictions
+                      INNER JOIN match_outcomes
+                        ON match_predictions.match_id = match_outcomes.match_id
+                      INNER JOIN matches ON match_predictions.match_id = matches.match_id
+                 WHERE matches.tournament_id = $1
+                   AND ($2::timestamptz IS NULL OR matches.time <= $2)
+                   AND match_predictions.home_score = match_outcomes.home_score
+                   AND match_predictions.away_score = match_outcomes.away_score
+                 GROUP BY match_predictions.user_id
+             ) AS exact ON users.user_id = exact.user_id
+        ",
+    ).bind::<Integer, _>(for_tournament_id)
+        .bind::<Nullable<Timestamptz>, _>(up_to)
+        .load::<tiebreak_stats::TiebreakRow>(conn)?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            (
+                row.user_id,
+                TiebreakStats {
+                    exact_scores: row.exact_scores,
+                    prediction_points: row.prediction_points,
+                    time_of_first_goal_points: row.time_of_first_goal_points,
+                    registered_at: row.registered_at,
+                },
+            )
+        })
+        .collect())
+}
+
+/// A place on the leaderboard
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct Position {
+    pub rank: usize,
+    /// Whether other users are level on score and all tiebreakers
+    pub shared: bool,
+}
+
+fn compare_stats(tiebreakers: &[Tiebreaker], a: &TiebreakStats, b: &TiebreakStats) -> Ordering {
+    for tiebreaker in tiebreakers {
+        let ordering = match *tiebreaker {
+            Tiebreaker::ExactScores => b.exact_scores.cmp(&a.exact_scores),
+            Tiebreaker::PredictionPoints => b.prediction_points.cmp(&a.prediction_points),
+            Tiebreaker::TimeOfFirstGoalPoints => b
+                .time_of_first_goal_points
+                .cmp(&a.time_of_first_goal_points),
+            Tiebreaker::Registration => a.registered_at.cmp(&b.registered_at),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+/// Orders `entries` for the leaderboard and gives each its position. `key` gives the user id
+/// and score of an entry, users without `stats` only differ by score.
+pub fn rank<T, F>(
+    mut entries: Vec<T>,
+    key: F,
+    stats: &HashMap<i32, TiebreakStats>,
+    tiebreakers: &[Tiebreaker],
+) -> Vec<(Position, T)>
+where
+    F: Fn(&T) -> (i32, i64),
+{
+    let compare = |a: &T, b: &T| {
+        let (a_id, a_score) = key(a);
+        let (b_id, b_score) = key(b);
+        b_score
+            .cmp(&a_score)
+            .then_with(|| match (stats.get(&a_id), stats.get(&b_id)) {
+                (Some(a), Some(b)) => compare_stats(tiebreakers, a, b),
+                _ => Ordering::Equal,
+            })
+    };
+
+    // Users that share a position are listed by user id, so the order doesn't jump around
+    entries.sort_by(|a, b| compare(a, b).then_with(|| key(a).0.cmp(&key(b).0)));
+
+    // Whether every entry is level with the next one
+    let level = entries
+        .windows(2)
+        .map(|pair| compare(&pair[0], &pair[1]) == Ordering::Equal)
+        .collect::<Vec<_>>();
+    let mut rank = 0;
+    let positions = (0..entries.len())
+        .map(|index| {
+            let level_with_previous = index > 0 && level[index - 1];
+            if !level_with_previous {
+                rank = index + 1;
+            }
+            Position {
+                rank,
+                shared: level_with_previous || level.get(index).cloned().unwrap_or(false),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    positions.into_iter().zip(entries).collect()
+}
@@ -0,0 +1,215 @@
+//! Emails for users that forgot to predict matches that kick off soon.
+//!
+//! The `EmailReminders` actor checks every few minutes for matches of the current tournament that
+//! kick off within `REMINDER_EMAIL_HOURS` hours (24 by default) and can still be predicted. Every
+//! user that turned reminders on in their settings gets one email listing all such matches they
+//! didn't predict yet, and every match is mentioned only once per user. Every email has its own
+//! unsubscribe link.
+
+use mailer::Email;
+use models::{MatchWithAllInfo, NewUnsubscribeToken, User};
+use notifications::describe_match;
+use web::app_state::DbExecutor;
+use web::auth::hash_token;
+use web::tournaments::current_tournament;
+
+use actix::prelude::*;
+use chrono::{Duration, Utc};
+use diesel::{self, prelude::*};
+use failure;
+use futures::Future;
+use rand::{distributions::Alphanumeric, prelude::*};
+use std::collections::HashSet;
+use std::time;
+
+const UNSUBSCRIBE_TOKEN_LENGTH: usize = 40;
+
+/// Seconds between the checks for due emails, a digest covers a day so they needn't be as
+/// frequent as the Slack reminders
+const REMINDER_CHECK_SECONDS: u64 = 900;
+
+/// Send the reminders that are due, returns what happened worth logging
+pub struct SendReminderEmails {
+    pub hours: i64,
+}
+
+impl Message for SendReminderEmails {
+    type Result = Result<Vec<String>, failure::Error>;
+}
+
+/// Remember which matches `user` was reminded of and email them, in one transaction so a failed
+/// email is tried again on the next check
+fn send_reminder_email(
+    db: &DbExecutor,
+    user: &User,
+    forgotten: &[&MatchWithAllInfo],
+) -> Result<(), failure::Error> {
+    use schema::{reminder_emails, unsubscribe_tokens};
+
+    db.connection.transaction::<_, failure::Error, _>(|| {
+        let token = thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(UNSUBSCRIBE_TOKEN_LENGTH)
+            .collect::<String>();
+        diesel::insert_into(unsubscribe_tokens::table)
+            .values(&NewUnsubscribeToken {
+                user_id: user.user_id,
+                token_hash: &hash_token(&token),
+            })
+            .execute(&db.connection)?;
+
+        diesel::insert_into(reminder_emails::table)
+            .values(
+                forgotten
+                    .iter()
+                    .map(|game| {
+                        (
+                            reminder_emails::user_id.eq(user.user_id),
+                            reminder_emails::match_id.eq(game.match_id),
+                        )
+                    })
+                    .collect::<Vec<_>>(),
+            )
+            .execute(&db.connection)?;
+
+        let matches = forgotten
+            .iter()
+            .map(|game| {
+                format!(
+                    "- {}, predictions close {}",
+                    describe_match(game),
+                    db.lock_policy
+                        .locks_at(game.time)
+                        .format("%A %-d %B at %H:%M UTC")
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        db.mailer.send(&Email {
+            to: user.email.clone(),
+            subject: if forgotten.len() == 1 {
+                "You haven't predicted a match yet".to_string()
+            } else {
+                format!("You haven't predicted {} matches yet", forgotten.len())
+            },
+            body: format!(
+                "These matches kick off soon and you haven't predicted them yet:\n\n{}\n\n\
+                 You can predict them at {}/\n\n\
+                 You get this email because you asked for reminders. To stop them, go to \
+                 {}/reminders/unsubscribe/{} or change your settings at {}/settings.",
+                matches, db.base_url, db.base_url, token, db.base_url
+            ),
+        })?;
+
+        Ok(())
+    })
+}
+
+impl Handler<SendReminderEmails> for DbExecutor {
+    type Result = Result<Vec<String>, failure::Error>;
+
+    fn handle(&mut self, msg: SendReminderEmails, _: &mut Self::Context) -> Self::Result {
+        use schema::{full_match_infos, match_predictions, reminder_emails, users};
+
+        let tournament = current_tournament(&self.connection)?;
+
+        // Matches that can't be predicted anymore aren't worth a reminder
+        let upcoming = full_match_infos::table
+            .filter(full_match_infos::tournament_id.eq(tournament.tournament_id))
+            .filter(full_match_infos::time.gt(self.lock_policy.open_kickoffs_after()))
+            .filter(full_match_infos::time.le(Utc::now() + Duration::hours(msg.hours)))
+            .order((full_match_infos::time, full_match_infos::match_number))
+            .load::<MatchWithAllInfo>(&self.connection)?;
+        if upcoming.is_empty() {
+            return Ok(Vec::new());
+        }
+        let match_ids = upcoming
+            .iter()
+            .map(|game| game.match_id)
+            .collect::<Vec<_>>();
+
+        // Predicted matches and matches the user was already reminded of
+        let mut done = match_predictions::table
+            .filter(match_predictions::match_id.eq_any(&match_ids))
+            .select((match_predictions::user_id, match_predictions::match_id))
+            .load::<(i32, i32)>(&self.connection)?
+            .into_iter()
+            .collect::<HashSet<_>>();
+        done.extend(
+            reminder_emails::table
+                .filter(reminder_emails::match_id.eq_any(&match_ids))
+                .select((reminder_emails::user_id, reminder_emails::match_id))
+                .load::<(i32, i32)>(&self.connection)?,
+        );
+
+        let users = users::table
+            .filter(users::wants_reminder_emails.eq(true))
+            .order(users::user_id)
+            .load::<User>(&self.connection)?;
+
+        let mut messages = Vec::new();
+        for user in users {
+            let forgotten = upcoming
+                .iter()
+                .filter(|game| !done.contains(&(user.user_id, game.match_id)))
+                .collect::<Vec<_>>();
+            if forgotten.is_empty() {
+                continue;
+            }
+
+            match send_reminder_email(self, &user, &forgotten) {
+                Ok(()) => messages.push(format!(
+                    "Reminded user {} of {} matches",
+                    user.user_id,
+                    forgotten.len()
+                )),
+                Err(error) => messages.push(format!(
+                    "The reminder for user {} failed: {}",
+                    user.user_id, error
+                )),
+            }
+        }
+
+        Ok(messages)
+    }
+}
+
+/// Checks every few minutes whether reminders are due
+pub struct EmailReminders {
+    db: Addr<DbExecutor>,
+    hours: i64,
+}
+
+impl EmailReminders {
+    pub fn new(db: Addr<DbExecutor>, hours: i64) -> EmailReminders {
+        EmailReminders { db, hours }
+    }
+
+    fn check(&self) {
+        Arbiter::spawn(
+            self.db
+                .send(SendReminderEmails { hours: self.hours })
+                .map_err(failure::Error::from)
+                .and_then(|result| result)
+                .map(|messages| {
+                    for message in messages {
+                        println!("Reminder emails: {}", message);
+                    }
+                })
+                .map_err(|error| println!("Reminder emails: {}", error)),
+        );
+    }
+}
+
+impl Actor for EmailReminders {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.check();
+        ctx.run_interval(
+            time::Duration::from_secs(REMINDER_CHECK_SECONDS),
+            |reminders, _| reminders.check(),
+        );
+    }
+}
@@ -1,21 +1,46 @@
-#[derive(Debug, DbEnum)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, DbEnum)]
+#[serde(rename_all = "snake_case")]
 pub enum StageType {
     Group,
     Knockout,
 }
 
+table! {
+    api_tokens (api_token_id) {
+        api_token_id -> Int4,
+        user_id -> Int4,
+        name -> Varchar,
+        token_hash -> Varchar,
+        created_at -> Timestamp,
+        last_used_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    audit_log_entries (audit_log_entry_id) {
+        audit_log_entry_id -> Int4,
+        actor_id -> Nullable<Int4>,
+        user_id -> Nullable<Int4>,
+        match_id -> Nullable<Int4>,
+        subject -> Varchar,
+        source -> Varchar,
+        old_value -> Nullable<Jsonb>,
+        new_value -> Nullable<Jsonb>,
+        created_at -> Timestamp,
+    }
+}
+
 table! {
     countries (country_id) {
         country_id -> Int4,
         name -> Varchar,
         flag -> Varchar,
         seeding_pot -> Bpchar,
-        qualified_for_knockout -> Bool,
     }
 }
 
 table! {
-    favourites (user_id, choice) {
+    favourites (user_id, tournament_id, choice) {
         user_id -> Int4,
         country_id -> Nullable<Int4>,
         choice -> Int2,
@@ -23,6 +48,7 @@ table! {
         updated_at -> Timestamp,
         phase -> Int2,
         source -> Varchar,
+        tournament_id -> Int4,
     }
 }
 
@@ -32,6 +58,7 @@ table! {
         country_id -> Int4,
         drawn_place -> Int2,
         current_position -> Int2,
+        qualified_for_knockout -> Bool,
     }
 }
 
@@ -50,6 +77,35 @@ table! {
     groups (group_id) {
         group_id -> Int4,
         name -> Varchar,
+        tournament_id -> Int4,
+    }
+}
+
+table! {
+    league_memberships (league_id, user_id) {
+        league_id -> Int4,
+        user_id -> Int4,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    leagues (league_id) {
+        league_id -> Int4,
+        name -> Varchar,
+        invite_code -> Varchar,
+        created_by -> Int4,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    live_scores (match_id) {
+        match_id -> Int4,
+        home_score -> Int2,
+        away_score -> Int2,
+        minute -> Nullable<Int2>,
+        updated_at -> Timestamp,
     }
 }
 
@@ -61,6 +117,16 @@ table! {
     }
 }
 
+table! {
+    login_attempts (login_attempt_id) {
+        login_attempt_id -> Int4,
+        login -> Varchar,
+        ip_address -> Varchar,
+        successful -> Bool,
+        created_at -> Timestamp,
+    }
+}
+
 table! {
     match_outcomes (match_id) {
         match_id -> Int4,
@@ -111,17 +177,85 @@ table! {
         home_participant_id -> Int4,
         away_participant_id -> Int4,
         time -> Timestamptz,
+        tournament_id -> Int4,
+        match_number -> Int4,
+    }
+}
+
+table! {
+    password_reset_tokens (password_reset_token_id) {
+        password_reset_token_id -> Int4,
+        user_id -> Int4,
+        token_hash -> Varchar,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    reminder_emails (user_id, match_id) {
+        user_id -> Int4,
+        match_id -> Int4,
+        sent_at -> Timestamp,
+    }
+}
+
+table! {
+    slack_reminders (match_id) {
+        match_id -> Int4,
+        sent_at -> Timestamp,
     }
 }
 
 table! {
-    use diesel::sql_types::{Int4, Nullable, Varchar};
+    use diesel::sql_types::{Bool, Int2, Int4, Nullable, Varchar};
     use super::StageTypeMapping;
     stages (stage_id) {
         stage_id -> Int4,
         parent_stage_id -> Nullable<Int4>,
         stage_type -> StageTypeMapping,
         description -> Varchar,
+        phase -> Int2,
+        is_final -> Bool,
+        tournament_id -> Int4,
+        stage_number -> Int4,
+    }
+}
+
+table! {
+    tournament_scores (tournament_id, user_id) {
+        tournament_id -> Int4,
+        user_id -> Int4,
+        score -> Int4,
+    }
+}
+
+table! {
+    tournaments (tournament_id) {
+        tournament_id -> Int4,
+        name -> Varchar,
+        is_archived -> Bool,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    unsubscribe_tokens (unsubscribe_token_id) {
+        unsubscribe_token_id -> Int4,
+        user_id -> Int4,
+        token_hash -> Varchar,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    user_group_points (user_id, group_id) {
+        user_id -> Int4,
+        group_id -> Int4,
+        winner -> Int4,
+        runnerup -> Int4,
+        total -> Int4,
     }
 }
 
@@ -147,6 +281,9 @@ table! {
         slack_handle -> Nullable<Varchar>,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        is_admin -> Bool,
+        tournament_id -> Nullable<Int4>,
+        wants_reminder_emails -> Bool,
     }
 }
 
@@ -171,15 +308,27 @@ table! {
 
         away_country_name -> Nullable<Varchar>,
         away_country_flag -> Nullable<Varchar>,
+
+        is_knockout -> Bool,
+
+        tournament_id -> Int4,
+        match_number -> Int4,
     }
 }
 
+joinable!(api_tokens -> users (user_id));
 joinable!(favourites -> countries (country_id));
+joinable!(favourites -> tournaments (tournament_id));
 joinable!(favourites -> users (user_id));
 joinable!(group_memberships -> countries (country_id));
 joinable!(group_memberships -> groups (group_id));
 joinable!(group_predictions -> groups (group_id));
 joinable!(group_predictions -> users (user_id));
+joinable!(groups -> tournaments (tournament_id));
+joinable!(league_memberships -> leagues (league_id));
+joinable!(league_memberships -> users (user_id));
+joinable!(leagues -> users (created_by));
+joinable!(live_scores -> matches (match_id));
 joinable!(match_outcomes -> matches (match_id));
 joinable!(match_participants -> countries (country_id));
 joinable!(match_participants -> stages (stage_id));
@@ -187,22 +336,47 @@ joinable!(match_predictions -> matches (match_id));
 joinable!(match_predictions -> users (user_id));
 joinable!(matches -> locations (location_id));
 joinable!(matches -> stages (stage_id));
+joinable!(matches -> tournaments (tournament_id));
+joinable!(password_reset_tokens -> users (user_id));
+joinable!(reminder_emails -> matches (match_id));
+joinable!(reminder_emails -> users (user_id));
+joinable!(slack_reminders -> matches (match_id));
+joinable!(stages -> tournaments (tournament_id));
+joinable!(tournament_scores -> tournaments (tournament_id));
+joinable!(tournament_scores -> users (user_id));
+joinable!(unsubscribe_tokens -> users (user_id));
+joinable!(user_group_points -> groups (group_id));
+joinable!(user_group_points -> users (user_id));
 joinable!(user_match_points -> matches (match_id));
 joinable!(user_match_points -> users (user_id));
+joinable!(users -> tournaments (tournament_id));
 
 allow_tables_to_appear_in_same_query!(
+    api_tokens,
+    audit_log_entries,
     countries,
     favourites,
     full_match_infos,
     group_memberships,
     group_predictions,
     groups,
+    league_memberships,
+    leagues,
+    live_scores,
     locations,
+    login_attempts,
     match_outcomes,
     match_participants,
     match_predictions,
     matches,
+    password_reset_tokens,
+    reminder_emails,
+    slack_reminders,
     stages,
+    tournament_scores,
+    tournaments,
+    unsubscribe_tokens,
+    user_group_points,
     user_match_points,
     users,
 );
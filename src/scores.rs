@@ -1,40 +1,155 @@
 use models::{
-    Favourite, MatchOutcome, MatchPrediction, MatchWithParticipants, User, UserMatchPoints,
+    Favourite, GroupPrediction, MatchOutcome, MatchPrediction, MatchWithParticipants, User,
+    UserGroupPoints, UserMatchPoints,
 };
+use ranking::{default_tiebreakers, Tiebreaker};
+
+use chrono::{DateTime, Utc};
+use failure;
+use toml;
 
 use std::cmp::max;
+use std::fs;
+use std::path::Path;
+
+/// All point values used when scoring predictions and favourites.
+///
+/// The defaults are the rules of the original pool, other pools can override any of them by
+/// pointing `SCORING_RULES` to a TOML file (keys that are left out keep their default value).
+/// The rules page is rendered from this struct as well, so the explanation matches the maths.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScoringRules {
+    /// Points for the right winner (or for correctly predicting a tie)
+    pub correct_winner: i32,
+    /// Points per team for which the number of goals is correct
+    pub correct_team_score: i32,
+    /// Bonus when the score line is correct for both teams
+    pub exact_score_bonus: i32,
+
+    /// Points for the exact minute of the first goal (only if the winner is right)
+    pub time_of_first_goal: i32,
+    /// Points lost for every minute the time of first goal is off
+    pub time_of_first_goal_decay: i32,
+
+    /// Knock-out: bonus for predicting that the match is decided by penalties
+    pub penalties_predicted: i32,
+    /// Knock-out: points for the right winner of the penalty shoot-out
+    pub penalties_correct_winner: i32,
+    /// Knock-out: points per team for which the number of penalties is correct
+    pub penalties_correct_team_score: i32,
+    /// Knock-out: bonus when the penalty score line is correct for both teams
+    pub penalties_exact_score_bonus: i32,
+    /// Knock-out: points when no tie was predicted, but the predicted winner wins the penalties
+    pub penalties_unpredicted_winner: i32,
+    /// Knock-out: points for the right duration (90 or 120 minutes)
+    pub correct_duration: i32,
+
+    /// Points when a favourite country wins its match
+    pub favourite_win: i32,
+    /// Points when a favourite country plays a tie
+    pub favourite_tie: i32,
+    /// Points per goal scored by a favourite country
+    pub favourite_goal: i32,
+    /// Bonus when a favourite country wins the final
+    pub favourite_champion: i32,
+
+    /// Points for the right group winner
+    pub group_winner: i32,
+    /// Points for the right runner-up of a group
+    pub group_runnerup: i32,
+    /// Points for a predicted winner or runner-up that qualifies, but in the other position
+    pub group_qualifier: i32,
+
+    /// How users with the same score are ordered on the leaderboard, see `ranking`
+    pub tiebreakers: Vec<Tiebreaker>,
+}
+
+impl Default for ScoringRules {
+    fn default() -> Self {
+        ScoringRules {
+            correct_winner: 2,
+            correct_team_score: 1,
+            exact_score_bonus: 3,
+
+            time_of_first_goal: 5,
+            time_of_first_goal_decay: 1,
+
+            penalties_predicted: 1,
+            penalties_correct_winner: 1,
+            penalties_correct_team_score: 1,
+            penalties_exact_score_bonus: 1,
+            penalties_unpredicted_winner: 1,
+            correct_duration: 1,
+
+            favourite_win: 3,
+            favourite_tie: 1,
+            favourite_goal: 1,
+            favourite_champion: 3,
+
+            group_winner: 3,
+            group_runnerup: 2,
+            group_qualifier: 1,
 
-fn phase_of(game: &MatchWithParticipants) -> i16 {
-    match game.stage_id {
-        1 => 0,
-        2 | 3 => 1,
-        4 | 5 | 6 => 2,
-        _ => unreachable!(),
+            tiebreakers: default_tiebreakers(),
+        }
+    }
+}
+
+impl ScoringRules {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<ScoringRules, failure::Error> {
+        let contents = fs::read_to_string(path)?;
+        Ok(toml::from_str(&contents)?)
+    }
+
+    /// Total amount of points for a prediction that is completely right (excluding the time of
+    /// first goal)
+    pub fn exact_score_points(&self) -> i32 {
+        self.correct_winner + 2 * self.correct_team_score + self.exact_score_bonus
+    }
+
+    /// Total amount of points for a completely right prediction of the penalty shoot-out
+    pub fn exact_penalties_points(&self) -> i32 {
+        self.penalties_predicted
+            + self.penalties_correct_winner
+            + 2 * self.penalties_correct_team_score
+            + self.penalties_exact_score_bonus
+    }
+
+    /// Number of minutes the time of first goal can be off before it doesn't earn points anymore
+    pub fn time_of_first_goal_window(&self) -> i32 {
+        if self.time_of_first_goal_decay > 0 {
+            (self.time_of_first_goal + self.time_of_first_goal_decay - 1)
+                / self.time_of_first_goal_decay
+        } else {
+            0
+        }
     }
 }
 
 fn favourite_points(
+    rules: &ScoringRules,
     favourite: &Favourite,
     game: &MatchWithParticipants,
     outcome: &MatchOutcome,
-) -> i16 {
+) -> i32 {
     if favourite.updated_at >= game.time.naive_utc() {
         return 0;
     }
-    if favourite.phase != phase_of(game) {
+    if favourite.tournament_id != game.stage.tournament_id || favourite.phase != game.stage.phase {
         return 0;
     }
 
-    let championship_points = if game.stage_id == 5 {
+    let championship_points = if game.stage.is_final {
         if favourite.country_id == game.home_participant.country_id {
             if outcome.winner() == 1 {
-                3
+                rules.favourite_champion
             } else {
                 0
             }
-        } else if favourite.country_id == game.home_participant.country_id {
+        } else if favourite.country_id == game.away_participant.country_id {
             if outcome.winner() == -1 {
-                3
+                rules.favourite_champion
             } else {
                 0
             }
@@ -45,28 +160,27 @@ fn favourite_points(
         0
     };
 
-    championship_points + if favourite.country_id == game.home_participant.country_id {
-        outcome.home_score + if outcome.home_score > outcome.away_score {
-            3
-        } else if outcome.home_score == outcome.away_score {
-            1
+    fn match_points(rules: &ScoringRules, scored: i16, conceded: i16) -> i32 {
+        rules.favourite_goal * i32::from(scored) + if scored > conceded {
+            rules.favourite_win
+        } else if scored == conceded {
+            rules.favourite_tie
         } else {
             0
         }
+    }
+
+    championship_points + if favourite.country_id == game.home_participant.country_id {
+        match_points(rules, outcome.home_score, outcome.away_score)
     } else if favourite.country_id == game.away_participant.country_id {
-        outcome.away_score + if outcome.away_score > outcome.home_score {
-            3
-        } else if outcome.home_score == outcome.away_score {
-            1
-        } else {
-            0
-        }
+        match_points(rules, outcome.away_score, outcome.home_score)
     } else {
         0
     }
 }
 
 fn prediction_and_tofg_points(
+    rules: &ScoringRules,
     prediction: &MatchPrediction,
     game: &MatchWithParticipants,
     outcome: &MatchOutcome,
@@ -90,21 +204,23 @@ fn prediction_and_tofg_points(
 
     let mut result = 0;
     if predicted_winner == actual_winner {
-        result += 2;
+        result += rules.correct_winner;
     }
     if prediction.home_score == outcome.home_score {
-        result += 1;
+        result += rules.correct_team_score;
     }
     if prediction.away_score == outcome.away_score {
-        result += 1;
+        result += rules.correct_team_score;
     }
-    if result == 4 {
+    if predicted_winner == actual_winner
+        && prediction.home_score == outcome.home_score
+        && prediction.away_score == outcome.away_score
+    {
         // Outcome equals prediction completely so far
-        result += 3;
+        result += rules.exact_score_bonus;
     }
 
-    // Technically this should check knock-out or group round
-    if phase_of(game) >= 1 {
+    if game.stage.is_knockout() {
         if actual_winner == 0 {
             // Check penalties
             if predicted_winner == 0 {
@@ -113,21 +229,23 @@ fn prediction_and_tofg_points(
                 let mut penalty_result = 0;
 
                 // Bonus point for predicting the way the game would end: in penalties
-                result += 1;
+                result += rules.penalties_predicted;
 
                 if predicted_home_win == actual_home_win {
-                    penalty_result += 1;
+                    penalty_result += rules.penalties_correct_winner;
 
                     if prediction.home_penalties == outcome.home_penalties {
-                        penalty_result += 1;
+                        penalty_result += rules.penalties_correct_team_score;
                     }
                     if prediction.away_penalties == outcome.away_penalties {
-                        penalty_result += 1;
+                        penalty_result += rules.penalties_correct_team_score;
                     }
 
                     // Bonus point if the outcome of the penalties is fully correct
-                    if penalty_result == 3 {
-                        penalty_result += 1;
+                    if prediction.home_penalties == outcome.home_penalties
+                        && prediction.away_penalties == outcome.away_penalties
+                    {
+                        penalty_result += rules.penalties_exact_score_bonus;
                     }
 
                     result += penalty_result;
@@ -141,7 +259,7 @@ fn prediction_and_tofg_points(
                 let penalty_winner = compare(home_penalties, away_penalties);
 
                 if penalty_winner == predicted_winner {
-                    result += 1;
+                    result += rules.penalties_unpredicted_winner;
                 }
             }
         } else if predicted_winner != 0 {
@@ -151,7 +269,7 @@ fn prediction_and_tofg_points(
             // matches, lets' count that as a 90 minutes outcome (as long as they didn't
             // predict penalties of course)
             if prediction.duration.or(Some(90)) == outcome.duration {
-                result += 1
+                result += rules.correct_duration
             }
         }
     }
@@ -159,27 +277,30 @@ fn prediction_and_tofg_points(
     // For the time of first goal we will only look at the right time in the regular time + extra
     // time, penalties don't matter
     let points_for_time_of_goal = if predicted_winner == actual_winner {
+        let minutes_off =
+            i32::from((outcome.time_of_first_goal - prediction.time_of_first_goal).abs());
         max(
             0,
-            5 - (outcome.time_of_first_goal - prediction.time_of_first_goal).abs(),
+            rules.time_of_first_goal - rules.time_of_first_goal_decay * minutes_off,
         )
     } else {
         0
     };
 
-    (result, points_for_time_of_goal.into())
+    (result, points_for_time_of_goal)
 }
 
 pub fn user_match_points(
+    rules: &ScoringRules,
     user_with_prediction: &(User, Option<MatchPrediction>, Vec<Favourite>),
     game: &(MatchWithParticipants, MatchOutcome),
 ) -> UserMatchPoints {
     let mut fav_points = 0;
     for favourite in &user_with_prediction.2 {
-        fav_points += i32::from(favourite_points(&favourite, &game.0, &game.1));
+        fav_points += favourite_points(rules, &favourite, &game.0, &game.1);
     }
     let (prediction_points, tofg_points) = if let Some(prediction) = &user_with_prediction.1 {
-        prediction_and_tofg_points(&prediction, &game.0, &game.1)
+        prediction_and_tofg_points(rules, &prediction, &game.0, &game.1)
     } else {
         (0, 0)
     };
@@ -195,3 +316,44 @@ pub fn user_match_points(
         total: prediction_points + tofg_points + fav_points,
     }
 }
+
+/// Points for the prediction of the winner and runner-up of a group, given the final standings
+/// and the start of the group phase (after which predictions don't count)
+pub fn user_group_points(
+    rules: &ScoringRules,
+    prediction: &GroupPrediction,
+    group_phase_start: &DateTime<Utc>,
+    winner_id: i32,
+    runnerup_id: i32,
+) -> UserGroupPoints {
+    let (winner, runnerup) = if prediction.updated_at >= group_phase_start.naive_utc() {
+        (0, 0)
+    } else {
+        let winner = if prediction.winner_id == winner_id {
+            rules.group_winner
+        } else if prediction.winner_id == runnerup_id {
+            rules.group_qualifier
+        } else {
+            0
+        };
+        let runnerup = if prediction.runnerup_id == runnerup_id {
+            rules.group_runnerup
+        } else if prediction.runnerup_id == winner_id {
+            rules.group_qualifier
+        } else {
+            0
+        };
+
+        (winner, runnerup)
+    };
+
+    UserGroupPoints {
+        user_id: prediction.user_id,
+        group_id: prediction.group_id,
+
+        winner,
+        runnerup,
+
+        total: winner + runnerup,
+    }
+}
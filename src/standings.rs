@@ -0,0 +1,150 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+const POINTS_FOR_WIN: i32 = 3;
+const POINTS_FOR_TIE: i32 = 1;
+
+/// The result of a single group match, expressed in countries instead of match participants
+#[derive(Debug, Clone)]
+pub struct GroupMatchResult {
+    pub home_country_id: i32,
+    pub away_country_id: i32,
+    pub home_score: i16,
+    pub away_score: i16,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TeamStanding {
+    pub country_id: i32,
+    pub drawn_place: i16,
+    pub position: i16,
+
+    pub played: i32,
+    pub won: i32,
+    pub drawn: i32,
+    pub lost: i32,
+    pub goals_for: i32,
+    pub goals_against: i32,
+    pub goal_difference: i32,
+    pub points: i32,
+}
+
+impl TeamStanding {
+    fn new(country_id: i32, drawn_place: i16) -> TeamStanding {
+        TeamStanding {
+            country_id,
+            drawn_place,
+            position: drawn_place,
+
+            played: 0,
+            won: 0,
+            drawn: 0,
+            lost: 0,
+            goals_for: 0,
+            goals_against: 0,
+            goal_difference: 0,
+            points: 0,
+        }
+    }
+
+    fn add_match(&mut self, scored: i16, conceded: i16) {
+        let (scored, conceded) = (i32::from(scored), i32::from(conceded));
+
+        self.played += 1;
+        self.goals_for += scored;
+        self.goals_against += conceded;
+        self.goal_difference = self.goals_for - self.goals_against;
+
+        if scored > conceded {
+            self.won += 1;
+            self.points += POINTS_FOR_WIN;
+        } else if scored == conceded {
+            self.drawn += 1;
+            self.points += POINTS_FOR_TIE;
+        } else {
+            self.lost += 1;
+        }
+    }
+
+    fn compare(&self, other: &TeamStanding) -> Ordering {
+        other
+            .points
+            .cmp(&self.points)
+            .then(other.goal_difference.cmp(&self.goal_difference))
+            .then(other.goals_for.cmp(&self.goals_for))
+    }
+}
+
+/// Build a table for the given teams, only counting matches in which both teams are part of the
+/// given set
+fn table_for(teams: &[(i32, i16)], results: &[GroupMatchResult]) -> HashMap<i32, TeamStanding> {
+    let mut table = teams
+        .iter()
+        .map(|&(country_id, drawn_place)| (country_id, TeamStanding::new(country_id, drawn_place)))
+        .collect::<HashMap<_, _>>();
+
+    for result in results {
+        if !table.contains_key(&result.home_country_id)
+            || !table.contains_key(&result.away_country_id)
+        {
+            continue;
+        }
+
+        if let Some(home) = table.get_mut(&result.home_country_id) {
+            home.add_match(result.home_score, result.away_score);
+        }
+        if let Some(away) = table.get_mut(&result.away_country_id) {
+            away.add_match(result.away_score, result.home_score);
+        }
+    }
+
+    table
+}
+
+/// Compute the standings of a group given its teams (country id and drawn place) and the results
+/// that are already known.
+///
+/// Teams are ranked on points, goal difference and goals scored. Teams that are still tied are
+/// ranked on the same criteria, but only taking the matches between the tied teams into account.
+/// As we don't track fair play points, the drawn place is used as the last resort instead of
+/// drawing lots.
+pub fn compute_standings(teams: &[(i32, i16)], results: &[GroupMatchResult]) -> Vec<TeamStanding> {
+    let table = table_for(teams, results);
+
+    let mut standings = table.values().cloned().collect::<Vec<_>>();
+    standings.sort_by(|a, b| a.compare(b).then(a.drawn_place.cmp(&b.drawn_place)));
+
+    let mut ranked = Vec::with_capacity(standings.len());
+    let mut start = 0;
+    while start < standings.len() {
+        let mut end = start + 1;
+        while end < standings.len() && standings[start].compare(&standings[end]) == Ordering::Equal
+        {
+            end += 1;
+        }
+
+        let mut tied = standings[start..end].to_vec();
+        if tied.len() > 1 {
+            let tied_teams = tied
+                .iter()
+                .map(|standing| (standing.country_id, standing.drawn_place))
+                .collect::<Vec<_>>();
+            let head_to_head = table_for(&tied_teams, results);
+
+            tied.sort_by(|a, b| {
+                head_to_head[&a.country_id]
+                    .compare(&head_to_head[&b.country_id])
+                    .then(a.drawn_place.cmp(&b.drawn_place))
+            });
+        }
+        ranked.extend(tied);
+
+        start = end;
+    }
+
+    for (index, standing) in ranked.iter_mut().enumerate() {
+        standing.position = (index + 1) as i16;
+    }
+
+    ranked
+}
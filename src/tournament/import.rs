@@ -0,0 +1,1050 @@
+//! Bring the database in line with a tournament definition. Rows are matched on their natural
+//! keys (tournament name, country name, group name, stadium, stage number and match number), so
+//! importing the same definition twice changes nothing, and rows that users have predicted are
+//! never removed without `force`. Countries and stadiums are shared by all tournaments, they are
+//! only removed when no other tournament uses them.
+
+use super::{InvalidTournament, ParticipantDefinition, Tournament};
+use audit::{self, Change};
+use models::{
+    self, Country, Favourite, Group, GroupMembership, Location, Match, MatchOutcome,
+    MatchParticipant, MatchPrediction, Stage,
+};
+use web::admin::scores::update_user_scores;
+
+use diesel::{self, prelude::*};
+use failure;
+use std::collections::{HashMap, HashSet};
+use std::{error::Error as StdError, fmt};
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ImportOptions {
+    /// Only report what would change, the transaction is rolled back
+    pub dry_run: bool,
+    /// Also remove matches, groups and countries that predictions or outcomes refer to, together
+    /// with those predictions and outcomes
+    pub force: bool,
+}
+
+/// Rows that are no longer in the tournament, but can't be removed without `force`
+#[derive(Debug)]
+pub struct ReferencedRows {
+    pub rows: Vec<String>,
+}
+
+impl fmt::Display for ReferencedRows {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Refusing to remove rows that are still referenced, use --force to remove:")?;
+        for row in &self.rows {
+            write!(f, "\n  - {}", row)?;
+        }
+        Ok(())
+    }
+}
+
+impl StdError for ReferencedRows {
+    fn description(&self) -> &str {
+        "Refusing to remove rows that are still referenced"
+    }
+}
+
+/// Rolls back the transaction of a dry run
+#[derive(Debug)]
+struct DryRun;
+
+impl fmt::Display for DryRun {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Dry run")
+    }
+}
+
+impl StdError for DryRun {
+    fn description(&self) -> &str {
+        "Dry run"
+    }
+}
+
+/// "field old -> new" for every field that differs, empty when nothing changed
+fn changed_fields(fields: &[(&str, String, String)]) -> String {
+    fields
+        .iter()
+        .filter(|(_, old, new)| old != new)
+        .map(|(field, old, new)| format!("{} {} -> {}", field, old, new))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn describe_participant(
+    group: Option<&str>,
+    drawn_place: Option<i32>,
+    previous_match: Option<i32>,
+    result: Option<&str>,
+) -> String {
+    match (group, drawn_place, previous_match, result) {
+        (Some(group), Some(place), _, _) => format!("group {} place {}", group, place),
+        (Some(group), _, _, Some(result)) => format!("{} of group {}", result, group),
+        (_, _, Some(previous_match), Some(result)) => {
+            format!("{} of match {}", result, previous_match)
+        }
+        _ => "unknown participant".to_string(),
+    }
+}
+
+/// The rows in the database whose natural key is not in the tournament
+#[derive(Default)]
+struct StaleRows {
+    matches: Vec<Match>,
+    groups: Vec<Group>,
+    countries: Vec<Country>,
+    locations: Vec<i32>,
+    stages: Vec<Stage>,
+}
+
+struct Importer<'a> {
+    tournament: &'a Tournament,
+    conn: &'a PgConnection,
+    changes: &'a mut Vec<String>,
+    tournament_id: i32,
+}
+
+/// The id of the tournament with the name of the definition, which is created when it is new
+fn tournament_row(
+    conn: &PgConnection,
+    definition: &Tournament,
+    changes: &mut Vec<String>,
+) -> QueryResult<i32> {
+    use schema::tournaments::dsl::*;
+
+    let existing = tournaments
+        .filter(name.eq(&definition.name))
+        .first::<models::Tournament>(conn)
+        .optional()?;
+
+    match existing {
+        Some(tournament) => {
+            if tournament.is_archived != definition.archived {
+                diesel::update(tournaments.find(tournament.tournament_id))
+                    .set(is_archived.eq(definition.archived))
+                    .execute(conn)?;
+                changes.push(format!(
+                    "~ tournament {}: archived {} -> {}",
+                    definition.name, tournament.is_archived, definition.archived
+                ));
+            }
+            Ok(tournament.tournament_id)
+        }
+        None => {
+            let id = diesel::insert_into(tournaments)
+                .values((name.eq(&definition.name), is_archived.eq(definition.archived)))
+                .returning(tournament_id)
+                .get_result::<i32>(conn)?;
+            changes.push(format!("+ tournament {}", definition.name));
+            Ok(id)
+        }
+    }
+}
+
+impl Tournament {
+    /// Validate the tournament and make the database match it, in one transaction. Returns what
+    /// changed (or would change, for a dry run).
+    pub fn import(
+        &self,
+        conn: &PgConnection,
+        options: ImportOptions,
+    ) -> Result<Vec<String>, failure::Error> {
+        let problems = self.problems();
+        if !problems.is_empty() {
+            Err(InvalidTournament { problems })?
+        }
+
+        let mut changes = Vec::new();
+        let result = conn.transaction::<_, failure::Error, _>(|| {
+            let tournament_id = tournament_row(conn, self, &mut changes)?;
+            let mut importer = Importer {
+                tournament: self,
+                conn,
+                changes: &mut changes,
+                tournament_id,
+            };
+            importer.run(options.force)?;
+
+            if options.dry_run {
+                Err(DryRun)?
+            }
+            Ok(())
+        });
+
+        match result {
+            Ok(()) => Ok(changes),
+            Err(ref error) if error.downcast_ref::<DryRun>().is_some() => Ok(changes),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+impl<'a> Importer<'a> {
+    fn run(&mut self, force: bool) -> Result<(), failure::Error> {
+        let stale = self.stale_rows()?;
+        let referenced = self.referenced_rows(&stale)?;
+        if !referenced.is_empty() && !force {
+            Err(ReferencedRows { rows: referenced })?
+        }
+
+        let country_ids = self.countries()?;
+        let group_ids = self.groups(&country_ids)?;
+        let location_ids = self.locations()?;
+        let stage_ids = self.stages()?;
+        self.matches(&country_ids, &group_ids, &location_ids, &stage_ids)?;
+
+        self.remove_matches(&stale.matches)?;
+        self.remove_locations(&stale.locations)?;
+        self.remove_stages(&stale.stages)?;
+        self.remove_groups(&stale.groups)?;
+        self.remove_countries(&stale.countries)?;
+
+        // The points of removed matches and groups no longer count
+        if !stale.matches.is_empty() || !stale.groups.is_empty() {
+            update_user_scores(self.conn, self.tournament_id)?;
+        }
+
+        Ok(())
+    }
+
+    fn stale_rows(&self) -> QueryResult<StaleRows> {
+        use schema::{countries, group_memberships, groups, locations, match_participants};
+        use schema::{matches, stages};
+
+        let tournament = self.tournament;
+
+        // Countries and stadiums that other tournaments still need
+        let countries_elsewhere = group_memberships::table
+            .inner_join(groups::table)
+            .filter(groups::tournament_id.ne(self.tournament_id))
+            .select(group_memberships::country_id)
+            .load::<i32>(self.conn)?
+            .into_iter()
+            .chain(
+                match_participants::table
+                    .inner_join(stages::table)
+                    .filter(stages::tournament_id.ne(self.tournament_id))
+                    .select(match_participants::country_id)
+                    .load::<Option<i32>>(self.conn)?
+                    .into_iter()
+                    .filter_map(|id| id),
+            )
+            .collect::<HashSet<_>>();
+        let locations_elsewhere = matches::table
+            .filter(matches::tournament_id.ne(self.tournament_id))
+            .select(matches::location_id)
+            .load::<i32>(self.conn)?
+            .into_iter()
+            .collect::<HashSet<_>>();
+
+        Ok(StaleRows {
+            matches: matches::table
+                .filter(matches::tournament_id.eq(self.tournament_id))
+                .order(matches::match_number)
+                .load::<Match>(self.conn)?
+                .into_iter()
+                .filter(|game| {
+                    !tournament
+                        .matches
+                        .iter()
+                        .any(|definition| definition.id == game.match_number)
+                })
+                .collect(),
+            groups: groups::table
+                .filter(groups::tournament_id.eq(self.tournament_id))
+                .order(groups::name)
+                .load::<Group>(self.conn)?
+                .into_iter()
+                .filter(|group| !tournament.groups.iter().any(|g| g.name == group.name))
+                .collect(),
+            countries: countries::table
+                .order(countries::name)
+                .load::<Country>(self.conn)?
+                .into_iter()
+                .filter(|country| !countries_elsewhere.contains(&country.country_id))
+                .filter(|country| !tournament.countries.iter().any(|c| c.name == country.name))
+                .collect(),
+            locations: locations::table
+                .load::<Location>(self.conn)?
+                .into_iter()
+                .filter(|location| !locations_elsewhere.contains(&location.location_id))
+                .filter(|location| {
+                    !tournament
+                        .locations
+                        .iter()
+                        .any(|l| l.stadium == location.stadium)
+                })
+                .map(|location| location.location_id)
+                .collect(),
+            stages: stages::table
+                .filter(stages::tournament_id.eq(self.tournament_id))
+                .order(stages::stage_number)
+                .load::<Stage>(self.conn)?
+                .into_iter()
+                .filter(|stage| {
+                    !tournament
+                        .stages
+                        .iter()
+                        .any(|definition| definition.id == stage.stage_number)
+                })
+                .collect(),
+        })
+    }
+
+    /// Descriptions of the stale rows that users' predictions or the outcomes refer to
+    fn referenced_rows(&self, stale: &StaleRows) -> QueryResult<Vec<String>> {
+        use diesel::dsl::count_star;
+        use schema::{favourites, group_predictions, match_outcomes, match_predictions};
+
+        let mut referenced = Vec::new();
+
+        for game in &stale.matches {
+            let predictions = match_predictions::table
+                .filter(match_predictions::match_id.eq(game.match_id))
+                .select(count_star())
+                .first::<i64>(self.conn)?;
+            let outcomes = match_outcomes::table
+                .filter(match_outcomes::match_id.eq(game.match_id))
+                .select(count_star())
+                .first::<i64>(self.conn)?;
+            if predictions > 0 || outcomes > 0 {
+                referenced.push(format!(
+                    "match {} ({} predictions{})",
+                    game.match_number,
+                    predictions,
+                    if outcomes > 0 { ", outcome" } else { "" }
+                ));
+            }
+        }
+
+        for group in &stale.groups {
+            let predictions = group_predictions::table
+                .filter(group_predictions::group_id.eq(group.group_id))
+                .select(count_star())
+                .first::<i64>(self.conn)?;
+            if predictions > 0 {
+                referenced.push(format!(
+                    "group {} ({} predictions)",
+                    group.name, predictions
+                ));
+            }
+        }
+
+        for country in &stale.countries {
+            let favourites = favourites::table
+                .filter(favourites::country_id.eq(country.country_id))
+                .select(count_star())
+                .first::<i64>(self.conn)?;
+            let predictions = group_predictions::table
+                .filter(
+                    group_predictions::winner_id
+                        .eq(country.country_id)
+                        .or(group_predictions::runnerup_id.eq(country.country_id)),
+                )
+                .select(count_star())
+                .first::<i64>(self.conn)?;
+            if favourites > 0 || predictions > 0 {
+                referenced.push(format!(
+                    "country {} ({} favourites, {} group predictions)",
+                    country.name, favourites, predictions
+                ));
+            }
+        }
+
+        Ok(referenced)
+    }
+
+    fn countries(&mut self) -> QueryResult<HashMap<String, i32>> {
+        use schema::countries::dsl::*;
+
+        let existing = countries.load::<Country>(self.conn)?;
+        let mut ids = HashMap::new();
+
+        for definition in &self.tournament.countries {
+            let id = match existing.iter().find(|country| country.name == definition.name) {
+                Some(country) => {
+                    let changed = changed_fields(&[
+                        ("flag", country.flag.clone(), definition.flag.clone()),
+                        (
+                            "seeding_pot",
+                            country.seeding_pot.clone(),
+                            definition.seeding_pot.clone(),
+                        ),
+                    ]);
+                    if !changed.is_empty() {
+                        diesel::update(countries.find(country.country_id))
+                            .set((
+                                flag.eq(&definition.flag),
+                                seeding_pot.eq(&definition.seeding_pot),
+                            ))
+                            .execute(self.conn)?;
+                        self.changes
+                            .push(format!("~ country {}: {}", definition.name, changed));
+                    }
+                    country.country_id
+                }
+                None => {
+                    let id = diesel::insert_into(countries)
+                        .values((
+                            name.eq(&definition.name),
+                            flag.eq(&definition.flag),
+                            seeding_pot.eq(&definition.seeding_pot),
+                        ))
+                        .returning(country_id)
+                        .get_result::<i32>(self.conn)?;
+                    self.changes.push(format!("+ country {}", definition.name));
+                    id
+                }
+            };
+            ids.insert(definition.name.clone(), id);
+        }
+
+        Ok(ids)
+    }
+
+    fn groups(&mut self, country_ids: &HashMap<String, i32>) -> QueryResult<HashMap<String, i32>> {
+        use schema::{group_memberships, groups};
+
+        let existing = groups::table
+            .filter(groups::tournament_id.eq(self.tournament_id))
+            .load::<Group>(self.conn)?;
+        let existing_ids = existing.iter().map(|group| group.group_id).collect::<Vec<_>>();
+        let memberships = group_memberships::table
+            .filter(group_memberships::group_id.eq_any(&existing_ids))
+            .select((
+                group_memberships::country_id,
+                group_memberships::group_id,
+                group_memberships::drawn_place,
+                group_memberships::current_position,
+                group_memberships::qualified_for_knockout,
+            ))
+            .load::<GroupMembership>(self.conn)?;
+        let mut ids = HashMap::new();
+
+        for definition in &self.tournament.groups {
+            let id = match existing.iter().find(|group| group.name == definition.name) {
+                Some(group) => group.group_id,
+                None => {
+                    let id = diesel::insert_into(groups::table)
+                        .values((
+                            groups::tournament_id.eq(self.tournament_id),
+                            groups::name.eq(&definition.name),
+                        ))
+                        .returning(groups::group_id)
+                        .get_result::<i32>(self.conn)?;
+                    self.changes.push(format!("+ group {}", definition.name));
+                    id
+                }
+            };
+
+            for (place, member) in (1i16..).zip(&definition.members) {
+                let member_id = country_ids[member];
+                let qualified = definition.is_qualified(member);
+                let current = memberships.iter().find(|membership| {
+                    membership.group_id == id && membership.drawn_place == place
+                });
+                let place_filter = group_memberships::table
+                    .filter(group_memberships::group_id.eq(id))
+                    .filter(group_memberships::drawn_place.eq(place));
+
+                match current {
+                    Some(membership) if membership.country_id == member_id => {
+                        if let Some(qualified) = qualified {
+                            if membership.qualified_for_knockout != qualified {
+                                diesel::update(place_filter)
+                                    .set(group_memberships::qualified_for_knockout.eq(qualified))
+                                    .execute(self.conn)?;
+                                self.changes.push(format!(
+                                    "~ group {}: {} {}",
+                                    definition.name,
+                                    member,
+                                    if qualified {
+                                        "qualified for the knock-out round"
+                                    } else {
+                                        "no longer qualified for the knock-out round"
+                                    }
+                                ));
+                            }
+                        }
+                    }
+                    Some(_) => {
+                        // The qualification belonged to the country that used to be here
+                        diesel::update(place_filter)
+                            .set((
+                                group_memberships::country_id.eq(member_id),
+                                group_memberships::qualified_for_knockout
+                                    .eq(qualified.unwrap_or(false)),
+                            ))
+                            .execute(self.conn)?;
+                        self.changes.push(format!(
+                            "~ group {}: place {} is now {}",
+                            definition.name, place, member
+                        ));
+                    }
+                    None => {
+                        diesel::insert_into(group_memberships::table)
+                            .values((
+                                group_memberships::group_id.eq(id),
+                                group_memberships::country_id.eq(member_id),
+                                group_memberships::drawn_place.eq(place),
+                                group_memberships::current_position.eq(place),
+                                group_memberships::qualified_for_knockout
+                                    .eq(qualified.unwrap_or(false)),
+                            ))
+                            .execute(self.conn)?;
+                        self.changes.push(format!(
+                            "+ group {}: {} in place {}",
+                            definition.name, member, place
+                        ));
+                    }
+                }
+            }
+
+            let removed_places = diesel::delete(
+                group_memberships::table
+                    .filter(group_memberships::group_id.eq(id))
+                    .filter(group_memberships::drawn_place.gt(definition.members.len() as i16)),
+            ).execute(self.conn)?;
+            if removed_places > 0 {
+                self.changes.push(format!(
+                    "- group {}: {} places",
+                    definition.name, removed_places
+                ));
+            }
+
+            ids.insert(definition.name.clone(), id);
+        }
+
+        Ok(ids)
+    }
+
+    fn locations(&mut self) -> QueryResult<HashMap<String, i32>> {
+        use schema::locations::dsl::*;
+
+        let existing = locations.load::<Location>(self.conn)?;
+        let mut ids = HashMap::new();
+
+        for definition in &self.tournament.locations {
+            let id = match existing
+                .iter()
+                .find(|location| location.stadium == definition.stadium)
+            {
+                Some(location) => {
+                    if location.city != definition.city {
+                        diesel::update(locations.find(location.location_id))
+                            .set(city.eq(&definition.city))
+                            .execute(self.conn)?;
+                        self.changes.push(format!(
+                            "~ stadium {}: city {} -> {}",
+                            definition.stadium, location.city, definition.city
+                        ));
+                    }
+                    location.location_id
+                }
+                None => {
+                    let id = diesel::insert_into(locations)
+                        .values((city.eq(&definition.city), stadium.eq(&definition.stadium)))
+                        .returning(location_id)
+                        .get_result::<i32>(self.conn)?;
+                    self.changes.push(format!("+ stadium {}", definition.stadium));
+                    id
+                }
+            };
+            ids.insert(definition.stadium.clone(), id);
+        }
+
+        Ok(ids)
+    }
+
+    /// Returns the id of every stage by its number
+    fn stages(&mut self) -> QueryResult<HashMap<i32, i32>> {
+        use schema::stages::dsl::*;
+
+        let existing = stages
+            .filter(tournament_id.eq(self.tournament_id))
+            .load::<Stage>(self.conn)?;
+        let existing_numbers = existing
+            .iter()
+            .map(|stage| (stage.stage_id, stage.stage_number))
+            .collect::<HashMap<_, _>>();
+        let mut ids = HashMap::new();
+
+        for definition in &self.tournament.stages {
+            // Parents are listed before their children, so their id is known
+            let parent = definition.parent.map(|number| ids[&number]);
+            let values = (
+                parent_stage_id.eq(parent),
+                stage_type.eq(definition.stage_type),
+                description.eq(&definition.description),
+                phase.eq(definition.phase),
+                is_final.eq(definition.is_final),
+            );
+
+            let id = match existing
+                .iter()
+                .find(|stage| stage.stage_number == definition.id)
+            {
+                Some(stage) => {
+                    let changed = changed_fields(&[
+                        (
+                            "parent",
+                            format!(
+                                "{:?}",
+                                stage.parent_stage_id.map(|id| existing_numbers[&id])
+                            ),
+                            format!("{:?}", definition.parent),
+                        ),
+                        (
+                            "type",
+                            format!("{:?}", stage.stage_type),
+                            format!("{:?}", definition.stage_type),
+                        ),
+                        (
+                            "description",
+                            stage.description.clone(),
+                            definition.description.clone(),
+                        ),
+                        ("phase", stage.phase.to_string(), definition.phase.to_string()),
+                        (
+                            "is_final",
+                            stage.is_final.to_string(),
+                            definition.is_final.to_string(),
+                        ),
+                    ]);
+                    if !changed.is_empty() {
+                        diesel::update(stages.find(stage.stage_id))
+                            .set(values)
+                            .execute(self.conn)?;
+                        self.changes
+                            .push(format!("~ stage {}: {}", definition.id, changed));
+                    }
+                    stage.stage_id
+                }
+                None => {
+                    let id = diesel::insert_into(stages)
+                        .values((
+                            tournament_id.eq(self.tournament_id),
+                            stage_number.eq(definition.id),
+                            values,
+                        ))
+                        .returning(stage_id)
+                        .get_result::<i32>(self.conn)?;
+                    self.changes.push(format!("+ stage {}", definition.id));
+                    id
+                }
+            };
+            ids.insert(definition.id, id);
+        }
+
+        Ok(ids)
+    }
+
+    /// The participant of a match in the stage, reusing an existing one when it is the same, so a
+    /// country plays all its group matches as one participant
+    fn participant(
+        &self,
+        stage: i32,
+        definition: &ParticipantDefinition,
+        participants: &mut Vec<MatchParticipant>,
+        country_ids: &HashMap<String, i32>,
+        group_ids: &HashMap<String, i32>,
+        match_ids: &HashMap<i32, i32>,
+    ) -> QueryResult<i32> {
+        use schema::match_participants::dsl::*;
+
+        let group = definition.group.as_ref().map(|group| group_ids[group]);
+        // Previous matches are listed before the matches they lead to, so their id is known
+        let previous_match = definition.previous_match.map(|number| match_ids[&number]);
+        let country = self
+            .tournament
+            .country_of(definition)
+            .map(|country| country_ids[country]);
+
+        let existing = participants.iter_mut().find(|participant| {
+            participant.stage_id == stage
+                && participant.group_id == group
+                && participant.group_drawn_place == definition.drawn_place
+                && participant.previous_match_id == previous_match
+                && participant.result == definition.result
+        });
+        if let Some(participant) = existing {
+            // Countries are filled in as the tournament progresses, only a country in the
+            // definition overrides what is already known
+            if country.is_some() && participant.country_id != country {
+                diesel::update(match_participants.find(participant.match_participant_id))
+                    .set(country_id.eq(country))
+                    .execute(self.conn)?;
+                participant.country_id = country;
+            }
+            return Ok(participant.match_participant_id);
+        }
+
+        let inserted = diesel::insert_into(match_participants)
+            .values((
+                stage_id.eq(stage),
+                country_id.eq(country),
+                group_id.eq(group),
+                group_drawn_place.eq(definition.drawn_place),
+                previous_match_id.eq(previous_match),
+                result.eq(definition.result.clone()),
+            ))
+            .get_result::<MatchParticipant>(self.conn)?;
+        let id = inserted.match_participant_id;
+        participants.push(inserted);
+
+        Ok(id)
+    }
+
+    fn matches(
+        &mut self,
+        country_ids: &HashMap<String, i32>,
+        group_ids: &HashMap<String, i32>,
+        location_ids: &HashMap<String, i32>,
+        stage_ids: &HashMap<i32, i32>,
+    ) -> QueryResult<()> {
+        use schema::{match_participants, matches};
+
+        let existing = matches::table
+            .filter(matches::tournament_id.eq(self.tournament_id))
+            .load::<Match>(self.conn)?;
+        let tournament_stages = stage_ids.values().cloned().collect::<Vec<_>>();
+        let mut participants = match_participants::table
+            .filter(match_participants::stage_id.eq_any(&tournament_stages))
+            .load::<MatchParticipant>(self.conn)?;
+        let mut match_ids = existing
+            .iter()
+            .map(|game| (game.match_number, game.match_id))
+            .collect::<HashMap<_, _>>();
+        let stage_numbers = stage_ids
+            .iter()
+            .map(|(&number, &id)| (id, number))
+            .collect::<HashMap<_, _>>();
+        let group_names = group_ids
+            .iter()
+            .map(|(group_name, &id)| (id, group_name.as_str()))
+            .collect::<HashMap<_, _>>();
+        let describe = |participant: &MatchParticipant, match_ids: &HashMap<i32, i32>| {
+            describe_participant(
+                participant
+                    .group_id
+                    .and_then(|id| group_names.get(&id).cloned()),
+                participant.group_drawn_place,
+                participant.previous_match_id.map(|previous| {
+                    match_ids
+                        .iter()
+                        .find(|&(_, &id)| id == previous)
+                        .map_or(previous, |(&number, _)| number)
+                }),
+                participant.result.as_ref().map(String::as_str),
+            )
+        };
+
+        for definition in &self.tournament.matches {
+            let stage_id = stage_ids[&definition.stage];
+            let home_id = self.participant(
+                stage_id,
+                &definition.home,
+                &mut participants,
+                country_ids,
+                group_ids,
+                &match_ids,
+            )?;
+            let away_id = self.participant(
+                stage_id,
+                &definition.away,
+                &mut participants,
+                country_ids,
+                group_ids,
+                &match_ids,
+            )?;
+            let location_id = location_ids[&definition.stadium];
+            let values = (
+                matches::stage_id.eq(stage_id),
+                matches::time.eq(definition.time),
+                matches::location_id.eq(location_id),
+                matches::home_participant_id.eq(home_id),
+                matches::away_participant_id.eq(away_id),
+            );
+
+            match existing
+                .iter()
+                .find(|game| game.match_number == definition.id)
+            {
+                Some(game) => {
+                    let participant = |id: i32| {
+                        participants
+                            .iter()
+                            .find(|participant| participant.match_participant_id == id)
+                            .map(|participant| describe(participant, &match_ids))
+                            .unwrap_or_default()
+                    };
+                    let changed = changed_fields(&[
+                        (
+                            "stage",
+                            stage_numbers
+                                .get(&game.stage_id)
+                                .map(ToString::to_string)
+                                .unwrap_or_default(),
+                            definition.stage.to_string(),
+                        ),
+                        ("time", game.time.to_string(), definition.time.to_string()),
+                        (
+                            "location",
+                            game.location_id.to_string(),
+                            location_id.to_string(),
+                        ),
+                        (
+                            "home",
+                            participant(game.home_participant_id),
+                            participant(home_id),
+                        ),
+                        (
+                            "away",
+                            participant(game.away_participant_id),
+                            participant(away_id),
+                        ),
+                    ]);
+                    if !changed.is_empty() {
+                        diesel::update(matches::table.find(game.match_id))
+                            .set(values)
+                            .execute(self.conn)?;
+                        self.changes
+                            .push(format!("~ match {}: {}", definition.id, changed));
+                    }
+                }
+                None => {
+                    let id = diesel::insert_into(matches::table)
+                        .values((
+                            matches::tournament_id.eq(self.tournament_id),
+                            matches::match_number.eq(definition.id),
+                            values,
+                        ))
+                        .returning(matches::match_id)
+                        .get_result::<i32>(self.conn)?;
+                    match_ids.insert(definition.id, id);
+                    self.changes.push(format!("+ match {}", definition.id));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Remove the matches with their predictions and outcome, and the participants that no match
+    /// uses anymore
+    fn remove_matches(&mut self, stale: &[Match]) -> QueryResult<()> {
+        use schema::{live_scores, match_outcomes, match_participants, match_predictions, matches};
+        use schema::{reminder_emails, slack_reminders, user_match_points};
+
+        let ids = stale.iter().map(|game| game.match_id).collect::<Vec<_>>();
+        let ids = ids.as_slice();
+
+        let predictions = match_predictions::table
+            .filter(match_predictions::match_id.eq_any(ids))
+            .load::<MatchPrediction>(self.conn)?;
+        for prediction in &predictions {
+            audit::record(
+                self.conn,
+                &Change {
+                    subject: audit::MATCH_PREDICTION,
+                    actor_id: None,
+                    user_id: Some(prediction.user_id),
+                    match_id: Some(prediction.match_id),
+                    source: "import",
+                    before: Some(prediction),
+                    after: None,
+                },
+            )?;
+        }
+        diesel::delete(match_predictions::table.filter(match_predictions::match_id.eq_any(ids)))
+            .execute(self.conn)?;
+
+        let outcomes = match_outcomes::table
+            .filter(match_outcomes::match_id.eq_any(ids))
+            .select((
+                match_outcomes::match_id,
+                match_outcomes::home_score,
+                match_outcomes::away_score,
+                match_outcomes::time_of_first_goal,
+                match_outcomes::home_penalties,
+                match_outcomes::away_penalties,
+                match_outcomes::duration,
+            ))
+            .load::<MatchOutcome>(self.conn)?;
+        for outcome in &outcomes {
+            audit::record(
+                self.conn,
+                &Change {
+                    subject: audit::MATCH_OUTCOME,
+                    actor_id: None,
+                    user_id: None,
+                    match_id: Some(outcome.match_id),
+                    source: "import",
+                    before: Some(outcome),
+                    after: None,
+                },
+            )?;
+        }
+        diesel::delete(match_outcomes::table.filter(match_outcomes::match_id.eq_any(ids)))
+            .execute(self.conn)?;
+        diesel::delete(user_match_points::table.filter(user_match_points::match_id.eq_any(ids)))
+            .execute(self.conn)?;
+        diesel::delete(live_scores::table.filter(live_scores::match_id.eq_any(ids)))
+            .execute(self.conn)?;
+        diesel::delete(slack_reminders::table.filter(slack_reminders::match_id.eq_any(ids)))
+            .execute(self.conn)?;
+        diesel::delete(reminder_emails::table.filter(reminder_emails::match_id.eq_any(ids)))
+            .execute(self.conn)?;
+
+        // Participants of removed matches can refer to other removed matches, so they are
+        // detached before the matches are removed
+        let used = matches::table
+            .filter(matches::match_id.ne_all(ids))
+            .select((matches::home_participant_id, matches::away_participant_id))
+            .load::<(i32, i32)>(self.conn)?
+            .into_iter()
+            .flat_map(|(home, away)| vec![home, away])
+            .collect::<HashSet<_>>();
+        let unused = match_participants::table
+            .select(match_participants::match_participant_id)
+            .load::<i32>(self.conn)?
+            .into_iter()
+            .filter(|id| !used.contains(id))
+            .collect::<Vec<_>>();
+        diesel::update(
+            match_participants::table
+                .filter(match_participants::match_participant_id.eq_any(&unused)),
+        ).set(match_participants::previous_match_id.eq(None::<i32>))
+            .execute(self.conn)?;
+
+        diesel::delete(matches::table.filter(matches::match_id.eq_any(ids)))
+            .execute(self.conn)?;
+        diesel::delete(
+            match_participants::table
+                .filter(match_participants::match_participant_id.eq_any(&unused)),
+        ).execute(self.conn)?;
+
+        for game in stale {
+            self.changes.push(format!(
+                "- match {} ({} predictions{})",
+                game.match_number,
+                predictions
+                    .iter()
+                    .filter(|prediction| prediction.match_id == game.match_id)
+                    .count(),
+                if outcomes.iter().any(|outcome| outcome.match_id == game.match_id) {
+                    ", outcome"
+                } else {
+                    ""
+                }
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn remove_locations(&mut self, ids: &[i32]) -> QueryResult<()> {
+        use schema::locations;
+
+        let removed = diesel::delete(locations::table.filter(locations::location_id.eq_any(ids)))
+            .returning(locations::stadium)
+            .get_results::<String>(self.conn)?;
+        for stadium in removed {
+            self.changes.push(format!("- stadium {}", stadium));
+        }
+
+        Ok(())
+    }
+
+    fn remove_stages(&mut self, stale: &[Stage]) -> QueryResult<()> {
+        use schema::stages::dsl::*;
+
+        let ids = stale.iter().map(|stage| stage.stage_id).collect::<Vec<_>>();
+
+        diesel::update(stages.filter(stage_id.eq_any(&ids)))
+            .set(parent_stage_id.eq(None::<i32>))
+            .execute(self.conn)?;
+        diesel::delete(stages.filter(stage_id.eq_any(&ids))).execute(self.conn)?;
+        for stage in stale {
+            self.changes.push(format!("- stage {}", stage.stage_number));
+        }
+
+        Ok(())
+    }
+
+    fn remove_groups(&mut self, stale: &[Group]) -> QueryResult<()> {
+        use schema::{group_memberships, group_predictions, groups, user_group_points};
+
+        let ids = stale.iter().map(|group| group.group_id).collect::<Vec<_>>();
+
+        diesel::delete(group_predictions::table.filter(group_predictions::group_id.eq_any(&ids)))
+            .execute(self.conn)?;
+        diesel::delete(user_group_points::table.filter(user_group_points::group_id.eq_any(&ids)))
+            .execute(self.conn)?;
+        diesel::delete(group_memberships::table.filter(group_memberships::group_id.eq_any(&ids)))
+            .execute(self.conn)?;
+        diesel::delete(groups::table.filter(groups::group_id.eq_any(&ids))).execute(self.conn)?;
+        for group in stale {
+            self.changes.push(format!("- group {}", group.name));
+        }
+
+        Ok(())
+    }
+
+    fn remove_countries(&mut self, stale: &[Country]) -> QueryResult<()> {
+        use schema::{countries, favourites, group_predictions, match_participants};
+
+        let ids = stale
+            .iter()
+            .map(|country| country.country_id)
+            .collect::<Vec<_>>();
+
+        // A favourite that is no longer in the tournament is no pick at all
+        let picked = favourites::table
+            .filter(favourites::country_id.eq_any(&ids))
+            .load::<Favourite>(self.conn)?;
+        let cleared = diesel::update(favourites::table.filter(favourites::country_id.eq_any(&ids)))
+            .set(favourites::country_id.eq(None::<i32>))
+            .get_results::<Favourite>(self.conn)?;
+        for favourite in &cleared {
+            audit::record(
+                self.conn,
+                &Change {
+                    subject: audit::FAVOURITE,
+                    actor_id: None,
+                    user_id: Some(favourite.user_id),
+                    match_id: None,
+                    source: "import",
+                    before: picked.iter().find(|old| {
+                        old.user_id == favourite.user_id
+                            && old.tournament_id == favourite.tournament_id
+                            && old.choice == favourite.choice
+                    }),
+                    after: Some(favourite),
+                },
+            )?;
+        }
+
+        diesel::delete(
+            group_predictions::table.filter(
+                group_predictions::winner_id
+                    .eq_any(&ids)
+                    .or(group_predictions::runnerup_id.eq_any(&ids)),
+            ),
+        ).execute(self.conn)?;
+        diesel::update(
+            match_participants::table.filter(match_participants::country_id.eq_any(&ids)),
+        ).set(match_participants::country_id.eq(None::<i32>))
+            .execute(self.conn)?;
+        diesel::delete(countries::table.filter(countries::country_id.eq_any(&ids)))
+            .execute(self.conn)?;
+        for country in stale {
+            self.changes.push(format!("- country {}", country.name));
+        }
+
+        Ok(())
+    }
+}
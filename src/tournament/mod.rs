@@ -0,0 +1,509 @@
+//! A whole tournament (countries, groups, stadiums, stages and the match schedule) described in a
+//! single JSON or YAML file, see `tournament.schema.json` for the format. The file is validated
+//! as a whole before anything is written, and it is imported in one transaction.
+
+mod import;
+
+pub use self::import::{ImportOptions, ReferencedRows};
+
+use models::{self, Country, Group, GroupMembership, Location, Match, MatchParticipant, Stage};
+use schema::StageType;
+
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use failure;
+use serde_json;
+use serde_yaml;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::path::Path;
+use std::{error::Error as StdError, fmt};
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Tournament {
+    /// Tournaments are matched on their name, e.g. "World Cup 2018"
+    pub name: String,
+    /// Archived tournaments are only shown to users who switch to them
+    #[serde(default)]
+    pub archived: bool,
+    pub countries: Vec<CountryDefinition>,
+    pub groups: Vec<GroupDefinition>,
+    pub locations: Vec<LocationDefinition>,
+    pub stages: Vec<StageDefinition>,
+    pub matches: Vec<MatchDefinition>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CountryDefinition {
+    pub name: String,
+    pub flag: String,
+    pub seeding_pot: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct GroupDefinition {
+    pub name: String,
+    /// Country names, in the order in which they were drawn into the group
+    pub members: Vec<String>,
+    /// The members that go through to the knock-out round, left out while that isn't known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub qualified: Option<Vec<String>>,
+}
+
+impl GroupDefinition {
+    /// `None` when the definition doesn't say
+    pub fn is_qualified(&self, member: &str) -> Option<bool> {
+        self.qualified
+            .as_ref()
+            .map(|qualified| qualified.iter().any(|country| country == member))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct LocationDefinition {
+    pub city: String,
+    pub stadium: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct StageDefinition {
+    /// The number of the stage within the tournament
+    pub id: i32,
+    #[serde(default)]
+    pub parent: Option<i32>,
+    #[serde(rename = "type")]
+    pub stage_type: StageType,
+    pub description: String,
+    /// The favourites phase during which the matches of this stage are played
+    pub phase: i16,
+    #[serde(default)]
+    pub is_final: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct MatchDefinition {
+    /// The number of the match in the schedule of the tournament
+    pub id: i32,
+    pub stage: i32,
+    pub time: DateTime<Utc>,
+    pub stadium: String,
+    pub home: ParticipantDefinition,
+    pub away: ParticipantDefinition,
+}
+
+/// Who plays a match: a country drawn into a group (`group` and `drawn_place`), the winner or
+/// runner-up of a group (`group` and `result`), or the winner or loser of a previous match
+/// (`previous_match` and `result`). The `country` can be given up front when it is known.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, Hash)]
+#[serde(deny_unknown_fields)]
+pub struct ParticipantDefinition {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub drawn_place: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_match: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct InvalidTournament {
+    pub problems: Vec<String>,
+}
+
+impl fmt::Display for InvalidTournament {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The tournament is not valid:")?;
+        for problem in &self.problems {
+            write!(f, "\n  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl StdError for InvalidTournament {
+    fn description(&self) -> &str {
+        "The tournament is not valid"
+    }
+}
+
+fn is_yaml(path: &Path) -> bool {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("yaml") | Some("yml") => true,
+        _ => false,
+    }
+}
+
+impl Tournament {
+    /// Read a tournament file, YAML when the extension is `.yaml` or `.yml` and JSON otherwise
+    pub fn from_path(path: &Path) -> Result<Tournament, failure::Error> {
+        let file = File::open(path)?;
+
+        if is_yaml(path) {
+            Ok(serde_yaml::from_reader(file)?)
+        } else {
+            Ok(serde_json::from_reader(file)?)
+        }
+    }
+
+    pub fn to_path(&self, path: &Path) -> Result<(), failure::Error> {
+        let file = File::create(path)?;
+
+        if is_yaml(path) {
+            serde_yaml::to_writer(file, self)?;
+        } else {
+            serde_json::to_writer_pretty(file, self)?;
+        }
+
+        Ok(())
+    }
+
+    /// Everything that is wrong with the tournament, empty when it can be loaded
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if self.name.trim().is_empty() {
+            problems.push("The tournament needs a name".to_string());
+        }
+
+        let mut countries = HashSet::new();
+        for country in &self.countries {
+            if !countries.insert(country.name.as_str()) {
+                problems.push(format!("Country {} is listed twice", country.name));
+            }
+            if country.seeding_pot.chars().count() != 1 {
+                problems.push(format!(
+                    "The seeding pot of {} must be a single character",
+                    country.name
+                ));
+            }
+        }
+
+        let mut groups = HashSet::new();
+        let mut group_members = HashSet::new();
+        for group in &self.groups {
+            if !groups.insert(group.name.as_str()) {
+                problems.push(format!("Group {} is listed twice", group.name));
+            }
+            for member in &group.members {
+                if !countries.contains(member.as_str()) {
+                    problems.push(format!(
+                        "Group {} contains unknown country {}",
+                        group.name, member
+                    ));
+                }
+                if !group_members.insert(member.as_str()) {
+                    problems.push(format!("Country {} is drawn into two groups", member));
+                }
+            }
+            for country in group.qualified.iter().flat_map(|qualified| qualified.iter()) {
+                if !group.members.contains(country) {
+                    problems.push(format!(
+                        "Group {} has qualified country {} that isn't a member",
+                        group.name, country
+                    ));
+                }
+            }
+        }
+
+        let mut stadiums = HashSet::new();
+        for location in &self.locations {
+            if !stadiums.insert(location.stadium.as_str()) {
+                problems.push(format!("Stadium {} is listed twice", location.stadium));
+            }
+        }
+
+        let mut stages = HashSet::new();
+        for stage in &self.stages {
+            if let Some(parent) = stage.parent {
+                if !stages.contains(&parent) {
+                    problems.push(format!(
+                        "Stage {} refers to parent {}, which must be listed before it",
+                        stage.id, parent
+                    ));
+                }
+            }
+            if !stages.insert(stage.id) {
+                problems.push(format!("Stage {} is listed twice", stage.id));
+            }
+        }
+
+        let mut matches = HashSet::new();
+        for game in &self.matches {
+            if !stages.contains(&game.stage) {
+                problems.push(format!(
+                    "Match {} is played in unknown stage {}",
+                    game.id, game.stage
+                ));
+            }
+            if !stadiums.contains(game.stadium.as_str()) {
+                problems.push(format!(
+                    "Match {} is played in unknown stadium {}",
+                    game.id, game.stadium
+                ));
+            }
+            for participant in &[&game.home, &game.away] {
+                problems.extend(
+                    self.participant_problems(participant, &countries, &groups, &matches)
+                        .into_iter()
+                        .map(|problem| format!("Match {}: {}", game.id, problem)),
+                );
+            }
+            if !matches.insert(game.id) {
+                problems.push(format!("Match {} is listed twice", game.id));
+            }
+        }
+
+        problems
+    }
+
+    fn participant_problems(
+        &self,
+        participant: &ParticipantDefinition,
+        countries: &HashSet<&str>,
+        groups: &HashSet<&str>,
+        earlier_matches: &HashSet<i32>,
+    ) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if let Some(ref country) = participant.country {
+            if !countries.contains(country.as_str()) {
+                problems.push(format!("unknown country {}", country));
+            }
+        }
+
+        match (
+            &participant.group,
+            participant.drawn_place,
+            participant.previous_match,
+            participant.result.as_ref().map(String::as_str),
+        ) {
+            (Some(group), Some(drawn_place), None, None) => {
+                if !groups.contains(group.as_str()) {
+                    problems.push(format!("unknown group {}", group));
+                } else if !self.groups.iter().any(|definition| {
+                    definition.name == *group
+                        && drawn_place >= 1
+                        && drawn_place as usize <= definition.members.len()
+                }) {
+                    problems.push(format!("group {} has no place {}", group, drawn_place));
+                }
+            }
+            (Some(group), None, None, Some("winner"))
+            | (Some(group), None, None, Some("runnerup")) => {
+                if !groups.contains(group.as_str()) {
+                    problems.push(format!("unknown group {}", group));
+                }
+            }
+            (None, None, Some(previous_match), Some("winner"))
+            | (None, None, Some(previous_match), Some("loser")) => {
+                if !earlier_matches.contains(&previous_match) {
+                    problems.push(format!(
+                        "previous match {} must be listed before this match",
+                        previous_match
+                    ));
+                }
+            }
+            _ => problems.push(
+                "a participant needs a group with a drawn_place, a group with result winner or \
+                 runnerup, or a previous_match with result winner or loser"
+                    .to_string(),
+            ),
+        }
+
+        problems
+    }
+
+    /// The country of the participant, for group matches it follows from the draw
+    pub(crate) fn country_of<'a>(
+        &'a self,
+        participant: &'a ParticipantDefinition,
+    ) -> Option<&'a String> {
+        participant.country.as_ref().or_else(|| {
+            match (&participant.group, participant.drawn_place) {
+                (Some(group), Some(drawn_place)) if drawn_place >= 1 => self
+                    .groups
+                    .iter()
+                    .find(|definition| definition.name == *group)
+                    .and_then(|definition| definition.members.get(drawn_place as usize - 1)),
+                _ => None,
+            }
+        })
+    }
+
+    /// A tournament as it is currently stored in the database. Countries and stadiums are shared
+    /// by all tournaments, only those that the tournament uses are included.
+    pub fn export(
+        conn: &PgConnection,
+        for_tournament_id: i32,
+    ) -> Result<Tournament, failure::Error> {
+        use schema::{countries, group_memberships, groups, locations, match_participants};
+        use schema::{matches, stages, tournaments};
+
+        let tournament = tournaments::table
+            .find(for_tournament_id)
+            .first::<models::Tournament>(conn)?;
+        let all_groups = groups::table
+            .filter(groups::tournament_id.eq(for_tournament_id))
+            .order(groups::name)
+            .load::<Group>(conn)?;
+        let group_ids = all_groups.iter().map(|group| group.group_id).collect::<Vec<_>>();
+        let memberships = group_memberships::table
+            .filter(group_memberships::group_id.eq_any(&group_ids))
+            .order((group_memberships::group_id, group_memberships::drawn_place))
+            .select((
+                group_memberships::country_id,
+                group_memberships::group_id,
+                group_memberships::drawn_place,
+                group_memberships::current_position,
+                group_memberships::qualified_for_knockout,
+            ))
+            .load::<GroupMembership>(conn)?;
+        let all_stages = stages::table
+            .filter(stages::tournament_id.eq(for_tournament_id))
+            .order(stages::stage_number)
+            .load::<Stage>(conn)?;
+        let stage_ids = all_stages.iter().map(|stage| stage.stage_id).collect::<Vec<_>>();
+        let all_matches = matches::table
+            .filter(matches::tournament_id.eq(for_tournament_id))
+            .order(matches::match_number)
+            .load::<Match>(conn)?;
+        let participants = match_participants::table
+            .filter(match_participants::stage_id.eq_any(&stage_ids))
+            .load::<MatchParticipant>(conn)?
+            .into_iter()
+            .map(|participant| (participant.match_participant_id, participant))
+            .collect::<HashMap<_, _>>();
+
+        let used_countries = memberships
+            .iter()
+            .map(|membership| membership.country_id)
+            .chain(
+                participants
+                    .values()
+                    .filter_map(|participant| participant.country_id),
+            )
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        let all_countries = countries::table
+            .filter(countries::country_id.eq_any(&used_countries))
+            .order(countries::country_id)
+            .load::<Country>(conn)?;
+        let used_locations = all_matches
+            .iter()
+            .map(|game| game.location_id)
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        let all_locations = locations::table
+            .filter(locations::location_id.eq_any(&used_locations))
+            .order(locations::location_id)
+            .load::<Location>(conn)?;
+
+        let country_names = all_countries
+            .iter()
+            .map(|country| (country.country_id, country.name.clone()))
+            .collect::<HashMap<_, _>>();
+        let group_names = all_groups
+            .iter()
+            .map(|group| (group.group_id, group.name.clone()))
+            .collect::<HashMap<_, _>>();
+        let stadiums = all_locations
+            .iter()
+            .map(|location| (location.location_id, location.stadium.clone()))
+            .collect::<HashMap<_, _>>();
+        let stage_numbers = all_stages
+            .iter()
+            .map(|stage| (stage.stage_id, stage.stage_number))
+            .collect::<HashMap<_, _>>();
+        let match_numbers = all_matches
+            .iter()
+            .map(|game| (game.match_id, game.match_number))
+            .collect::<HashMap<_, _>>();
+
+        let participant = |id: i32| {
+            let participant = &participants[&id];
+
+            ParticipantDefinition {
+                country: participant.country_id.map(|id| country_names[&id].clone()),
+                group: participant.group_id.map(|id| group_names[&id].clone()),
+                drawn_place: participant.group_drawn_place,
+                previous_match: participant.previous_match_id.map(|id| match_numbers[&id]),
+                result: participant.result.clone(),
+            }
+        };
+
+        Ok(Tournament {
+            name: tournament.name,
+            archived: tournament.is_archived,
+            countries: all_countries
+                .iter()
+                .map(|country| CountryDefinition {
+                    name: country.name.clone(),
+                    flag: country.flag.clone(),
+                    seeding_pot: country.seeding_pot.clone(),
+                })
+                .collect(),
+            groups: all_groups
+                .iter()
+                .map(|group| GroupDefinition {
+                    name: group.name.clone(),
+                    members: memberships
+                        .iter()
+                        .filter(|membership| membership.group_id == group.group_id)
+                        .map(|membership| country_names[&membership.country_id].clone())
+                        .collect(),
+                    qualified: Some(
+                        memberships
+                            .iter()
+                            .filter(|membership| {
+                                membership.group_id == group.group_id
+                                    && membership.qualified_for_knockout
+                            })
+                            .map(|membership| country_names[&membership.country_id].clone())
+                            .collect(),
+                    ),
+                })
+                .collect(),
+            locations: all_locations
+                .iter()
+                .map(|location| LocationDefinition {
+                    city: location.city.clone(),
+                    stadium: location.stadium.clone(),
+                })
+                .collect(),
+            stages: all_stages
+                .iter()
+                .map(|stage| StageDefinition {
+                    id: stage.stage_number,
+                    parent: stage.parent_stage_id.map(|id| stage_numbers[&id]),
+                    stage_type: stage.stage_type,
+                    description: stage.description.clone(),
+                    phase: stage.phase,
+                    is_final: stage.is_final,
+                })
+                .collect(),
+            matches: all_matches
+                .iter()
+                .map(|game| MatchDefinition {
+                    id: game.match_number,
+                    stage: stage_numbers[&game.stage_id],
+                    time: game.time,
+                    stadium: stadiums[&game.location_id].clone(),
+                    home: participant(game.home_participant_id),
+                    away: participant(game.away_participant_id),
+                })
+                .collect(),
+        })
+    }
+}
@@ -0,0 +1,137 @@
+use models::{AuditLogEntry, User};
+use templates::{Context, TEMPLATE_SERVICE};
+use web::app_state::DbExecutor;
+
+use actix::prelude::*;
+use actix_web::{AsyncResponder, HttpResponse, Query, Responder, State};
+use diesel::prelude::*;
+use failure;
+use futures::Future;
+use std::collections::HashMap;
+use web::{app_state::AppState, auth::AdminUser};
+
+/// Only the most recent entries are shown, filter on a user or match to see older ones
+const MAX_ENTRIES: i64 = 500;
+
+#[derive(Deserialize, Debug)]
+pub struct AuditLogFilter {
+    user_id: Option<i32>,
+    match_id: Option<i32>,
+}
+
+struct FetchAuditLog {
+    user_id: Option<i32>,
+    match_id: Option<i32>,
+}
+
+impl Message for FetchAuditLog {
+    type Result = Result<(Vec<AuditLogEntry>, Vec<User>), failure::Error>;
+}
+
+impl Handler<FetchAuditLog> for DbExecutor {
+    type Result = Result<(Vec<AuditLogEntry>, Vec<User>), failure::Error>;
+
+    fn handle(&mut self, msg: FetchAuditLog, _ctx: &mut Self::Context) -> Self::Result {
+        use schema::audit_log_entries::dsl::*;
+        use schema::users;
+
+        let mut query = audit_log_entries.into_boxed();
+        if let Some(filter_user_id) = msg.user_id {
+            query = query.filter(user_id.eq(filter_user_id));
+        }
+        if let Some(filter_match_id) = msg.match_id {
+            query = query.filter(match_id.eq(filter_match_id));
+        }
+
+        let entries = query
+            .order((created_at.desc(), audit_log_entry_id.desc()))
+            .limit(MAX_ENTRIES)
+            .load::<AuditLogEntry>(&self.connection)?;
+        let all_users = users::table.load::<User>(&self.connection)?;
+
+        Ok((entries, all_users))
+    }
+}
+
+#[derive(Serialize)]
+struct AuditLogRow<'a> {
+    entry: &'a AuditLogEntry,
+    user: Option<&'a str>,
+    actor: Option<&'a str>,
+    old_value: Option<String>,
+    new_value: Option<String>,
+}
+
+fn render_audit_log(
+    current_user: &User,
+    filter: &AuditLogFilter,
+    entries: &[AuditLogEntry],
+    all_users: &[User],
+) -> HttpResponse {
+    let names = all_users
+        .iter()
+        .map(|user| {
+            (
+                user.user_id,
+                user.display_name.as_ref().unwrap_or(&user.email).as_str(),
+            )
+        })
+        .collect::<HashMap<_, _>>();
+    let name = |id: Option<i32>| id.and_then(|id| names.get(&id).cloned());
+    let rows = entries
+        .iter()
+        .map(|entry| AuditLogRow {
+            entry,
+            user: name(entry.user_id),
+            actor: name(entry.actor_id),
+            old_value: entry.old_value.as_ref().map(|value| value.to_string()),
+            new_value: entry.new_value.as_ref().map(|value| value.to_string()),
+        })
+        .collect::<Vec<_>>();
+
+    let mut context = Context::new();
+    context.add("current_user", current_user);
+    context.add("rows", &rows);
+    context.add("filtered_user", &name(filter.user_id));
+    context.add("filtered_match_id", &filter.match_id);
+    let rendered = TEMPLATE_SERVICE.render("admin/audit_log/index.html", &context);
+
+    match rendered {
+        Ok(body) => HttpResponse::Ok().content_type("text/html").body(body),
+        Err(error) => {
+            println!("{:?}", error);
+            HttpResponse::InternalServerError()
+                .content_type("text/html")
+                .body("Something went wrong")
+        }
+    }
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn index(
+    (auth, filter, state): (AdminUser, Query<AuditLogFilter>, State<AppState>),
+) -> impl Responder {
+    let filter = filter.into_inner();
+
+    state
+        .db
+        .send(FetchAuditLog {
+            user_id: filter.user_id,
+            match_id: filter.match_id,
+        })
+        .and_then(move |result| match result {
+            Ok((entries, all_users)) => Ok(render_audit_log(
+                &auth.current_user,
+                &filter,
+                &entries,
+                &all_users,
+            )),
+            Err(error) => {
+                println!("{:?}", error);
+                Ok(HttpResponse::InternalServerError()
+                    .content_type("text/html")
+                    .body("Something went wrong"))
+            }
+        })
+        .responder()
+}
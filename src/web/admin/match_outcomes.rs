@@ -1,19 +1,31 @@
+use audit::{self, Change};
+use notifications::{tournament_scores, Notification, OutcomeReport};
 use models::{
-    Favourite, Match, MatchOutcome, MatchPrediction, MatchWithAllInfo, MatchWithParticipants, User,
+    Favourite, Match, MatchOutcome, MatchPrediction, MatchWithAllInfo, MatchWithParticipants,
+    Stage, User,
 };
-use scores::user_match_points;
+use scores::{user_match_points, ScoringRules};
 use templates::{Context, TEMPLATE_SERVICE};
+use web::admin::scores::update_user_scores;
 use web::app_state::DbExecutor;
+use web::group_predictions::update_group_prediction_points;
+use web::groups::{resolve_group_participants, update_group_standings};
+use web::tournaments::viewed_tournament;
 
 use actix::prelude::*;
-use actix_web::{AsyncResponder, Either, Form, HttpResponse, Path, Responder, State};
+use actix_web::{AsyncResponder, Form, HttpResponse, Path, Responder, State};
 use chrono::Utc;
 use diesel::{self, prelude::*};
 use failure;
 use futures::Future;
-use web::{app_state::AppState, auth::CurrentUser};
+use std::collections::HashMap;
+use std::num::ParseIntError;
+use web::{app_state::AppState, auth::AdminUser};
 
-struct IndexMatchOutcomes;
+/// The matches that have been played in the tournament the user is looking at
+pub struct IndexMatchOutcomes {
+    pub user_id: i32,
+}
 
 impl Message for IndexMatchOutcomes {
     type Result = Result<Vec<(MatchWithAllInfo, Option<MatchOutcome>)>, failure::Error>;
@@ -22,11 +34,14 @@ impl Message for IndexMatchOutcomes {
 impl Handler<IndexMatchOutcomes> for DbExecutor {
     type Result = Result<Vec<(MatchWithAllInfo, Option<MatchOutcome>)>, failure::Error>;
 
-    fn handle(&mut self, _msg: IndexMatchOutcomes, _ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: IndexMatchOutcomes, _ctx: &mut Self::Context) -> Self::Result {
         use schema::full_match_infos::dsl::*;
         use schema::match_outcomes;
 
+        let tournament = viewed_tournament(&self.connection, msg.user_id)?;
+
         Ok(full_match_infos
+            .filter(tournament_id.eq(tournament.tournament_id))
             .filter(time.le(Utc::now()))
             .left_join(match_outcomes::table.on(match_outcomes::columns::match_id.eq(match_id)))
             .select((
@@ -46,43 +61,34 @@ impl Handler<IndexMatchOutcomes> for DbExecutor {
     }
 }
 
-pub fn index((auth, state): (CurrentUser, State<AppState>)) -> impl Responder {
-    if auth.current_user.user_id == 1 {
-        Either::A(
-            state
-                .db
-                .send(IndexMatchOutcomes)
-                .and_then(move |match_outcomes| match match_outcomes {
-                    Ok(matches) => {
-                        let mut context = Context::new();
-                        context.add("current_user", &auth.current_user);
-                        context.add("matches", &matches);
-                        let rendered =
-                            TEMPLATE_SERVICE.render("admin/matches/index.html", &context);
-
-                        match rendered {
-                            Ok(body) => Ok(HttpResponse::Ok().content_type("text/html").body(body)),
-                            Err(error) => {
-                                println!("{:?}", error);
-                                Ok(HttpResponse::InternalServerError()
-                                    .content_type("text/html")
-                                    .body("Something went wrong"))
-                            }
-                        }
+pub fn index((auth, state): (AdminUser, State<AppState>)) -> impl Responder {
+    state
+        .db
+        .send(IndexMatchOutcomes {
+            user_id: auth.current_user.user_id,
+        })
+        .and_then(move |match_outcomes| match match_outcomes {
+            Ok(matches) => {
+                let mut context = Context::new();
+                context.add("current_user", &auth.current_user);
+                context.add("matches", &matches);
+                let rendered = TEMPLATE_SERVICE.render("admin/matches/index.html", &context);
+
+                match rendered {
+                    Ok(body) => Ok(HttpResponse::Ok().content_type("text/html").body(body)),
+                    Err(error) => {
+                        println!("{:?}", error);
+                        Ok(HttpResponse::InternalServerError()
+                            .content_type("text/html")
+                            .body("Something went wrong"))
                     }
-                    Err(_) => Ok(HttpResponse::InternalServerError()
-                        .content_type("text/html")
-                        .body("Something went wrong")),
-                })
-                .responder(),
-        )
-    } else {
-        Either::B(
-            HttpResponse::Forbidden()
+                }
+            }
+            Err(_) => Ok(HttpResponse::InternalServerError()
                 .content_type("text/html")
-                .body("You do not have permission to view this page"),
-        )
-    }
+                .body("Something went wrong")),
+        })
+        .responder()
 }
 
 struct FetchMatchOutcomeInfo {
@@ -120,198 +126,278 @@ impl Handler<FetchMatchOutcomeInfo> for DbExecutor {
     }
 }
 
-pub fn edit((auth, path, state): (CurrentUser, Path<(i32,)>, State<AppState>)) -> impl Responder {
-    if auth.current_user.user_id == 1 {
-        Either::A(
-            state
-                .db
-                .send(FetchMatchOutcomeInfo { match_id: path.0 })
-                .and_then(move |result| match result {
-                    Ok((game, outcome)) => {
-                        let mut context = Context::new();
-                        context.add("current_user", &auth.current_user);
-                        context.add("match", &game);
-                        context.add("outcome", &outcome);
-                        let rendered = TEMPLATE_SERVICE.render("admin/matches/edit.html", &context);
-
-                        match rendered {
-                            Ok(body) => Ok(HttpResponse::Ok().content_type("text/html").body(body)),
-                            Err(error) => {
-                                println!("{:?}", error);
-                                Ok(HttpResponse::InternalServerError()
-                                    .content_type("text/html")
-                                    .body("Something went wrong"))
-                            }
-                        }
-                    }
+pub fn edit((auth, path, state): (AdminUser, Path<(i32,)>, State<AppState>)) -> impl Responder {
+    state
+        .db
+        .send(FetchMatchOutcomeInfo { match_id: path.0 })
+        .and_then(move |result| match result {
+            Ok((game, outcome)) => {
+                let mut context = Context::new();
+                context.add("current_user", &auth.current_user);
+                context.add("match", &game);
+                context.add("outcome", &outcome);
+                let rendered = TEMPLATE_SERVICE.render("admin/matches/edit.html", &context);
+
+                match rendered {
+                    Ok(body) => Ok(HttpResponse::Ok().content_type("text/html").body(body)),
                     Err(error) => {
                         println!("{:?}", error);
                         Ok(HttpResponse::InternalServerError()
                             .content_type("text/html")
                             .body("Something went wrong"))
                     }
-                })
-                .responder(),
-        )
-    } else {
-        Either::B(
-            HttpResponse::Forbidden()
-                .content_type("text/html")
-                .body("You do not have permission to view this page"),
-        )
-    }
+                }
+            }
+            Err(error) => {
+                println!("{:?}", error);
+                Ok(HttpResponse::InternalServerError()
+                    .content_type("text/html")
+                    .body("Something went wrong"))
+            }
+        })
+        .responder()
 }
 
 struct UpdateMatchOutcomeInfo {
-    outcome: MatchOutcome,
+    outcome: MatchOutcomeWithStrings,
+    admin_id: i32,
 }
 
 impl Message for UpdateMatchOutcomeInfo {
-    type Result = Result<(), failure::Error>;
+    type Result = Result<Notification, failure::Error>;
 }
 
 impl Handler<UpdateMatchOutcomeInfo> for DbExecutor {
-    type Result = Result<(), failure::Error>;
+    type Result = Result<Notification, failure::Error>;
 
     fn handle(&mut self, msg: UpdateMatchOutcomeInfo, _ctx: &mut Self::Context) -> Self::Result {
-        // First start a transaction
-        // Create the match outcome, or replace it
-        // Then go over all users (we only have a few, so should be doable), and calculate the
-        // UserMatchPoints, insert/replace on conflict
-        // Sum the scores for all users at the end
-        // commit
-        use diesel::{insert_into, update};
-
-        Ok(self.connection
-            .transaction::<(), diesel::result::Error, _>(|| {
-                {
-                    use schema::match_outcomes::dsl::*;
-
-                    insert_into(match_outcomes)
-                        .values(&msg.outcome)
-                        .on_conflict(match_id)
-                        .do_update()
-                        .set(&msg.outcome)
-                        .execute(&self.connection)?;
-                }
-                let game = {
-                    let game = {
-                        use schema::matches::dsl::*;
-
-                        matches
-                            .filter(match_id.eq(msg.outcome.match_id))
-                            .first::<Match>(&self.connection)?
-                    };
-
-                    let home_participant = {
-                        use schema::match_participants::dsl::*;
-
-                        match_participants
-                            .filter(match_participant_id.eq(game.home_participant_id))
-                            .first(&self.connection)?
-                    };
-
-                    let away_participant = {
-                        use schema::match_participants::dsl::*;
-
-                        match_participants
-                            .filter(match_participant_id.eq(game.away_participant_id))
-                            .first(&self.connection)?
-                    };
-
-                    (
-                        MatchWithParticipants {
-                            match_id: game.match_id,
-                            stage_id: game.stage_id,
-                            home_participant,
-                            away_participant,
-                            time: game.time,
-                        },
-                        msg.outcome.clone(),
-                    )
-                };
-
-                let users_with_prediction =
-                    {
-                        use schema::match_predictions;
-                        use schema::users::dsl::*;
-
-                        users
-                            .left_join(match_predictions::table.on(
-                                match_predictions::columns::user_id.eq(user_id).and(
-                                    match_predictions::columns::match_id.eq(msg.outcome.match_id),
-                                ),
-                            ))
-                            .load::<(User, Option<MatchPrediction>)>(&self.connection)?
-                    };
-                let users = users_with_prediction
-                    .iter()
-                    .map(|u| u.0.clone())
-                    .collect::<Vec<_>>();
-                let favourites = Favourite::belonging_to(&users)
-                    .load::<Favourite>(&self.connection)?
-                    .grouped_by(&users);
-                let users_with_everything = users_with_prediction
-                    .into_iter()
-                    .zip(favourites)
-                    .map(|((a, b), c)| (a, b, c))
-                    .collect::<Vec<_>>();
-
-                for user in users_with_everything {
-                    let points = user_match_points(&user, &game);
-                    {
-                        use schema::user_match_points::dsl::*;
-
-                        insert_into(user_match_points)
-                            .values(&points)
-                            .on_conflict((user_id, match_id))
-                            .do_update()
-                            .set(&points)
-                            .execute(&self.connection)?;
-                    }
-                }
-
-                {
-                    use diesel::dsl::sql;
-                    use schema::users::dsl::*;
+        let stage = {
+            use schema::{matches, stages};
+
+            matches::table
+                .inner_join(stages::table)
+                .filter(matches::columns::match_id.eq(msg.outcome.match_id))
+                .select(stages::all_columns)
+                .first::<Stage>(&self.connection)?
+        };
+        let outcome = msg.outcome.to_match_outcome(&stage)?;
 
-                    update(users)
-                        .set(score.eq(sql("(SELECT sum(user_match_points.total) FROM user_match_points WHERE user_match_points.user_id = users.user_id)")))
-                        .execute(&self.connection)?;
-                }
+        let report = save_match_outcome(
+            &self.connection,
+            &self.scoring_rules,
+            &outcome,
+            Some(msg.admin_id),
+            "admin",
+        )?;
 
-                {
-                    if game.0.stage_id > 0 {
-                        use schema::match_participants::dsl::*;
-                        let (winning_country_id, losing_country_id) = match game.1.winner() {
-                            1 => (
-                                game.0.home_participant.country_id,
-                                game.0.away_participant.country_id,
-                            ),
-                            -1 => (
-                                game.0.away_participant.country_id,
-                                game.0.home_participant.country_id,
-                            ),
-                            _ => unreachable!(),
-                        };
-
-                        update(match_participants)
-                            .filter(previous_match_id.eq(game.0.match_id))
-                            .filter(result.eq("winner"))
-                            .set(country_id.eq(winning_country_id))
-                            .execute(&self.connection)?;
-                        update(match_participants)
-                            .filter(previous_match_id.eq(game.0.match_id))
-                            .filter(result.eq("loser"))
-                            .set(country_id.eq(losing_country_id))
-                            .execute(&self.connection)?;
-                    }
-                }
-                Ok(())
-            })?)
+        Ok(report.notification())
     }
 }
 
+/// Store the outcome of a match and everything that follows from it: the points of every user,
+/// the group standings and the participants of the matches that come after it. `actor_id` is
+/// the admin that entered the outcome, `None` when it comes from the live scores. Returns what
+/// changed on the leaderboard, to be posted to Slack.
+pub fn save_match_outcome(
+    conn: &PgConnection,
+    scoring_rules: &ScoringRules,
+    outcome: &MatchOutcome,
+    actor_id: Option<i32>,
+    source: &str,
+) -> Result<OutcomeReport, failure::Error> {
+    // First start a transaction
+    // Create the match outcome, or replace it
+    // Then go over all users (we only have a few, so should be doable), and calculate the
+    // UserMatchPoints, insert/replace on conflict
+    // Sum the scores for all users at the end
+    // commit
+    use diesel::{insert_into, update};
+
+    let (game, stage) = {
+        use schema::{matches, stages};
+
+        matches::table
+            .inner_join(stages::table)
+            .filter(matches::columns::match_id.eq(outcome.match_id))
+            .first::<(Match, Stage)>(conn)?
+    };
+
+    Ok(conn.transaction::<_, diesel::result::Error, _>(|| {
+        // Outcomes of the same match are saved one after the other, see `live::StoreLiveScores`
+        {
+            use schema::matches;
+
+            matches::table
+                .find(outcome.match_id)
+                .select(matches::match_id)
+                .for_update()
+                .first::<i32>(conn)?;
+        }
+        let scores_before =
+            tournament_scores(conn, stage.tournament_id, &scoring_rules.tiebreakers)?;
+        {
+            use schema::match_outcomes::dsl::*;
+
+            let before = match_outcomes
+                .filter(match_id.eq(outcome.match_id))
+                .select((
+                    match_id,
+                    home_score,
+                    away_score,
+                    time_of_first_goal,
+                    home_penalties,
+                    away_penalties,
+                    duration,
+                ))
+                .for_update()
+                .first::<MatchOutcome>(conn)
+                .optional()?;
+
+            insert_into(match_outcomes)
+                .values(outcome)
+                .on_conflict(match_id)
+                .do_update()
+                .set(outcome)
+                .execute(conn)?;
+
+            audit::record(
+                conn,
+                &Change {
+                    subject: audit::MATCH_OUTCOME,
+                    actor_id,
+                    user_id: None,
+                    match_id: Some(outcome.match_id),
+                    source,
+                    before: before.as_ref(),
+                    after: Some(outcome),
+                },
+            )?;
+        }
+        let game = {
+            let home_participant = {
+                use schema::match_participants::dsl::*;
+
+                match_participants
+                    .filter(match_participant_id.eq(game.home_participant_id))
+                    .first(conn)?
+            };
+
+            let away_participant = {
+                use schema::match_participants::dsl::*;
+
+                match_participants
+                    .filter(match_participant_id.eq(game.away_participant_id))
+                    .first(conn)?
+            };
+
+            (
+                MatchWithParticipants {
+                    match_id: game.match_id,
+                    stage: stage.clone(),
+                    home_participant,
+                    away_participant,
+                    time: game.time,
+                },
+                outcome.clone(),
+            )
+        };
+
+        let users_with_prediction = {
+            use schema::match_predictions;
+            use schema::users::dsl::*;
+
+            users
+                .left_join(
+                    match_predictions::table.on(match_predictions::columns::user_id
+                        .eq(user_id)
+                        .and(match_predictions::columns::match_id.eq(outcome.match_id))),
+                )
+                .load::<(User, Option<MatchPrediction>)>(conn)?
+        };
+        let users = users_with_prediction
+            .iter()
+            .map(|u| u.0.clone())
+            .collect::<Vec<_>>();
+        let favourites = Favourite::belonging_to(&users)
+            .load::<Favourite>(conn)?
+            .grouped_by(&users);
+        let users_with_everything = users_with_prediction
+            .into_iter()
+            .zip(favourites)
+            .map(|((a, b), c)| (a, b, c))
+            .collect::<Vec<_>>();
+
+        let mut match_points = HashMap::new();
+        for user in users_with_everything {
+            let points = user_match_points(scoring_rules, &user, &game);
+            match_points.insert(points.user_id, points.total);
+            {
+                use schema::user_match_points::dsl::*;
+
+                insert_into(user_match_points)
+                    .values(&points)
+                    .on_conflict((user_id, match_id))
+                    .do_update()
+                    .set(&points)
+                    .execute(conn)?;
+            }
+        }
+
+        if game.0.home_participant.group_drawn_place.is_some() {
+            let tables = update_group_standings(conn, game.0.stage.tournament_id)?;
+            resolve_group_participants(conn, &tables)?;
+            update_group_prediction_points(conn, scoring_rules, &tables)?;
+        }
+
+        update_user_scores(conn, stage.tournament_id)?;
+
+        {
+            let winner_and_loser = match game.1.winner() {
+                1 => Some((
+                    game.0.home_participant.country_id,
+                    game.0.away_participant.country_id,
+                )),
+                -1 => Some((
+                    game.0.away_participant.country_id,
+                    game.0.home_participant.country_id,
+                )),
+                // A tie in the group phase, there is nothing to propagate
+                _ => None,
+            };
+
+            if let Some((winning_country_id, losing_country_id)) = winner_and_loser {
+                use schema::match_participants::dsl::*;
+
+                update(match_participants)
+                    .filter(previous_match_id.eq(game.0.match_id))
+                    .filter(result.eq("winner"))
+                    .set(country_id.eq(winning_country_id))
+                    .execute(conn)?;
+                update(match_participants)
+                    .filter(previous_match_id.eq(game.0.match_id))
+                    .filter(result.eq("loser"))
+                    .set(country_id.eq(losing_country_id))
+                    .execute(conn)?;
+            }
+        }
+
+        let info = {
+            use schema::full_match_infos;
+
+            full_match_infos::table
+                .find(outcome.match_id)
+                .first::<MatchWithAllInfo>(conn)?
+        };
+        Ok(OutcomeReport::new(
+            info,
+            outcome,
+            &scores_before,
+            tournament_scores(conn, stage.tournament_id, &scoring_rules.tiebreakers)?,
+            &match_points,
+        ))
+    })?)
+}
+
 #[derive(Deserialize)]
 pub struct MatchOutcomeWithStrings {
     pub match_id: i32,
@@ -326,8 +412,10 @@ pub struct MatchOutcomeWithStrings {
 }
 
 impl MatchOutcomeWithStrings {
-    fn to_match_outcome(&self) -> Result<MatchOutcome, failure::Error> {
-        let (home_penalties, away_penalties) = if self.match_id > 48 && self.home_penalties == "" {
+    /// Group matches have neither penalties nor a duration, knock-out matches need a duration
+    fn to_match_outcome(&self, stage: &Stage) -> Result<MatchOutcome, failure::Error> {
+        let knockout = stage.is_knockout();
+        let (home_penalties, away_penalties) = if !knockout || self.home_penalties == "" {
             (None, None)
         } else {
             (
@@ -335,7 +423,7 @@ impl MatchOutcomeWithStrings {
                 Some(self.away_penalties.parse()?),
             )
         };
-        let duration = if self.match_id > 48 {
+        let duration = if knockout {
             Some(self.duration.parse()?)
         } else {
             None
@@ -355,44 +443,39 @@ impl MatchOutcomeWithStrings {
     }
 }
 
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
 pub fn update(
-    (auth, outcome, state): (CurrentUser, Form<MatchOutcomeWithStrings>, State<AppState>),
+    (auth, outcome, state): (AdminUser, Form<MatchOutcomeWithStrings>, State<AppState>),
 ) -> impl Responder {
-    if auth.current_user.user_id == 1 {
-        match outcome.into_inner().to_match_outcome() {
-            Ok(outcome) => Either::A(
-                state
-                    .db
-                    .send(UpdateMatchOutcomeInfo {
-                        outcome: outcome.clone(),
-                    })
-                    .and_then(move |data| match data {
-                        Ok(()) => Ok(HttpResponse::SeeOther()
-                            .header("Location", "/admin/matches")
-                            .finish()),
-                        Err(error) => {
-                            println!("{:?}", error);
-                            Ok(HttpResponse::SeeOther()
-                                .header("Location", format!("/admin/matches/{}", outcome.match_id))
-                                .finish())
-                        }
-                    })
-                    .responder(),
-            ),
+    let outcome = outcome.into_inner();
+    let outcome_match_id = outcome.match_id;
+    let notifications = state.notifications.clone();
+
+    state
+        .db
+        .send(UpdateMatchOutcomeInfo {
+            outcome,
+            admin_id: auth.current_user.user_id,
+        })
+        .and_then(move |data| match data {
+            Ok(notification) => {
+                notifications.do_send(notification);
+                Ok(HttpResponse::SeeOther()
+                    .header("Location", "/admin/matches")
+                    .finish())
+            }
             Err(error) => {
                 println!("{:?}", error);
-                Either::B(
-                    HttpResponse::Forbidden()
+                if error.downcast_ref::<ParseIntError>().is_some() {
+                    Ok(HttpResponse::BadRequest()
                         .content_type("text/html")
-                        .body("You do not have permission to view this page"),
-                )
+                        .body("The outcome couldn't be parsed"))
+                } else {
+                    Ok(HttpResponse::SeeOther()
+                        .header("Location", format!("/admin/matches/{}", outcome_match_id))
+                        .finish())
+                }
             }
-        }
-    } else {
-        Either::B(
-            HttpResponse::Forbidden()
-                .content_type("text/html")
-                .body("You do not have permission to view this page"),
-        )
-    }
+        })
+        .responder()
 }
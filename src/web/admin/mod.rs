@@ -1,2 +1,4 @@
+pub mod audit_log;
 pub mod match_outcomes;
 pub mod scores;
+pub mod users;
@@ -1,15 +1,67 @@
 use models::{
-    Favourite, Match, MatchOutcome, MatchParticipant, MatchPrediction, MatchWithParticipants, User,
+    Favourite, Match, MatchOutcome, MatchParticipant, MatchPrediction, MatchWithParticipants,
+    Stage, User,
 };
 use scores::user_match_points;
 use web::app_state::DbExecutor;
+use web::group_predictions::update_group_prediction_points;
+use web::groups::load_group_tables;
 
 use actix::prelude::*;
-use actix_web::{AsyncResponder, Either, HttpResponse, Responder, State};
+use actix_web::{AsyncResponder, HttpResponse, Responder, State};
 use diesel::{self, prelude::*};
 use failure;
 use futures::Future;
-use web::{app_state::AppState, auth::CurrentUser};
+use web::{app_state::AppState, auth::AdminUser};
+
+/// The users that took part in a tournament: those with a prediction or a favourite in it
+const TOURNAMENT_PLAYERS: &str = "\
+    SELECT match_predictions.user_id FROM match_predictions JOIN matches USING (match_id) \
+        WHERE matches.tournament_id = $1 \
+    UNION SELECT group_predictions.user_id FROM group_predictions JOIN groups USING (group_id) \
+        WHERE groups.tournament_id = $1 \
+    UNION SELECT user_id FROM favourites WHERE country_id IS NOT NULL AND tournament_id = $1";
+
+/// Store the points of every user in a tournament in `tournament_scores`, and the sum of all
+/// their match and group points in `users.score`. Only the rows of `for_tournament_id` are
+/// written, as an upsert, so saves for the same or other tournaments can run at the same time.
+pub fn update_user_scores(conn: &PgConnection, for_tournament_id: i32) -> QueryResult<usize> {
+    use diesel::dsl::sql;
+    use diesel::sql_types::Integer;
+    use diesel::{sql_query, update};
+    use schema::users::dsl::*;
+
+    sql_query(format!(
+        "INSERT INTO tournament_scores (tournament_id, user_id, score) \
+         SELECT $1::integer, players.user_id, coalesce(sum(points.total), 0) \
+         FROM ({}) players LEFT JOIN ( \
+             SELECT user_match_points.user_id, user_match_points.total \
+                 FROM user_match_points JOIN matches USING (match_id) \
+                 WHERE matches.tournament_id = $1 \
+             UNION ALL SELECT user_group_points.user_id, user_group_points.total \
+                 FROM user_group_points JOIN groups USING (group_id) \
+                 WHERE groups.tournament_id = $1 \
+         ) points USING (user_id) \
+         GROUP BY players.user_id \
+         ON CONFLICT (tournament_id, user_id) DO UPDATE SET score = excluded.score",
+        TOURNAMENT_PLAYERS
+    )).bind::<Integer, _>(for_tournament_id)
+        .execute(conn)?;
+    // Users whose predictions were removed along with their matches
+    sql_query(format!(
+        "DELETE FROM tournament_scores \
+         WHERE tournament_id = $1 AND user_id NOT IN ({})",
+        TOURNAMENT_PLAYERS
+    )).bind::<Integer, _>(for_tournament_id)
+        .execute(conn)?;
+
+    update(users)
+        .set(score.eq(sql(
+            "(SELECT coalesce(sum(user_match_points.total), 0) FROM user_match_points WHERE user_match_points.user_id = users.user_id) + \
+             (SELECT coalesce(sum(user_group_points.total), 0) FROM user_group_points WHERE user_group_points.user_id = users.user_id)",
+        )))
+        .execute(conn)
+}
 
 struct RecalculateScores;
 
@@ -21,15 +73,32 @@ impl Handler<RecalculateScores> for DbExecutor {
     type Result = Result<(), failure::Error>;
 
     fn handle(&mut self, _msg: RecalculateScores, _ctx: &mut Self::Context) -> Self::Result {
-        use diesel::{insert_into, update};
+        use diesel::insert_into;
 
         Ok(self.connection
             .transaction::<(), diesel::result::Error, _>(|| {
+                // Archived tournaments keep the points of the rules they were played with
+                let tournament_ids = {
+                    use schema::tournaments::dsl::*;
+
+                    tournaments
+                        .filter(is_archived.eq(false))
+                        .select(tournament_id)
+                        .load::<i32>(&self.connection)?
+                };
+
                 let games = {
-                    use schema::{match_participants, matches};
+                    use schema::{match_participants, matches, stages};
                     use std::collections::HashMap;
 
-                    let plain_games = matches::table.load::<Match>(&self.connection)?;
+                    let plain_games = matches::table
+                        .filter(matches::tournament_id.eq_any(&tournament_ids))
+                        .load::<Match>(&self.connection)?;
+                    let stages_by_id = stages::table
+                        .load::<Stage>(&self.connection)?
+                        .into_iter()
+                        .map(|stage| (stage.stage_id, stage))
+                        .collect::<HashMap<_, _>>();
                     let match_participants =
                         match_participants::table.load::<MatchParticipant>(&self.connection)?;
 
@@ -64,7 +133,7 @@ impl Handler<RecalculateScores> for DbExecutor {
                             (
                                 MatchWithParticipants {
                                     match_id: game.match_id,
-                                    stage_id: game.stage_id,
+                                    stage: stages_by_id[&game.stage_id].clone(),
                                     home_participant: participants_by_id[&game.home_participant_id]
                                         .clone(),
                                     away_participant: participants_by_id[&game.away_participant_id]
@@ -107,6 +176,7 @@ impl Handler<RecalculateScores> for DbExecutor {
                             .find(|prediction| prediction.match_id == game.0.match_id)
                             .cloned();
                         match_points.push(user_match_points(
+                            &self.scoring_rules,
                             &(user.0.clone(), prediction, user.2.clone()),
                             game,
                         ));
@@ -130,13 +200,14 @@ impl Handler<RecalculateScores> for DbExecutor {
                         .execute(&self.connection)?;
                 }
 
-                {
-                    use diesel::dsl::sql;
-                    use schema::users::dsl::*;
-
-                    update(users)
-                        .set(score.eq(sql("(SELECT sum(user_match_points.total) FROM user_match_points WHERE user_match_points.user_id = users.user_id)")))
-                        .execute(&self.connection)?;
+                for &tournament_id in &tournament_ids {
+                    let tables = load_group_tables(&self.connection, tournament_id)?;
+                    update_group_prediction_points(
+                        &self.connection,
+                        &self.scoring_rules,
+                        &tables,
+                    )?;
+                    update_user_scores(&self.connection, tournament_id)?;
                 }
 
                 Ok(())
@@ -144,28 +215,19 @@ impl Handler<RecalculateScores> for DbExecutor {
     }
 }
 
-pub fn recalculate((auth, state): (CurrentUser, State<AppState>)) -> impl Responder {
-    if auth.current_user.user_id == 1 {
-        Either::A(
-            state
-                .db
-                .send(RecalculateScores)
-                .and_then(move |data| match data {
-                    Ok(()) => Ok(HttpResponse::SeeOther()
-                        .header("Location", "/admin/matches")
-                        .finish()),
-                    Err(error) => {
-                        println!("{:?}", error);
-                        Ok(HttpResponse::SeeOther().header("Location", "/").finish())
-                    }
-                })
-                .responder(),
-        )
-    } else {
-        Either::B(
-            HttpResponse::Forbidden()
-                .content_type("text/html")
-                .body("You do not have permission to view this page"),
-        )
-    }
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn recalculate((_auth, state): (AdminUser, State<AppState>)) -> impl Responder {
+    state
+        .db
+        .send(RecalculateScores)
+        .and_then(move |data| match data {
+            Ok(()) => Ok(HttpResponse::SeeOther()
+                .header("Location", "/admin/matches")
+                .finish()),
+            Err(error) => {
+                println!("{:?}", error);
+                Ok(HttpResponse::SeeOther().header("Location", "/").finish())
+            }
+        })
+        .responder()
 }
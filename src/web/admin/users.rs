@@ -0,0 +1,134 @@
+use models::User;
+use templates::{Context, TEMPLATE_SERVICE};
+use web::app_state::DbExecutor;
+
+use actix::prelude::*;
+use actix_web::{AsyncResponder, Form, HttpResponse, Path, Responder, State};
+use diesel::{self, prelude::*};
+use failure;
+use futures::Future;
+use std::{error::Error as StdError, fmt};
+use web::{app_state::AppState, auth::AdminUser};
+
+struct IndexUsers;
+
+impl Message for IndexUsers {
+    type Result = Result<Vec<User>, failure::Error>;
+}
+
+impl Handler<IndexUsers> for DbExecutor {
+    type Result = Result<Vec<User>, failure::Error>;
+
+    fn handle(&mut self, _msg: IndexUsers, _ctx: &mut Self::Context) -> Self::Result {
+        use schema::users::dsl::*;
+
+        Ok(users
+            .order(display_name.asc())
+            .load::<User>(&self.connection)?)
+    }
+}
+
+pub fn index((auth, state): (AdminUser, State<AppState>)) -> impl Responder {
+    state
+        .db
+        .send(IndexUsers)
+        .and_then(move |result| match result {
+            Ok(all_users) => {
+                let mut context = Context::new();
+                context.add("current_user", &auth.current_user);
+                context.add("users", &all_users);
+                let rendered = TEMPLATE_SERVICE.render("admin/users/index.html", &context);
+
+                match rendered {
+                    Ok(body) => Ok(HttpResponse::Ok().content_type("text/html").body(body)),
+                    Err(error) => {
+                        println!("{:?}", error);
+                        Ok(HttpResponse::InternalServerError()
+                            .content_type("text/html")
+                            .body("Something went wrong"))
+                    }
+                }
+            }
+            Err(error) => {
+                println!("{:?}", error);
+                Ok(HttpResponse::InternalServerError()
+                    .content_type("text/html")
+                    .body("Something went wrong"))
+            }
+        })
+        .responder()
+}
+
+#[derive(Debug)]
+struct CannotRevokeOwnAdmin;
+
+impl fmt::Display for CannotRevokeOwnAdmin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Admins can't revoke their own admin rights")
+    }
+}
+
+impl StdError for CannotRevokeOwnAdmin {
+    fn description(&self) -> &str {
+        "Admins can't revoke their own admin rights"
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AdminForm {
+    is_admin: bool,
+}
+
+struct UpdateAdmin {
+    current_user_id: i32,
+    user_id: i32,
+    is_admin: bool,
+}
+
+impl Message for UpdateAdmin {
+    type Result = Result<(), failure::Error>;
+}
+
+impl Handler<UpdateAdmin> for DbExecutor {
+    type Result = Result<(), failure::Error>;
+
+    fn handle(&mut self, msg: UpdateAdmin, _ctx: &mut Self::Context) -> Self::Result {
+        use schema::users::dsl::*;
+
+        // Make sure there is always at least one admin left
+        if msg.user_id == msg.current_user_id && !msg.is_admin {
+            Err(CannotRevokeOwnAdmin)?
+        }
+
+        diesel::update(users.filter(user_id.eq(msg.user_id)))
+            .set(is_admin.eq(msg.is_admin))
+            .execute(&self.connection)?;
+
+        Ok(())
+    }
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn update(
+    (auth, path, form, state): (AdminUser, Path<(i32,)>, Form<AdminForm>, State<AppState>),
+) -> impl Responder {
+    state
+        .db
+        .send(UpdateAdmin {
+            current_user_id: auth.current_user.user_id,
+            user_id: path.0,
+            is_admin: form.is_admin,
+        })
+        .and_then(|result| match result {
+            Ok(()) => Ok(HttpResponse::SeeOther()
+                .header("Location", "/admin/users")
+                .finish()),
+            Err(error) => {
+                println!("{:?}", error);
+                Ok(HttpResponse::BadRequest()
+                    .content_type("text/html")
+                    .body(format!("{}", error)))
+            }
+        })
+        .responder()
+}
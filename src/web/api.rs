@@ -0,0 +1,416 @@
+//! JSON versions of the HTML endpoints, mounted under `/api/v1`. The handlers send the same
+//! messages to the `DbExecutor` as the HTML handlers do, only the rendering differs.
+
+use lock::{PredictionsLocked, Rejection};
+use models::{MatchOutcome, MatchPrediction, MatchWithAllInfo};
+use web::admin::match_outcomes::IndexMatchOutcomes;
+use web::app_state::{AppState, DbExecutor};
+use web::auth::CurrentUser;
+use web::favourites::{
+    FavouriteSelection, FavouritesClosed, FetchFavouriteInfo, InvalidFavourites,
+    UpdatedFavouriteInfo,
+};
+use web::leagues::FetchUserLeagues;
+use web::match_predictions::{
+    BulkPredictionErrors, BulkUpdatePredictions, FetchBulkPredictionInfo, FetchPredictionInfo,
+    InvalidPrediction, MatchPredictionItem, PredictionInput, UpdatePredictionInfo,
+};
+use web::score_history::FetchScoreHistory;
+use web::scores::FetchLeaderBoard;
+use web::tournaments::viewed_tournament;
+
+use actix::prelude::*;
+use actix_web::{
+    http::StatusCode, AsyncResponder, FutureResponse, HttpResponse, Json, Path, Query, State,
+};
+use chrono::{DateTime, Utc};
+use diesel::{self, prelude::*};
+use failure;
+use futures::future::{self, Either};
+use futures::Future;
+use std::collections::BTreeMap;
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+fn json_error(status: StatusCode, message: &str) -> HttpResponse {
+    HttpResponse::build(status).json(ErrorBody {
+        error: message.to_string(),
+    })
+}
+
+/// What is wrong with each of the predictions of a bulk update
+#[derive(Serialize)]
+struct InvalidPredictionsBody<'a> {
+    error: String,
+    errors: &'a BTreeMap<i32, String>,
+}
+
+/// Lists every match of the request that can't be predicted (anymore)
+#[derive(Serialize)]
+struct LockedBody<'a> {
+    error: String,
+    rejections: &'a [Rejection],
+}
+
+/// Translate errors of the `DbExecutor` into a JSON body with a fitting status code
+fn error_response(error: &failure::Error) -> HttpResponse {
+    if let Some(locked) = error.downcast_ref::<PredictionsLocked>() {
+        HttpResponse::build(StatusCode::CONFLICT).json(LockedBody {
+            error: format!("{}", locked),
+            rejections: &locked.rejections,
+        })
+    } else if let Some(invalid) = error.downcast_ref::<BulkPredictionErrors>() {
+        HttpResponse::build(StatusCode::UNPROCESSABLE_ENTITY).json(InvalidPredictionsBody {
+            error: format!("{}", invalid),
+            errors: &invalid.errors,
+        })
+    } else if error.downcast_ref::<InvalidPrediction>().is_some()
+        || error.downcast_ref::<InvalidFavourites>().is_some()
+    {
+        json_error(StatusCode::UNPROCESSABLE_ENTITY, &format!("{}", error))
+    } else if error.downcast_ref::<FavouritesClosed>().is_some() {
+        json_error(StatusCode::CONFLICT, &format!("{}", error))
+    } else if let Some(diesel::result::Error::NotFound) = error.downcast_ref() {
+        json_error(StatusCode::NOT_FOUND, "Not found")
+    } else {
+        println!("{:?}", error);
+        json_error(StatusCode::INTERNAL_SERVER_ERROR, "Something went wrong")
+    }
+}
+
+struct FetchMatches {
+    user_id: i32,
+}
+
+impl Message for FetchMatches {
+    type Result = Result<Vec<(MatchWithAllInfo, Option<MatchOutcome>)>, failure::Error>;
+}
+
+impl Handler<FetchMatches> for DbExecutor {
+    type Result = Result<Vec<(MatchWithAllInfo, Option<MatchOutcome>)>, failure::Error>;
+
+    fn handle(&mut self, msg: FetchMatches, _ctx: &mut Self::Context) -> Self::Result {
+        use schema::full_match_infos::dsl::*;
+        use schema::match_outcomes;
+
+        let tournament = viewed_tournament(&self.connection, msg.user_id)?;
+
+        Ok(full_match_infos
+            .filter(tournament_id.eq(tournament.tournament_id))
+            .left_join(match_outcomes::table.on(match_outcomes::columns::match_id.eq(match_id)))
+            .select((
+                full_match_infos::all_columns(),
+                (
+                    match_outcomes::columns::match_id,
+                    match_outcomes::columns::home_score,
+                    match_outcomes::columns::away_score,
+                    match_outcomes::columns::time_of_first_goal,
+                    match_outcomes::columns::home_penalties,
+                    match_outcomes::columns::away_penalties,
+                    match_outcomes::columns::duration,
+                ).nullable(),
+            ))
+            .order((time.asc(), match_id.asc()))
+            .load::<(MatchWithAllInfo, Option<MatchOutcome>)>(&self.connection)?)
+    }
+}
+
+#[derive(Serialize)]
+struct MatchEntry {
+    #[serde(rename = "match")]
+    game: MatchWithAllInfo,
+    outcome: Option<MatchOutcome>,
+}
+
+/// `GET /api/v1/matches`
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn matches((auth, state): (CurrentUser, State<AppState>)) -> FutureResponse<HttpResponse> {
+    state
+        .db
+        .send(FetchMatches {
+            user_id: auth.current_user.user_id,
+        })
+        .from_err()
+        .and_then(|result| {
+            Ok(match result {
+                Ok(matches) => HttpResponse::Ok().json(
+                    matches
+                        .into_iter()
+                        .map(|(game, outcome)| MatchEntry { game, outcome })
+                        .collect::<Vec<_>>(),
+                ),
+                Err(error) => error_response(&error),
+            })
+        })
+        .responder()
+}
+
+/// `GET /api/v1/matches/{id}`, the predictions of others are only included after kick-off
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn show_match(
+    (auth, path, state): (CurrentUser, Path<(i32,)>, State<AppState>),
+) -> FutureResponse<HttpResponse> {
+    state
+        .db
+        .send(FetchPredictionInfo {
+            user_id: auth.current_user.user_id,
+            match_id: path.0,
+        })
+        .from_err()
+        .and_then(|result| {
+            Ok(match result {
+                Ok(info) => HttpResponse::Ok().json(info),
+                Err(error) => error_response(&error),
+            })
+        })
+        .responder()
+}
+
+/// `GET /api/v1/matches/{id}/prediction`, `null` when nothing was predicted yet
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn show_prediction(
+    (auth, path, state): (CurrentUser, Path<(i32,)>, State<AppState>),
+) -> FutureResponse<HttpResponse> {
+    state
+        .db
+        .send(FetchPredictionInfo {
+            user_id: auth.current_user.user_id,
+            match_id: path.0,
+        })
+        .from_err()
+        .and_then(|result| {
+            Ok(match result {
+                Ok(info) => HttpResponse::Ok().json(info.prediction),
+                Err(error) => error_response(&error),
+            })
+        })
+        .responder()
+}
+
+/// `PUT /api/v1/matches/{id}/prediction`
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn update_prediction(
+    (auth, path, prediction, state): (
+        CurrentUser,
+        Path<(i32,)>,
+        Json<PredictionInput>,
+        State<AppState>,
+    ),
+) -> FutureResponse<HttpResponse> {
+    state
+        .db
+        .send(UpdatePredictionInfo {
+            user_id: auth.current_user.user_id,
+            match_id: path.0,
+            prediction: prediction.into_inner(),
+            source: "api".to_string(),
+        })
+        .from_err()
+        .and_then(|result| {
+            Ok(match result {
+                Ok(()) => HttpResponse::NoContent().finish(),
+                Err(error) => error_response(&error),
+            })
+        })
+        .responder()
+}
+
+#[derive(Serialize)]
+struct PredictionEntry {
+    #[serde(rename = "match")]
+    game: MatchWithAllInfo,
+    prediction: Option<MatchPrediction>,
+}
+
+/// `GET /api/v1/predictions`, all upcoming matches with the prediction of the current user
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn predictions((auth, state): (CurrentUser, State<AppState>)) -> FutureResponse<HttpResponse> {
+    state
+        .db
+        .send(FetchBulkPredictionInfo {
+            user_id: auth.current_user.user_id,
+        })
+        .from_err()
+        .and_then(|result| {
+            Ok(match result {
+                Ok(matches) => HttpResponse::Ok().json(
+                    matches
+                        .into_iter()
+                        .map(|(game, prediction)| PredictionEntry { game, prediction })
+                        .collect::<Vec<_>>(),
+                ),
+                Err(error) => error_response(&error),
+            })
+        })
+        .responder()
+}
+
+/// `PUT /api/v1/predictions`, updates all given predictions or none of them
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn update_predictions(
+    (auth, predictions, state): (CurrentUser, Json<Vec<MatchPredictionItem>>, State<AppState>),
+) -> FutureResponse<HttpResponse> {
+    state
+        .db
+        .send(BulkUpdatePredictions {
+            user_id: auth.current_user.user_id,
+            match_predictions: predictions.into_inner(),
+            source: "api".to_string(),
+        })
+        .from_err()
+        .and_then(|result| {
+            Ok(match result {
+                Ok(()) => HttpResponse::NoContent().finish(),
+                Err(error) => error_response(&error),
+            })
+        })
+        .responder()
+}
+
+/// `GET /api/v1/favourites`, the picks and deadlines of every phase
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn favourites((auth, state): (CurrentUser, State<AppState>)) -> FutureResponse<HttpResponse> {
+    state
+        .db
+        .send(FetchFavouriteInfo {
+            user_id: auth.current_user.user_id,
+        })
+        .from_err()
+        .and_then(|result| {
+            Ok(match result {
+                Ok(info) => HttpResponse::Ok().json(info),
+                Err(error) => error_response(&error),
+            })
+        })
+        .responder()
+}
+
+/// `PUT /api/v1/favourites`, the picks for one phase, while that phase is open
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn update_favourites(
+    (auth, selection, state): (CurrentUser, Json<FavouriteSelection>, State<AppState>),
+) -> FutureResponse<HttpResponse> {
+    state
+        .db
+        .send(UpdatedFavouriteInfo {
+            user_id: auth.current_user.user_id,
+            selection: selection.into_inner(),
+            source: "api".to_string(),
+        })
+        .from_err()
+        .and_then(|result| {
+            Ok(match result {
+                Ok(()) => HttpResponse::NoContent().finish(),
+                Err(error) => error_response(&error),
+            })
+        })
+        .responder()
+}
+
+/// `GET /api/v1/outcomes`, the outcomes of all matches that have been played
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn outcomes((auth, state): (CurrentUser, State<AppState>)) -> FutureResponse<HttpResponse> {
+    state
+        .db
+        .send(IndexMatchOutcomes {
+            user_id: auth.current_user.user_id,
+        })
+        .from_err()
+        .and_then(|result| {
+            Ok(match result {
+                Ok(matches) => HttpResponse::Ok().json(
+                    matches
+                        .into_iter()
+                        .filter_map(|(_game, outcome)| outcome)
+                        .collect::<Vec<_>>(),
+                ),
+                Err(error) => error_response(&error),
+            })
+        })
+        .responder()
+}
+
+#[derive(Serialize)]
+struct LeaderBoard<T> {
+    leader_board: Vec<T>,
+    previous: Option<DateTime<Utc>>,
+}
+
+/// `GET /api/v1/scores?up_to=<unix timestamp>&league=<league id>`, like `/scores` only leagues
+/// the current user is a member of can be requested
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn scores(
+    (auth, query, state): (CurrentUser, Query<FetchLeaderBoard>, State<AppState>),
+) -> FutureResponse<HttpResponse> {
+    let mut data = query.into_inner();
+    data.user_id = auth.current_user.user_id;
+    let db = state.db.clone();
+
+    state
+        .db
+        .send(FetchUserLeagues {
+            user_id: auth.current_user.user_id,
+        })
+        .from_err()
+        .and_then(move |leagues| match leagues {
+            Ok(ref leagues)
+                if data.league.map_or(true, |league_id| {
+                    leagues.iter().any(|league| league.league_id == league_id)
+                }) =>
+            {
+                Either::A(db.send(data).from_err().map(|result| match result {
+                    Ok((leader_board, previous)) => HttpResponse::Ok().json(LeaderBoard {
+                        leader_board,
+                        previous,
+                    }),
+                    Err(error) => error_response(&error),
+                }))
+            }
+            Ok(_) => Either::B(future::ok(json_error(StatusCode::NOT_FOUND, "Not found"))),
+            Err(error) => Either::B(future::ok(error_response(&error))),
+        })
+        .responder()
+}
+
+#[derive(Deserialize)]
+pub struct HistoryQuery {
+    league: Option<i32>,
+}
+
+/// `GET /api/v1/scores/history?league=<league id>`, the points and rank of every user after each
+/// match day, with the positions gained since the previous one
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn score_history(
+    (auth, query, state): (CurrentUser, Query<HistoryQuery>, State<AppState>),
+) -> FutureResponse<HttpResponse> {
+    let league_id = query.league;
+    let user_id = auth.current_user.user_id;
+    let db = state.db.clone();
+
+    state
+        .db
+        .send(FetchUserLeagues { user_id })
+        .from_err()
+        .and_then(move |leagues| match leagues {
+            Ok(ref leagues)
+                if league_id.map_or(true, |league_id| {
+                    leagues.iter().any(|league| league.league_id == league_id)
+                }) =>
+            {
+                Either::A(
+                    db.send(FetchScoreHistory { user_id, league_id })
+                        .from_err()
+                        .map(|result| match result {
+                            Ok(history) => HttpResponse::Ok().json(history),
+                            Err(error) => error_response(&error),
+                        }),
+                )
+            }
+            Ok(_) => Either::B(future::ok(json_error(StatusCode::NOT_FOUND, "Not found"))),
+            Err(error) => Either::B(future::ok(error_response(&error))),
+        })
+        .responder()
+}
@@ -1,9 +1,21 @@
 use actix::prelude::*;
 use diesel::pg::PgConnection;
 use diesel::Connection;
+use lock::LockPolicy;
+use mailer::Mailer;
+use notifications::Notifications;
+use scores::ScoringRules;
+use std::net::IpAddr;
+use std::sync::Arc;
+use web::validation::RegistrationPolicy;
 
 pub struct DbExecutor {
     pub connection: PgConnection,
+    pub scoring_rules: ScoringRules,
+    pub lock_policy: LockPolicy,
+    pub mailer: Arc<Mailer>,
+    /// Used to build the links in emails, e.g. `https://predictions.example.com`
+    pub base_url: String,
 }
 
 impl Actor for DbExecutor {
@@ -13,10 +25,28 @@ impl Actor for DbExecutor {
 /// This is state where we will store *DbExecutor* address.
 pub struct AppState {
     pub db: Addr<DbExecutor>,
+    pub scoring_rules: ScoringRules,
+    pub registration_policy: RegistrationPolicy,
+    /// Signs the `next` parameter of the login page, see `web::redirects`
+    pub redirect_key: Vec<u8>,
+    /// Results for Slack, see `notifications`
+    pub notifications: Addr<Notifications>,
+    /// The reverse proxy whose `X-Forwarded-For` is believed, from `TRUSTED_PROXY`
+    pub trusted_proxy: Option<IpAddr>,
 }
 
-pub fn establish_connection(database_url: &str) -> DbExecutor {
+pub fn establish_connection(
+    database_url: &str,
+    scoring_rules: &ScoringRules,
+    lock_policy: LockPolicy,
+    mailer: &Arc<Mailer>,
+    base_url: &str,
+) -> DbExecutor {
     DbExecutor {
         connection: PgConnection::establish(&database_url).unwrap(),
+        scoring_rules: scoring_rules.clone(),
+        lock_policy,
+        mailer: mailer.clone(),
+        base_url: base_url.to_string(),
     }
 }
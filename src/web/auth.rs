@@ -1,50 +1,144 @@
 use actix::prelude::*;
 use actix_web::{
-    self, dev::AsyncResult, error::ResponseError, middleware::identity::RequestIdentity,
-    AsyncResponder, Form, FromRequest, FutureResponse, HttpRequest, HttpResponse, State,
+    self, dev::AsyncResult, error::ResponseError, http::{header, Method, StatusCode},
+    middleware::identity::RequestIdentity, AsyncResponder, Form, FromRequest, FutureResponse,
+    HttpRequest, HttpResponse, Query, State,
 };
 use bcrypt::verify;
+use chrono::{Duration, Utc};
+use diesel::sql_types::{Nullable, Text};
 use diesel::{self, prelude::*};
 use failure;
 use futures::{future, Future};
-use models::{NewUser, User};
+use models::{NewLoginAttempt, NewUser, User};
+use sha2::{Digest, Sha256};
 use std::error::Error as StdError;
 use std::fmt;
 use templates::{Context, TEMPLATE_SERVICE};
 use web::app_state::{AppState, DbExecutor};
+use web::redirects::{sign_next, verify_next, with_next};
+use web::validation::{password_problem, FieldErrors, RegistrationPolicy};
 
 pub struct CurrentUser {
     pub current_user: User,
 }
 
+/// API and password reset tokens are stored as the hex encoded SHA-256 hash of the token
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// The token of an `Authorization: Bearer <token>` header, if there is one
+fn bearer_token(req: &HttpRequest<AppState>) -> Option<String> {
+    let value = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+
+    if value.starts_with("Bearer ") {
+        Some(value["Bearer ".len()..].trim().to_string())
+    } else {
+        None
+    }
+}
+
+/// Fetch the user that is identified by the API token of the request, or else the user that is
+/// remembered in the identity of the request. Tokens only count for the API, so one can't be used
+/// to create more tokens or to change the account.
+fn identified_user(
+    req: &HttpRequest<AppState>,
+) -> Box<Future<Item = User, Error = actix_web::Error>> {
+    // Scripted clients get a 401 instead of a redirect to the login page
+    let api = req.path().starts_with("/api/");
+    // Only pages that can be opened again with a GET are worth returning to after logging in
+    let next = if req.method() == Method::GET {
+        let requested = match req.query_string() {
+            "" => req.path().to_string(),
+            query => format!("{}?{}", req.path(), query),
+        };
+        sign_next(&req.state().redirect_key, &requested)
+    } else {
+        None
+    };
+    let rejection = move || -> actix_web::Error {
+        if api {
+            Unauthorized.into()
+        } else {
+            Unauthenticated { next: next.clone() }.into()
+        }
+    };
+
+    let token = if api { bearer_token(req) } else { None };
+    if let Some(token) = token {
+        return Box::new(
+            req.state()
+                .db
+                .send(FetchTokenUser {
+                    token_hash: hash_token(&token),
+                })
+                .then(|x| match x {
+                    Ok(Ok(x)) => future::ok(x),
+                    _ => future::err(Unauthorized.into()),
+                }),
+        );
+    }
+
+    match req.identity() {
+        Some(current_user_id_string) => match current_user_id_string.parse() {
+            Ok(current_user_id) => Box::new(
+                req.state()
+                    .db
+                    .send(FetchCurrentUser {
+                        user_id: current_user_id,
+                    })
+                    .then(move |x| match x {
+                        Ok(Ok(x)) => future::ok(x),
+                        Ok(Err(ref y)) if y.downcast_ref::<Unauthenticated>().is_some() => {
+                            future::err(rejection())
+                        }
+                        Ok(Err(y)) => future::err(y.into()),
+                        Err(_) => future::err(rejection()),
+                    }),
+            ),
+            _ => Box::new(future::err(rejection())),
+        },
+        None => Box::new(future::err(rejection())),
+    }
+}
+
 impl FromRequest<AppState> for CurrentUser {
     type Config = ();
     type Result = AsyncResult<Self, actix_web::Error>;
 
     fn from_request(req: &HttpRequest<AppState>, _cfg: &Self::Config) -> Self::Result {
-        match req.identity() {
-            Some(current_user_id_string) => match current_user_id_string.parse() {
-                Ok(current_user_id) => AsyncResult::async(Box::new(
-                    req.state()
-                        .db
-                        .send(FetchCurrentUser {
-                            user_id: current_user_id,
-                        })
-                        .then(|x| match x {
-                            Ok(Ok(x)) => future::ok(CurrentUser { current_user: x }),
-                            Ok(Err(y)) => future::err(y.into()),
-                            Err(_) => future::err(Unauthenticated.into()),
-                        }),
-                )),
-                _ => AsyncResult::err(Unauthenticated),
-            },
-            None => AsyncResult::err(Unauthenticated),
-        }
+        AsyncResult::async(Box::new(
+            identified_user(req).map(|user| CurrentUser { current_user: user }),
+        ))
     }
 }
 
-#[derive(Debug)]
-pub struct Unauthenticated;
+/// Like `CurrentUser`, but only accepts users that are allowed to use the admin pages
+pub struct AdminUser {
+    pub current_user: User,
+}
+
+impl FromRequest<AppState> for AdminUser {
+    type Config = ();
+    type Result = AsyncResult<Self, actix_web::Error>;
+
+    fn from_request(req: &HttpRequest<AppState>, _cfg: &Self::Config) -> Self::Result {
+        AsyncResult::async(Box::new(identified_user(req).and_then(|user| {
+            if user.is_admin {
+                Ok(AdminUser { current_user: user })
+            } else {
+                Err(Forbidden { current_user: user }.into())
+            }
+        })))
+    }
+}
+
+/// Redirects to the login page, `next` is the signed path to return to after logging in
+#[derive(Debug, Default)]
+pub struct Unauthenticated {
+    pub next: Option<String>,
+}
 
 impl fmt::Display for Unauthenticated {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -61,11 +155,75 @@ impl StdError for Unauthenticated {
 impl ResponseError for Unauthenticated {
     fn error_response(&self) -> HttpResponse {
         HttpResponse::TemporaryRedirect()
-            .header("Location", "/login")
+            .header(
+                "Location",
+                with_next("/login", self.next.as_ref().map(String::as_str)),
+            )
             .finish()
     }
 }
 
+#[derive(Debug)]
+pub struct Unauthorized;
+
+impl fmt::Display for Unauthorized {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Missing or invalid API token")
+    }
+}
+
+impl StdError for Unauthorized {
+    fn description(&self) -> &str {
+        "Missing or invalid API token"
+    }
+}
+
+impl ResponseError for Unauthorized {
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::Unauthorized()
+            .header(header::WWW_AUTHENTICATE, "Bearer")
+            .content_type("application/json")
+            .body(r#"{"error":"Missing or invalid API token"}"#)
+    }
+}
+
+#[derive(Debug)]
+pub struct Forbidden {
+    current_user: User,
+}
+
+impl fmt::Display for Forbidden {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "User not allowed to view this page")
+    }
+}
+
+impl StdError for Forbidden {
+    fn description(&self) -> &str {
+        "User not allowed to view this page"
+    }
+}
+
+impl ResponseError for Forbidden {
+    fn error_response(&self) -> HttpResponse {
+        let mut context = Context::new();
+        context.add("current_user", &self.current_user);
+
+        let rendered = TEMPLATE_SERVICE.render("forbidden.html", &context);
+        match rendered {
+            Ok(body) => HttpResponse::Forbidden()
+                .content_type("text/html")
+                .body(body),
+            Err(error) => {
+                println!("{}", error);
+                HttpResponse::Forbidden()
+                    .content_type("text/html")
+                    .body("You do not have permission to view this page")
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct UserNotFoundError;
 
@@ -85,6 +243,8 @@ impl StdError for UserNotFoundError {
 pub struct LoginForm {
     username: String,
     password: String,
+    #[serde(default)]
+    next: String,
 }
 
 #[derive(Deserialize, Clone)]
@@ -92,21 +252,33 @@ pub struct RegistrationForm {
     username: String,
     name: String,
     password: String,
+    #[serde(default)]
+    invite_code: String,
+    #[serde(default)]
+    next: String,
 }
 
-pub fn login() -> HttpResponse {
-    let rendered = TEMPLATE_SERVICE.render("login.html", &Context::new());
-    match rendered {
-        Ok(body) => HttpResponse::Ok().content_type("text/html").body(body),
-        Err(error) => {
-            println!("{}", error);
-            HttpResponse::InternalServerError()
-                .content_type("text/html")
-                .body("Something went wrong")
-        }
+#[derive(Deserialize, Debug)]
+pub struct NextQuery {
+    #[serde(default)]
+    next: String,
+}
+
+/// The signed `next` parameter if its signature is valid, an empty string otherwise, so a
+/// tampered parameter isn't passed along to the next page
+fn checked_next<'a>(state: &AppState, next: &'a str) -> &'a str {
+    if verify_next(&state.redirect_key, next).is_some() {
+        next
+    } else {
+        ""
     }
 }
 
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn login((query, state): (Query<NextQuery>, State<AppState>)) -> HttpResponse {
+    render_login(StatusCode::OK, None, checked_next(&state, &query.next))
+}
+
 struct FetchCurrentUser {
     user_id: i32,
 }
@@ -125,63 +297,209 @@ impl Handler<FetchCurrentUser> for DbExecutor {
         let result = users
             .filter(user_id.eq(msg.user_id))
             .first(&self.connection)
-            .map_err(|_| Unauthenticated)?;
+            .map_err(|_| Unauthenticated::default())?;
 
         Ok(result)
     }
 }
 
-impl Message for LoginForm {
+struct FetchTokenUser {
+    token_hash: String,
+}
+
+impl Message for FetchTokenUser {
+    type Result = Result<User, failure::Error>;
+}
+
+impl Handler<FetchTokenUser> for DbExecutor {
+    type Result = Result<User, failure::Error>;
+
+    fn handle(&mut self, msg: FetchTokenUser, _: &mut Self::Context) -> Self::Result {
+        use schema::api_tokens::dsl::*;
+        use schema::users;
+
+        let user = diesel::update(api_tokens.filter(token_hash.eq(&msg.token_hash)))
+            .set(last_used_at.eq(Utc::now().naive_utc()))
+            .returning(user_id)
+            .get_result::<i32>(&self.connection)
+            .optional()?
+            .ok_or(Unauthorized)?;
+
+        Ok(users::table
+            .filter(users::user_id.eq(user))
+            .first(&self.connection)?)
+    }
+}
+
+/// Failed logins are limited per account and per IP address within this window
+fn failed_login_window() -> Duration {
+    Duration::minutes(15)
+}
+
+const MAX_FAILED_LOGINS_PER_ACCOUNT: i64 = 5;
+const MAX_FAILED_LOGINS_PER_IP: i64 = 20;
+
+#[derive(Debug)]
+pub struct TooManyLoginAttempts;
+
+impl fmt::Display for TooManyLoginAttempts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Too many failed login attempts, please try again later")
+    }
+}
+
+impl StdError for TooManyLoginAttempts {
+    fn description(&self) -> &str {
+        "Too many failed login attempts, please try again later"
+    }
+}
+
+struct PerformLogin {
+    form: LoginForm,
+    /// `None` when the address of the client isn't known, the limit per IP is skipped then
+    ip_address: Option<String>,
+}
+
+impl Message for PerformLogin {
     type Result = Result<i32, failure::Error>;
 }
 
-impl Handler<LoginForm> for DbExecutor {
+impl Handler<PerformLogin> for DbExecutor {
     type Result = Result<i32, failure::Error>;
 
-    fn handle(&mut self, form: LoginForm, _context: &mut Self::Context) -> Self::Result {
-        use schema::users;
+    fn handle(&mut self, msg: PerformLogin, _context: &mut Self::Context) -> Self::Result {
+        use schema::{login_attempts, users};
+
+        // Like the email address of `Register`
+        let login = msg.form.username.trim();
+        let since = (Utc::now() - failed_login_window()).naive_utc();
+        let account_failures = login_attempts::table
+            .filter(login_attempts::successful.eq(false))
+            .filter(login_attempts::created_at.gt(since))
+            .filter(login_attempts::login.eq(login))
+            .count()
+            .get_result::<i64>(&self.connection)?;
+        let ip_failures = match msg.ip_address {
+            Some(ref ip_address) => login_attempts::table
+                .filter(login_attempts::successful.eq(false))
+                .filter(login_attempts::created_at.gt(since))
+                .filter(login_attempts::ip_address.eq(ip_address))
+                .count()
+                .get_result::<i64>(&self.connection)?,
+            None => 0,
+        };
+
+        if account_failures >= MAX_FAILED_LOGINS_PER_ACCOUNT
+            || ip_failures >= MAX_FAILED_LOGINS_PER_IP
+        {
+            Err(TooManyLoginAttempts)?
+        }
 
         let user = users::table
-            .filter(users::email.eq(form.username))
+            .filter(users::email.eq(login))
             .first::<User>(&self.connection)
             .optional()?;
 
-        match user {
+        let result = match user {
             Some(u) => {
-                if verify(&form.password, &u.encrypted_password)? {
+                if verify(&msg.form.password, &u.encrypted_password)? {
                     Ok(u.user_id)
                 } else {
-                    Err(Unauthenticated.into())
+                    Err(Unauthenticated::default().into())
                 }
             }
             None => Err(UserNotFoundError.into()),
+        };
+
+        diesel::insert_into(login_attempts::table)
+            .values(&NewLoginAttempt {
+                login,
+                ip_address: msg.ip_address.as_ref().map_or("", String::as_str),
+                successful: result.is_ok(),
+            })
+            .execute(&self.connection)?;
+
+        result
+    }
+}
+
+fn render_login(status: StatusCode, error: Option<&str>, next: &str) -> HttpResponse {
+    let mut context = Context::new();
+    context.add("error", &error);
+    context.add("next", &next);
+    context.add("register_url", &with_next("/register", Some(next)));
+
+    let rendered = TEMPLATE_SERVICE.render("login.html", &context);
+    match rendered {
+        Ok(body) => HttpResponse::build(status)
+            .content_type("text/html")
+            .body(body),
+        Err(error) => {
+            println!("{}", error);
+            HttpResponse::InternalServerError()
+                .content_type("text/html")
+                .body("Something went wrong")
         }
     }
 }
 
+/// The address of the client, which is the last one the trusted proxy added to `X-Forwarded-For`
+/// when the request came through it. Without a trusted proxy every user behind a reverse proxy
+/// would have its address.
+fn client_address(req: &HttpRequest<AppState>) -> Option<String> {
+    let peer = req.peer_addr()?.ip();
+
+    if Some(peer) == req.state().trusted_proxy {
+        req.headers()
+            .get("X-Forwarded-For")?
+            .to_str()
+            .ok()?
+            .rsplit(',')
+            .map(str::trim)
+            .find(|address| !address.is_empty())
+            .map(str::to_string)
+    } else {
+        Some(peer.to_string())
+    }
+}
+
 #[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
 pub fn perform_login(
     (form, state, req): (Form<LoginForm>, State<AppState>, HttpRequest<AppState>),
 ) -> FutureResponse<HttpResponse> {
-    let inner_form = form.into_inner();
+    let ip_address = client_address(&req);
+    let form = form.into_inner();
+    let next = checked_next(&state, &form.next).to_string();
+    let destination = verify_next(&state.redirect_key, &next).unwrap_or_else(|| "/".to_string());
+
     state
         .db
-        .send(inner_form.clone())
+        .send(PerformLogin { form, ip_address })
         .from_err()
         .and_then(move |res| match res {
             Ok(user_id) => {
                 req.remember(user_id.to_string());
-                // TODO: check whether user was redirected to login, and redirect there instead
-                Ok(HttpResponse::SeeOther().header("Location", "/").finish())
+                Ok(HttpResponse::SeeOther()
+                    .header("Location", destination)
+                    .finish())
             }
             Err(err) => {
                 if err.downcast_ref::<Unauthenticated>().is_some()
                     || err.downcast_ref::<UserNotFoundError>().is_some()
                 {
-                    Ok(HttpResponse::SeeOther()
-                        .header("Location", "/login")
-                        .finish())
+                    Ok(render_login(
+                        StatusCode::UNAUTHORIZED,
+                        Some("Unknown email address or wrong password"),
+                        &next,
+                    ))
+                } else if err.downcast_ref::<TooManyLoginAttempts>().is_some() {
+                    Ok(render_login(
+                        StatusCode::TOO_MANY_REQUESTS,
+                        Some(&format!("{}", err)),
+                        &next,
+                    ))
                 } else {
+                    println!("{:?}", err);
                     Ok(HttpResponse::InternalServerError()
                         .content_type("text/plain; charset=utf-8")
                         .body("An unexpected error occurred"))
@@ -191,10 +509,38 @@ pub fn perform_login(
         .responder()
 }
 
-pub fn register() -> HttpResponse {
-    let rendered = TEMPLATE_SERVICE.render("register.html", &Context::new());
+fn render_registration(
+    status: StatusCode,
+    form: Option<&RegistrationForm>,
+    errors: Option<&FieldErrors>,
+    policy: &RegistrationPolicy,
+    next: &str,
+) -> HttpResponse {
+    let mut context = Context::new();
+    context.add("next", &next);
+    context.add("invite_only", &policy.invite_code.is_some());
+    context.add(
+        "allowed_email_domains",
+        &policy.allowed_email_domains.join(", "),
+    );
+    context.add("username", &form.map_or("", |form| form.username.as_str()));
+    context.add("name", &form.map_or("", |form| form.name.as_str()));
+    context.add(
+        "invite_code",
+        &form.map_or("", |form| form.invite_code.as_str()),
+    );
+    for field in &["username", "name", "password", "invite_code"] {
+        context.add(
+            &format!("{}_error", field),
+            &errors.and_then(|errors| errors.errors.get(field)),
+        );
+    }
+
+    let rendered = TEMPLATE_SERVICE.render("register.html", &context);
     match rendered {
-        Ok(body) => HttpResponse::Ok().content_type("text/html").body(body),
+        Ok(body) => HttpResponse::build(status)
+            .content_type("text/html")
+            .body(body),
         Err(error) => {
             println!("{}", error);
             HttpResponse::InternalServerError()
@@ -204,29 +550,107 @@ pub fn register() -> HttpResponse {
     }
 }
 
-impl Message for RegistrationForm {
-    type Result = Result<User, diesel::result::Error>;
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn register((query, state): (Query<NextQuery>, State<AppState>)) -> HttpResponse {
+    render_registration(
+        StatusCode::OK,
+        None,
+        None,
+        &state.registration_policy,
+        checked_next(&state, &query.next),
+    )
+}
+
+sql_function!(fn lower(x: Nullable<Text>) -> Nullable<Text>);
+
+/// Whether another user already uses this display name, ignoring case
+pub fn display_name_taken(
+    conn: &PgConnection,
+    name: &str,
+    except_user_id: Option<i32>,
+) -> QueryResult<bool> {
+    use schema::users::dsl::*;
+
+    let others = users
+        .filter(lower(display_name).eq(name.to_lowercase()))
+        .filter(user_id.ne(except_user_id.unwrap_or(0)))
+        .count()
+        .get_result::<i64>(conn)?;
+
+    Ok(others > 0)
+}
+
+struct Register {
+    form: RegistrationForm,
+    policy: RegistrationPolicy,
+}
+
+impl Message for Register {
+    type Result = Result<User, failure::Error>;
 }
-impl Handler<RegistrationForm> for DbExecutor {
-    type Result = Result<User, diesel::result::Error>;
 
-    fn handle(&mut self, form: RegistrationForm, _: &mut Self::Context) -> Self::Result {
+impl Handler<Register> for DbExecutor {
+    type Result = Result<User, failure::Error>;
+
+    fn handle(&mut self, msg: Register, _: &mut Self::Context) -> Self::Result {
+        use diesel::result::{DatabaseErrorKind, Error::DatabaseError};
         use schema::users;
 
-        // TODO: add minimumum length on password
-        // Possibly that should be in NewUser, however the values function doesn't accept
-        // validation, so should be different step, perhaps NewUser constructor
+        let email = msg.form.username.trim();
+        let name = msg.form.name.trim();
+
+        let mut errors = FieldErrors::new();
+        errors.add("username", msg.policy.email_problem(email));
+        if name.is_empty() {
+            errors.add("name", Some("Please choose a name".to_string()));
+        }
+        errors.add("password", password_problem(&msg.form.password, email));
+        errors.add(
+            "invite_code",
+            msg.policy.invite_code_problem(&msg.form.invite_code),
+        );
+
+        let email_taken = users::table
+            .filter(users::email.eq(email))
+            .count()
+            .get_result::<i64>(&self.connection)? > 0;
+        if email_taken {
+            errors.add(
+                "username",
+                Some("There already is an account with this email address".to_string()),
+            );
+        }
+        if display_name_taken(&self.connection, name, None)? {
+            errors.add("name", Some("This name is already taken".to_string()));
+        }
+
+        if !errors.is_empty() {
+            return Err(errors.into());
+        }
+
         let user = NewUser {
-            email: &form.username,
-            login: &form.username,
-            display_name: Some(&form.name),
-            password: &form.password,
+            email,
+            login: email,
+            display_name: Some(name),
+            password: &msg.form.password,
             slack_handle: None,
         };
 
-        diesel::insert_into(users::table)
+        match diesel::insert_into(users::table)
             .values(user)
             .get_result(&self.connection)
+        {
+            Ok(user) => Ok(user),
+            // Someone registered with the same address in the meantime
+            Err(DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+                errors.add(
+                    "username",
+                    Some("There already is an account with this email address".to_string()),
+                );
+                Err(errors)?
+            }
+            Err(error) => Err(error)?,
+        }
     }
 }
 
@@ -234,25 +658,36 @@ impl Handler<RegistrationForm> for DbExecutor {
 pub fn perform_registration(
     (form, state): (Form<RegistrationForm>, State<AppState>),
 ) -> FutureResponse<HttpResponse> {
-    use diesel::result::{DatabaseErrorKind, Error::DatabaseError};
-
     let inner_form = form.into_inner();
+    let policy = state.registration_policy.clone();
+    let next = checked_next(&state, &inner_form.next).to_string();
+
     state
         .db
-        .send(inner_form.clone())
+        .send(Register {
+            form: inner_form.clone(),
+            policy: policy.clone(),
+        })
         .from_err()
         .and_then(move |res| match res {
             Ok(_user) => Ok(HttpResponse::SeeOther()
-                .header("Location", "/login")
+                .header("Location", with_next("/login", Some(&next)))
                 .finish()),
-            Err(DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
-                Ok(HttpResponse::PreconditionFailed()
-                    .content_type("text/plain; charset=utf-8")
-                    .body(format!("User {} already registered", inner_form.username)))
-            }
-            Err(_) => Ok(HttpResponse::InternalServerError()
-                .content_type("text/plain; charset=utf-8")
-                .body("An unexpected error occurred")),
+            Err(error) => match error.downcast_ref::<FieldErrors>() {
+                Some(errors) => Ok(render_registration(
+                    StatusCode::BAD_REQUEST,
+                    Some(&inner_form),
+                    Some(errors),
+                    &policy,
+                    &next,
+                )),
+                None => {
+                    println!("{:?}", error);
+                    Ok(HttpResponse::InternalServerError()
+                        .content_type("text/plain; charset=utf-8")
+                        .body("An unexpected error occurred"))
+                }
+            },
         })
         .responder()
 }
@@ -1,11 +1,18 @@
-use models::{Country, Favourite, Group, MatchOutcome, MatchPrediction, MatchWithAllInfo, User};
+use models::{
+    Country, Favourite, Group, League, LiveScore, MatchOutcome, MatchPrediction,
+    MatchWithAllInfo, Tournament, User,
+};
+use ranking::{rank, tiebreak_stats, Position};
 use templates::{Context, TEMPLATE_SERVICE};
 use web::app_state::{AppState, DbExecutor};
+use web::leagues::{fetch_user_leagues, LeagueFilter};
+use web::score_history::score_history;
+use web::tournaments::viewed_tournament;
 
 use actix::prelude::*;
 use actix_web::{
     middleware::identity::RequestIdentity, AsyncResponder, Either, Error, FutureResponse,
-    HttpRequest, HttpResponse, State,
+    HttpRequest, HttpResponse, Query, State,
 };
 use futures::Future;
 
@@ -13,21 +20,30 @@ use diesel::prelude::*;
 use failure;
 
 use chrono::Utc;
+use std::collections::HashMap;
 
 struct DashboardData {
     current_user: User,
-    leader_board: Vec<User>,
+    tournament: Tournament,
+    leagues: Vec<League>,
+    current_league: Option<League>,
+    /// Users with their score in the tournament, the positions they gained on the last match
+    /// day (negative when they dropped) and their position after the tiebreakers
+    leader_board: Vec<(User, i32, i32, Position)>,
     upcoming: Vec<(MatchWithAllInfo, Option<MatchPrediction>)>,
+    /// The outcome of a match, or its live score while it is being played
     finished: Vec<(
         MatchWithAllInfo,
         Option<MatchOutcome>,
         Option<MatchPrediction>,
+        Option<LiveScore>,
     )>,
     favourites: Vec<(Favourite, Option<Country>, Option<Group>)>,
 }
 
 struct FetchDataForDashboard {
     user_id: i32,
+    league_id: Option<i32>,
 }
 
 impl Message for FetchDataForDashboard {
@@ -42,17 +58,46 @@ fn fetch_current_user(db: &DbExecutor, current_user_id: i32) -> Result<User, fai
         .first(&db.connection)?)
 }
 
-fn fetch_users(db: &DbExecutor, amount: i64) -> Result<Vec<User>, failure::Error> {
+fn fetch_users(
+    db: &DbExecutor,
+    tournament: &Tournament,
+    amount: usize,
+    league: Option<&League>,
+) -> Result<Vec<(Position, (User, i32))>, failure::Error> {
     use schema::users::dsl::*;
+    use schema::{league_memberships, tournament_scores};
 
-    Ok(users
-        .order(score.desc())
-        .limit(amount)
-        .get_results(&db.connection)?)
+    // All users are needed to find out who made the top after the tiebreakers
+    let mut query = users
+        .inner_join(tournament_scores::table)
+        .filter(tournament_scores::tournament_id.eq(tournament.tournament_id))
+        .select((users::all_columns(), tournament_scores::score))
+        .into_boxed();
+    if let Some(league) = league {
+        query = query.filter(
+            user_id.eq_any(
+                league_memberships::table
+                    .filter(league_memberships::league_id.eq(league.league_id))
+                    .select(league_memberships::user_id),
+            ),
+        );
+    }
+
+    let stats = tiebreak_stats(&db.connection, tournament.tournament_id, None)?;
+    let mut ranked = rank(
+        query.get_results::<(User, i32)>(&db.connection)?,
+        |&(ref user, total)| (user.user_id, i64::from(total)),
+        &stats,
+        &db.scoring_rules.tiebreakers,
+    );
+    ranked.truncate(amount);
+
+    Ok(ranked)
 }
 
 fn fetch_upcoming(
     db: &DbExecutor,
+    tournament: &Tournament,
     current_user_id: i32,
     amount: i64,
 ) -> Result<Vec<(MatchWithAllInfo, Option<MatchPrediction>)>, failure::Error> {
@@ -60,6 +105,7 @@ fn fetch_upcoming(
     use schema::match_predictions;
 
     Ok(full_match_infos
+        .filter(tournament_id.eq(tournament.tournament_id))
         .filter(time.gt(Utc::now()))
         .filter(home_country_name.is_not_null())
         .filter(away_country_name.is_not_null())
@@ -79,6 +125,7 @@ fn fetch_upcoming(
 
 fn fetch_previous(
     db: &DbExecutor,
+    tournament: &Tournament,
     current_user_id: i32,
     amount: i64,
 ) -> Result<
@@ -86,14 +133,17 @@ fn fetch_previous(
         MatchWithAllInfo,
         Option<MatchOutcome>,
         Option<MatchPrediction>,
+        Option<LiveScore>,
     )>,
     failure::Error,
 > {
     use schema::full_match_infos::dsl::*;
+    use schema::live_scores;
     use schema::match_outcomes;
     use schema::match_predictions;
 
     Ok(full_match_infos
+        .filter(tournament_id.eq(tournament.tournament_id))
         .filter(time.le(Utc::now()))
         .left_join(match_outcomes::table.on(match_outcomes::columns::match_id.eq(match_id)))
         .left_join(
@@ -101,6 +151,7 @@ fn fetch_previous(
                 .eq(match_id)
                 .and(match_predictions::columns::user_id.eq(current_user_id))),
         )
+        .left_join(live_scores::table.on(live_scores::columns::match_id.eq(match_id)))
         .select((
             full_match_infos::all_columns(),
             (
@@ -113,6 +164,7 @@ fn fetch_previous(
                 match_outcomes::columns::duration,
             ).nullable(),
             match_predictions::all_columns.nullable(),
+            live_scores::all_columns.nullable(),
         ))
         .limit(amount)
         .order((time.desc(), match_id.desc()))
@@ -121,24 +173,39 @@ fn fetch_previous(
 
 fn fetch_favourites(
     db: &DbExecutor,
+    tournament: &Tournament,
     current_user_id: i32,
 ) -> Result<Vec<(Favourite, Option<Country>, Option<Group>)>, failure::Error> {
+    // Countries play in a group of every tournament, only the group of this one is shown
+    let groups_by_country = {
+        use schema::{group_memberships, groups};
+
+        group_memberships::table
+            .inner_join(groups::table)
+            .filter(groups::tournament_id.eq(tournament.tournament_id))
+            .select((group_memberships::country_id, groups::all_columns))
+            .load::<(i32, Group)>(&db.connection)?
+            .into_iter()
+            .collect::<HashMap<_, _>>()
+    };
     let mut current_selection = {
+        use schema::countries;
         use schema::favourites::dsl::*;
-        use schema::{countries, group_memberships, groups};
 
         favourites
             .filter(user_id.eq(current_user_id))
-            .left_join(
-                countries::table.left_join(group_memberships::table.left_join(groups::table)),
-            )
-            .select((
-                favourites::all_columns(),
-                countries::all_columns.nullable(),
-                groups::all_columns.nullable(),
-            ))
+            .filter(tournament_id.eq(tournament.tournament_id))
+            .left_join(countries::table)
             .order(choice)
-            .load(&db.connection)?
+            .load::<(Favourite, Option<Country>)>(&db.connection)?
+            .into_iter()
+            .map(|(favourite, country)| {
+                let group = country
+                    .as_ref()
+                    .and_then(|country| groups_by_country.get(&country.country_id).cloned());
+                (favourite, country, group)
+            })
+            .collect::<Vec<_>>()
     };
 
     if current_selection.len() < 4 {
@@ -152,6 +219,7 @@ fn fetch_favourites(
                     updated_at: Utc::now().naive_local(),
                     phase: 0,
                     source: "manual".to_string(),
+                    tournament_id: tournament.tournament_id,
                 },
                 None,
                 None,
@@ -172,19 +240,44 @@ impl Handler<FetchDataForDashboard> for DbExecutor {
         // player
         // 6 following upcoming matches, with predictions for current player
 
+        let leagues = fetch_user_leagues(&self.connection, msg.user_id)?;
+        // Only show leaderboards of leagues the user is a member of
+        let current_league = leagues
+            .iter()
+            .find(|league| Some(league.league_id) == msg.league_id)
+            .cloned();
+
+        let tournament = viewed_tournament(&self.connection, msg.user_id)?;
+        let deltas = score_history(
+            &self.connection,
+            tournament.tournament_id,
+            current_league.as_ref().map(|league| league.league_id),
+            &self.scoring_rules.tiebreakers,
+        )?.latest_deltas();
+        let leader_board = fetch_users(&self, &tournament, 13, current_league.as_ref())?
+            .into_iter()
+            .map(|(position, (user, score))| {
+                let delta = deltas.get(&user.user_id).cloned().unwrap_or(0);
+                (user, score, delta, position)
+            })
+            .collect();
+
         Ok(DashboardData {
             current_user: fetch_current_user(&self, msg.user_id)?,
-            leader_board: fetch_users(&self, 13)?,
-            upcoming: fetch_upcoming(&self, msg.user_id, 10)?,
-            finished: fetch_previous(&self, msg.user_id, 10)?,
-            favourites: fetch_favourites(&self, msg.user_id)?,
+            leader_board,
+            leagues,
+            current_league,
+            upcoming: fetch_upcoming(&self, &tournament, msg.user_id, 10)?,
+            finished: fetch_previous(&self, &tournament, msg.user_id, 10)?,
+            favourites: fetch_favourites(&self, &tournament, msg.user_id)?,
+            tournament,
         })
     }
 }
 
 #[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
 pub fn index(
-    (request, state): (HttpRequest<AppState>, State<AppState>),
+    (request, filter, state): (HttpRequest<AppState>, Query<LeagueFilter>, State<AppState>),
 ) -> Either<FutureResponse<HttpResponse>, HttpResponse> {
     match request.identity() {
         Some(current_user_id) => Either::A(
@@ -192,6 +285,7 @@ pub fn index(
                 .db
                 .send(FetchDataForDashboard {
                     user_id: current_user_id.parse().unwrap(),
+                    league_id: filter.league,
                 })
                 .from_err()
                 .and_then(move |res| {
@@ -199,6 +293,9 @@ pub fn index(
                         Ok(dashboard_data) => {
                             let mut context = Context::new();
                             context.add("current_user", &dashboard_data.current_user);
+                            context.add("tournament", &dashboard_data.tournament);
+                            context.add("leagues", &dashboard_data.leagues);
+                            context.add("current_league", &dashboard_data.current_league);
                             context.add("leader_board", &dashboard_data.leader_board);
                             context.add("upcoming", &dashboard_data.upcoming);
                             context.add("finished", &dashboard_data.finished);
@@ -1,125 +1,303 @@
+use audit::{self, Change};
 use models::{Country, Favourite, UpdatedFavourite};
 use templates::{Context, TEMPLATE_SERVICE};
 use web::app_state::DbExecutor;
+use web::tournaments::viewed_tournament;
 use web::{app_state::AppState, auth::CurrentUser};
 
 use actix::prelude::*;
-use actix_web::{AsyncResponder, Form, HttpRequest, HttpResponse, Responder};
-use chrono::Utc;
+use actix_web::{AsyncResponder, Either, Form, HttpRequest, HttpResponse, Path, Responder};
+use chrono::{DateTime, Utc};
 use diesel::{self, prelude::*};
 use failure;
 use futures::Future;
 use std::{error::Error as StdError, fmt};
 
-struct FavouriteInfo {
-    current_selection: Vec<(Favourite, Option<Country>)>,
-    available_countries: Vec<Country>,
+/// Favourites are picked three times during the tournament, every pick is stored with its own
+/// `choice` number
+pub struct FavouritePhase {
+    pub phase: i16,
+    pub first_choice: i16,
+    pub picks: i16,
 }
 
-struct FetchFavouriteInfo {
-    user_id: i32,
+pub const PHASES: [FavouritePhase; 3] = [
+    FavouritePhase {
+        phase: 0,
+        first_choice: 1,
+        picks: 4,
+    },
+    FavouritePhase {
+        phase: 1,
+        first_choice: 5,
+        picks: 3,
+    },
+    FavouritePhase {
+        phase: 2,
+        first_choice: 8,
+        picks: 1,
+    },
+];
+
+/// When the favourites of a phase can be picked: once all matches of the previous phase have
+/// their outcome (so it is known who goes through), until the first match of the phase
+#[derive(Serialize, Debug, Clone)]
+pub struct PhaseSchedule {
+    pub phase: i16,
+    pub picks: i16,
+    /// The stages of the phase, e.g. "Round of 16, Quarter-finals"
+    pub description: String,
+    /// The kickoff of the last match of the previous phase, the phase opens after its outcome
+    pub opens_at: Option<DateTime<Utc>>,
+    pub closes_at: Option<DateTime<Utc>>,
+    /// Whether outcomes of the previous phase are still missing
+    pub waiting_for_outcomes: bool,
+    pub open: bool,
+}
+
+fn kickoffs_in_phase<'a>(
+    kickoffs: &'a [(i16, DateTime<Utc>)],
     phase: i16,
+) -> impl Iterator<Item = DateTime<Utc>> + 'a {
+    kickoffs
+        .iter()
+        .filter(move |&&(stage_phase, _)| stage_phase == phase)
+        .map(|&(_, kickoff)| kickoff)
 }
 
-impl Message for FetchFavouriteInfo {
-    type Result = Result<FavouriteInfo, failure::Error>;
+pub fn phase_schedules(conn: &PgConnection, tournament_id: i32) -> QueryResult<Vec<PhaseSchedule>> {
+    use schema::{match_outcomes, matches, stages};
+
+    let kickoffs = matches::table
+        .inner_join(stages::table)
+        .filter(stages::tournament_id.eq(tournament_id))
+        .select((stages::phase, matches::time))
+        .load::<(i16, DateTime<Utc>)>(conn)?;
+    let phases_with_missing_outcomes = matches::table
+        .inner_join(stages::table)
+        .filter(stages::tournament_id.eq(tournament_id))
+        .filter(matches::match_id.ne_all(match_outcomes::table.select(match_outcomes::match_id)))
+        .select(stages::phase)
+        .distinct()
+        .load::<i16>(conn)?;
+    let phase_stages = stages::table
+        .filter(stages::tournament_id.eq(tournament_id))
+        .order(stages::stage_number)
+        .select((stages::phase, stages::description))
+        .load::<(i16, String)>(conn)?;
+    let now = Utc::now();
+
+    Ok(PHASES
+        .iter()
+        .map(|definition| {
+            let (opens_at, waiting_for_outcomes) = if definition.phase == 0 {
+                (None, false)
+            } else {
+                (
+                    kickoffs_in_phase(&kickoffs, definition.phase - 1).max(),
+                    phases_with_missing_outcomes.contains(&(definition.phase - 1)),
+                )
+            };
+            let closes_at = kickoffs_in_phase(&kickoffs, definition.phase).min();
+
+            PhaseSchedule {
+                phase: definition.phase,
+                picks: definition.picks,
+                description: phase_stages
+                    .iter()
+                    .filter(|(stage_phase, _)| *stage_phase == definition.phase)
+                    .map(|(_, description)| description.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                opens_at,
+                closes_at,
+                waiting_for_outcomes,
+                open: !waiting_for_outcomes
+                    && opens_at.map_or(true, |opens_at| opens_at <= now)
+                    && closes_at.map_or(false, |closes_at| now < closes_at),
+            }
+        })
+        .collect())
+}
+
+#[derive(Serialize)]
+pub struct FavouriteInfo {
+    pub schedule: PhaseSchedule,
+    pub current_selection: Vec<(Favourite, Option<Country>)>,
+    pub available_countries: Vec<Country>,
 }
 
 #[derive(Debug)]
-struct UnexpectedError;
+pub struct FavouritesClosed;
 
-impl fmt::Display for UnexpectedError {
+impl fmt::Display for FavouritesClosed {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Unexpected error")
+        write!(f, "The favourites of this phase can't be changed at the moment")
     }
 }
 
-impl StdError for UnexpectedError {
+impl StdError for FavouritesClosed {
     fn description(&self) -> &str {
-        "Unexpected error"
+        "The favourites of this phase can't be changed at the moment"
     }
 }
 
+#[derive(Debug)]
+pub struct InvalidFavourites {
+    pub message: String,
+}
+
+impl fmt::Display for InvalidFavourites {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl StdError for InvalidFavourites {
+    fn description(&self) -> &str {
+        "Invalid favourites"
+    }
+}
+
+fn phase_definition(phase: i16) -> Result<&'static FavouritePhase, InvalidFavourites> {
+    PHASES
+        .iter()
+        .find(|definition| definition.phase == phase)
+        .ok_or_else(|| InvalidFavourites {
+            message: "There is no such favourites phase".to_string(),
+        })
+}
+
+/// The countries of the tournament that can be picked during the phase, only those that are
+/// still in the race
+fn available_countries(
+    conn: &PgConnection,
+    tournament_id: i32,
+    phase: i16,
+) -> QueryResult<Vec<Country>> {
+    use schema::countries::dsl::*;
+
+    if phase <= 1 {
+        use schema::{countries, group_memberships, groups};
+
+        let members = countries
+            .inner_join(group_memberships::table.inner_join(groups::table))
+            .filter(groups::tournament_id.eq(tournament_id))
+            .order(name.asc())
+            .select(countries::all_columns)
+            .distinct();
+
+        if phase == 0 {
+            members.load(conn)
+        } else {
+            members
+                .filter(group_memberships::qualified_for_knockout.eq(true))
+                .load(conn)
+        }
+    } else {
+        use schema::{countries, match_participants, stages};
+
+        countries
+            .inner_join(match_participants::table.inner_join(stages::table))
+            .filter(stages::tournament_id.eq(tournament_id))
+            .filter(stages::phase.eq(phase))
+            .order(name.asc())
+            .select(countries::all_columns)
+            .distinct()
+            .load(conn)
+    }
+}
+
+fn fetch_favourite_info(
+    conn: &PgConnection,
+    for_user_id: i32,
+    for_tournament_id: i32,
+    schedule: PhaseSchedule,
+) -> Result<FavouriteInfo, failure::Error> {
+    let definition = phase_definition(schedule.phase)?;
+    let available_countries = available_countries(conn, for_tournament_id, schedule.phase)?;
+    let selection = {
+        use schema::countries;
+        use schema::favourites::dsl::*;
+
+        favourites
+            .filter(user_id.eq(for_user_id))
+            .filter(tournament_id.eq(for_tournament_id))
+            .filter(phase.eq(schedule.phase))
+            .order(choice)
+            .left_join(countries::table)
+            .load::<(Favourite, Option<Country>)>(conn)?
+    };
+
+    // Picks that weren't made yet are shown as empty
+    let current_selection = (definition.first_choice..(definition.first_choice + definition.picks))
+        .map(|pick| {
+            selection
+                .iter()
+                .find(|(favourite, _)| favourite.choice == pick)
+                .cloned()
+                .unwrap_or_else(|| {
+                    (
+                        Favourite {
+                            user_id: for_user_id,
+                            country_id: None,
+                            choice: pick,
+                            phase: schedule.phase,
+                            source: "manual".to_string(),
+                            tournament_id: for_tournament_id,
+
+                            // Doesn't matter too much if naive_local is the right method
+                            // (as opposed to naive_utc), because we don't send it to the DB
+                            // here, we only need it to get the Favourite type to display things
+                            created_at: Utc::now().naive_local(),
+                            updated_at: Utc::now().naive_local(),
+                        },
+                        None,
+                    )
+                })
+        })
+        .collect();
+
+    Ok(FavouriteInfo {
+        schedule,
+        current_selection,
+        available_countries,
+    })
+}
+
+/// The favourites of the user for every phase
+pub struct FetchFavouriteInfo {
+    pub user_id: i32,
+}
+
+impl Message for FetchFavouriteInfo {
+    type Result = Result<Vec<FavouriteInfo>, failure::Error>;
+}
+
 impl Handler<FetchFavouriteInfo> for DbExecutor {
-    type Result = Result<FavouriteInfo, failure::Error>;
+    type Result = Result<Vec<FavouriteInfo>, failure::Error>;
 
     fn handle(&mut self, msg: FetchFavouriteInfo, _: &mut Self::Context) -> Self::Result {
-        let available_countries = {
-            use schema::countries::dsl::*;
-
-            if msg.phase == 0 {
-                countries.order(name.asc()).load(&self.connection)?
-            } else if msg.phase == 1 {
-                countries
-                    .filter(qualified_for_knockout.eq(true))
-                    .order(name.asc())
-                    .load(&self.connection)?
-            } else {
-                use schema::{countries, match_participants};
-
-                countries
-                    .left_join(match_participants::table)
-                    .filter(match_participants::columns::stage_id.eq(4))
-                    .order(name.asc())
-                    .select(countries::all_columns)
-                    .load(&self.connection)?
-            }
-        };
-        let mut current_selection = {
-            use schema::countries;
-            use schema::favourites::dsl::*;
-
-            favourites
-                .filter(user_id.eq(msg.user_id))
-                .filter(phase.eq(msg.phase))
-                .order(choice)
-                .left_join(countries::table)
-                .load(&self.connection)?
-        };
-
-        let (offset, length) = match msg.phase {
-            0 => (1, 4),
-            1 => (5, 3),
-            2 => (8, 1),
-            _ => unreachable!(),
-        };
-
-        if current_selection.is_empty() {
-            for i in offset..(offset + length) {
-                current_selection.push((
-                    Favourite {
-                        user_id: msg.user_id,
-                        country_id: None,
-                        choice: i as i16,
-                        phase: msg.phase,
-                        source: "manual".to_string(),
-
-                        // Doesn't matter too much if naive_local is the right method
-                        // (as opposed to naive_utc), because we don't send it to the DB here,
-                        // we only need it to get the Favourite type to display things
-                        created_at: Utc::now().naive_local(),
-                        updated_at: Utc::now().naive_local(),
-                    },
-                    None,
-                ));
-            }
-        }
-        if current_selection.len() != length {
-            Err(UnexpectedError.into())
-        } else {
-            Ok(FavouriteInfo {
-                available_countries,
-                current_selection,
+        let tournament = viewed_tournament(&self.connection, msg.user_id)?;
+
+        phase_schedules(&self.connection, tournament.tournament_id)?
+            .into_iter()
+            .map(|schedule| {
+                fetch_favourite_info(
+                    &self.connection,
+                    msg.user_id,
+                    tournament.tournament_id,
+                    schedule,
+                )
             })
-        }
+            .collect()
     }
 }
 
-fn render_favourite_selection(auth: &CurrentUser, fav_info: &FavouriteInfo) -> HttpResponse {
+fn render_favourite_selection(auth: &CurrentUser, phases: &[FavouriteInfo]) -> HttpResponse {
     let mut context = Context::new();
     context.add("current_user", &auth.current_user);
-    context.add("current_selection", &fav_info.current_selection);
-    context.add("available_countries", &fav_info.available_countries);
+    context.add("phases", &phases);
 
     let rendered = TEMPLATE_SERVICE.render("favourites/edit.html", &context);
 
@@ -140,10 +318,9 @@ pub fn edit((auth, req): (CurrentUser, HttpRequest<AppState>)) -> impl Responder
         .db
         .send(FetchFavouriteInfo {
             user_id: auth.current_user.user_id,
-            phase: 2,
         })
         .and_then(move |fav_info| match fav_info {
-            Ok(info) => Ok(render_favourite_selection(&auth, &info)),
+            Ok(phases) => Ok(render_favourite_selection(&auth, &phases)),
             Err(err) => {
                 println!("{:?}", err);
                 Ok(HttpResponse::InternalServerError()
@@ -154,10 +331,18 @@ pub fn edit((auth, req): (CurrentUser, HttpRequest<AppState>)) -> impl Responder
         .responder()
 }
 
-struct UpdatedFavouriteInfo {
-    user_id: i32,
-    data: FavouriteSelectionForm,
-    phase: i16,
+/// The countries picked for a phase, in the order of the picks, 0 leaves a pick empty
+#[derive(Deserialize, Debug)]
+pub struct FavouriteSelection {
+    pub phase: i16,
+    pub picks: Vec<i32>,
+}
+
+pub struct UpdatedFavouriteInfo {
+    pub user_id: i32,
+    pub selection: FavouriteSelection,
+    /// How the favourites were chosen, e.g. `manual` or `api`
+    pub source: String,
 }
 
 impl Message for UpdatedFavouriteInfo {
@@ -168,77 +353,167 @@ impl Handler<UpdatedFavouriteInfo> for DbExecutor {
     type Result = Result<(), failure::Error>;
 
     fn handle(&mut self, msg: UpdatedFavouriteInfo, _: &mut Self::Context) -> Self::Result {
-        let data = vec![msg.data.fav_1];
-
-        self.connection
-            .transaction::<_, diesel::result::Error, _>(|| {
-                let mut changes = Vec::new();
-                for (&country_id, choice_idx) in data.iter().zip((8..=8).into_iter()) {
-                    changes.push(UpdatedFavourite {
-                        user_id: msg.user_id,
-                        country_id: if country_id == 0 {
-                            None
-                        } else {
-                            Some(country_id)
-                        },
-                        phase: msg.phase,
-                        choice: choice_idx,
-                    });
+        let definition = phase_definition(msg.selection.phase)?;
+        let picks = &msg.selection.picks;
+
+        self.connection.transaction::<_, failure::Error, _>(|| {
+            let tournament = viewed_tournament(&self.connection, msg.user_id)?;
+            let schedule_open = phase_schedules(&self.connection, tournament.tournament_id)?
+                .iter()
+                .any(|schedule| schedule.phase == definition.phase && schedule.open);
+            if !schedule_open {
+                Err(FavouritesClosed)?
+            }
+
+            if picks.len() != definition.picks as usize {
+                Err(InvalidFavourites {
+                    message: format!("Please pick {} countries", definition.picks),
+                })?
+            }
+            let available =
+                available_countries(&self.connection, tournament.tournament_id, definition.phase)?;
+            for (index, &pick) in picks.iter().enumerate() {
+                if pick == 0 {
+                    continue;
                 }
+                if !available.iter().any(|country| country.country_id == pick) {
+                    Err(InvalidFavourites {
+                        message: "This country can't be picked in this phase".to_string(),
+                    })?
+                }
+                if picks[..index].contains(&pick) {
+                    Err(InvalidFavourites {
+                        message: "Every country can only be picked once".to_string(),
+                    })?
+                }
+            }
+
+            let changes = picks
+                .iter()
+                .zip(definition.first_choice..)
+                .map(|(&country_id, choice)| UpdatedFavourite {
+                    user_id: msg.user_id,
+                    country_id: if country_id == 0 {
+                        None
+                    } else {
+                        Some(country_id)
+                    },
+                    phase: definition.phase,
+                    choice,
+                    source: msg.source.clone(),
+                    tournament_id: tournament.tournament_id,
+                })
+                .collect::<Vec<_>>();
+
+            {
+                use diesel::insert_into;
+                use diesel::pg::upsert::excluded;
+                use schema::favourites::dsl::*;
+
+                let before = favourites
+                    .filter(user_id.eq(msg.user_id))
+                    .filter(tournament_id.eq(tournament.tournament_id))
+                    .filter(phase.eq(definition.phase))
+                    .for_update()
+                    .load::<Favourite>(&self.connection)?;
 
-                {
-                    use diesel::insert_into;
-                    use diesel::pg::upsert::excluded;
-                    use schema::favourites::dsl::*;
-
-                    insert_into(favourites)
-                        .values(&changes)
-                        .on_conflict((user_id, phase, choice))
-                        .do_update()
-                        .set(country_id.eq(excluded(country_id)))
-                        .execute(&self.connection)?;
+                let after = insert_into(favourites)
+                    .values(&changes)
+                    .on_conflict((user_id, tournament_id, phase, choice))
+                    .do_update()
+                    .set((
+                        country_id.eq(excluded(country_id)),
+                        source.eq(excluded(source)),
+                    ))
+                    .get_results::<Favourite>(&self.connection)?;
+
+                for favourite in &after {
+                    audit::record(
+                        &self.connection,
+                        &Change {
+                            subject: audit::FAVOURITE,
+                            actor_id: Some(msg.user_id),
+                            user_id: Some(msg.user_id),
+                            match_id: None,
+                            source: &msg.source,
+                            before: before.iter().find(|old| old.choice == favourite.choice),
+                            after: Some(favourite),
+                        },
+                    )?;
                 }
+            }
 
-                Ok(())
-            })?;
+            Ok(())
+        })
+    }
+}
 
-        Ok(())
+/// Back to the dashboard when the favourites were saved, explain what went wrong otherwise
+fn redirect_or_error(result: Result<(), failure::Error>) -> HttpResponse {
+    match result {
+        Ok(()) => HttpResponse::SeeOther().header("Location", "/").finish(),
+        Err(error) => {
+            println!("{:?}", error);
+            if error.downcast_ref::<FavouritesClosed>().is_some() {
+                HttpResponse::Conflict()
+                    .content_type("text/plain; charset=utf-8")
+                    .body(format!("{}", error))
+            } else if error.downcast_ref::<InvalidFavourites>().is_some() {
+                HttpResponse::BadRequest()
+                    .content_type("text/plain; charset=utf-8")
+                    .body(format!("{}", error))
+            } else {
+                HttpResponse::InternalServerError()
+                    .content_type("text/html")
+                    .body("Something went wrong")
+            }
+        }
     }
 }
 
-#[derive(Deserialize, Debug)]
-pub struct FavouriteSelectionForm {
-    // Why not use a Vec? Because Form uses serde_urlencoded to deserialize,
-    // and serde_deserialize decided that the rack like behaviour that converts
-    // values into strings doesn't belong in that crate but in serde_qs, I could
-    // write my own extractor based on that crate, but at the moment I'm trying
-    // to get something working
-    fav_1: i32,
+/// Every pick of the form is a `country_id` select, in the order of the picks
+fn parse_picks(fields: &[(String, String)]) -> Result<Vec<i32>, failure::Error> {
+    let mut picks = Vec::new();
+    for (key, value) in fields {
+        if key == "country_id" {
+            picks.push(value.parse()?);
+        }
+    }
+
+    Ok(picks)
 }
 
 #[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
 pub fn update(
-    (auth, form, req): (
+    (auth, path, form, req): (
         CurrentUser,
-        Form<FavouriteSelectionForm>,
+        Path<(i16,)>,
+        Form<Vec<(String, String)>>,
         HttpRequest<AppState>,
     ),
 ) -> impl Responder {
-    req.state()
-        .db
-        .send(UpdatedFavouriteInfo {
-            user_id: auth.current_user.user_id,
-            data: form.into_inner(),
-            phase: 2,
-        })
-        .and_then(|update| match update {
-            Ok(()) => Ok(HttpResponse::SeeOther().header("Location", "/").finish()),
-            Err(err) => {
-                println!("{:?}", err);
-                Ok(HttpResponse::InternalServerError()
+    match parse_picks(&form) {
+        Ok(picks) => Either::A(
+            req.state()
+                .db
+                .send(UpdatedFavouriteInfo {
+                    user_id: auth.current_user.user_id,
+                    selection: FavouriteSelection {
+                        phase: path.0,
+                        picks,
+                    },
+                    source: "manual".to_string(),
+                })
+                .and_then(|update| Ok(redirect_or_error(update)))
+                .responder(),
+        ),
+        Err(error) => {
+            println!("{:?}", error);
+            Either::B(
+                HttpResponse::BadRequest()
                     .content_type("text/html")
-                    .body("An error occurred"))
-            }
-        })
-        .responder()
+                    .body("The favourites couldn't be parsed"),
+            )
+        }
+    }
 }
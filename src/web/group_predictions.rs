@@ -0,0 +1,406 @@
+use models::{Country, Group, GroupPrediction, UpdatedGroupPrediction};
+use schema::StageType;
+use scores::{user_group_points, ScoringRules};
+use templates::{Context, TEMPLATE_SERVICE};
+use web::groups::GroupTable;
+use web::{
+    app_state::{AppState, DbExecutor}, auth::CurrentUser, tournaments::viewed_tournament,
+};
+
+use actix::prelude::*;
+use actix_web::{AsyncResponder, Either, Form, FutureResponse, HttpResponse, Responder, State};
+use chrono::{DateTime, Utc};
+use diesel::{self, prelude::*};
+use failure;
+use futures::Future;
+use std::collections::BTreeMap;
+use std::{error::Error as StdError, fmt};
+
+/// Kick-off of the first group match of a tournament, group predictions can be changed until then
+pub fn group_phase_start(
+    conn: &PgConnection,
+    tournament_id: i32,
+) -> QueryResult<Option<DateTime<Utc>>> {
+    use diesel::dsl::min;
+    use schema::{matches, stages};
+
+    matches::table
+        .inner_join(stages::table)
+        .filter(stages::tournament_id.eq(tournament_id))
+        .filter(stages::stage_type.eq(StageType::Group))
+        .select(min(matches::time))
+        .first(conn)
+}
+
+/// Award the points for the group predictions of every group that is finished (and remove them
+/// for groups that aren't, in case a result was corrected)
+pub fn update_group_prediction_points(
+    conn: &PgConnection,
+    rules: &ScoringRules,
+    tables: &[GroupTable],
+) -> QueryResult<()> {
+    use diesel::pg::upsert::excluded;
+    use diesel::{delete, insert_into};
+
+    for table in tables {
+        let start = match group_phase_start(conn, table.group.tournament_id)? {
+            Some(start) => start,
+            None => continue,
+        };
+        let position = |wanted| {
+            table
+                .standings
+                .iter()
+                .find(|(standing, _country, _qualified)| standing.position == wanted)
+                .map(|(standing, _country, _qualified)| standing.country_id)
+        };
+
+        let (winner_id, runnerup_id) = match (position(1), position(2)) {
+            (Some(winner_id), Some(runnerup_id)) if table.complete => (winner_id, runnerup_id),
+            _ => {
+                use schema::user_group_points::dsl::*;
+
+                delete(user_group_points.filter(group_id.eq(table.group.group_id)))
+                    .execute(conn)?;
+                continue;
+            }
+        };
+
+        let predictions = {
+            use schema::group_predictions::dsl::*;
+
+            group_predictions
+                .filter(group_id.eq(table.group.group_id))
+                .load::<GroupPrediction>(conn)?
+        };
+
+        let points = predictions
+            .iter()
+            .map(|prediction| user_group_points(rules, prediction, &start, winner_id, runnerup_id))
+            .collect::<Vec<_>>();
+
+        {
+            use schema::user_group_points::dsl::*;
+
+            insert_into(user_group_points)
+                .values(&points)
+                .on_conflict((user_id, group_id))
+                .do_update()
+                .set((
+                    winner.eq(excluded(winner)),
+                    runnerup.eq(excluded(runnerup)),
+                    total.eq(excluded(total)),
+                ))
+                .execute(conn)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct GroupPredictionInfo {
+    groups: Vec<(Group, Vec<Country>, Option<GroupPrediction>)>,
+    deadline: Option<DateTime<Utc>>,
+}
+
+impl GroupPredictionInfo {
+    fn open(&self) -> bool {
+        self.deadline.map_or(true, |deadline| deadline > Utc::now())
+    }
+}
+
+struct FetchGroupPredictionInfo {
+    user_id: i32,
+}
+
+impl Message for FetchGroupPredictionInfo {
+    type Result = Result<GroupPredictionInfo, failure::Error>;
+}
+
+impl Handler<FetchGroupPredictionInfo> for DbExecutor {
+    type Result = Result<GroupPredictionInfo, failure::Error>;
+
+    fn handle(&mut self, msg: FetchGroupPredictionInfo, _: &mut Self::Context) -> Self::Result {
+        let tournament = viewed_tournament(&self.connection, msg.user_id)?;
+
+        let groups = {
+            use schema::groups::dsl::*;
+
+            groups
+                .filter(tournament_id.eq(tournament.tournament_id))
+                .order(name.asc())
+                .load::<Group>(&self.connection)?
+        };
+
+        let members = {
+            use schema::countries;
+            use schema::group_memberships::dsl::*;
+
+            group_memberships
+                .inner_join(countries::table)
+                .select((group_id, countries::all_columns))
+                .order((group_id, drawn_place))
+                .load::<(i32, Country)>(&self.connection)?
+        };
+
+        let mut predictions = {
+            use schema::group_predictions::dsl::*;
+
+            group_predictions
+                .filter(user_id.eq(msg.user_id))
+                .load::<GroupPrediction>(&self.connection)?
+        };
+
+        let groups = groups
+            .into_iter()
+            .map(|group| {
+                let countries = members
+                    .iter()
+                    .filter(|(group_id, _country)| *group_id == group.group_id)
+                    .map(|(_group_id, country)| country.clone())
+                    .collect();
+                let prediction = predictions
+                    .iter()
+                    .position(|prediction| prediction.group_id == group.group_id)
+                    .map(|index| predictions.remove(index));
+
+                (group, countries, prediction)
+            })
+            .collect();
+
+        Ok(GroupPredictionInfo {
+            groups,
+            deadline: group_phase_start(&self.connection, tournament.tournament_id)?,
+        })
+    }
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn edit((auth, state): (CurrentUser, State<AppState>)) -> impl Responder {
+    state
+        .db
+        .send(FetchGroupPredictionInfo {
+            user_id: auth.current_user.user_id,
+        })
+        .and_then(move |result| match result {
+            Ok(info) => {
+                let mut context = Context::new();
+                context.add("current_user", &auth.current_user);
+                context.add("groups", &info.groups);
+                context.add("deadline", &info.deadline);
+                context.add("open", &info.open());
+
+                let rendered = TEMPLATE_SERVICE.render("groups/predictions.html", &context);
+
+                match rendered {
+                    Ok(body) => Ok(HttpResponse::Ok().content_type("text/html").body(body)),
+                    Err(error) => {
+                        println!("{:?}", error);
+                        Ok(HttpResponse::InternalServerError()
+                            .content_type("text/html")
+                            .body("Something went wrong"))
+                    }
+                }
+            }
+            Err(error) => {
+                println!("{:?}", error);
+                Ok(HttpResponse::InternalServerError()
+                    .content_type("text/html")
+                    .body("Something went wrong"))
+            }
+        })
+        .responder()
+}
+
+#[derive(Debug)]
+struct GroupPredictionsClosed;
+
+impl fmt::Display for GroupPredictionsClosed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The group phase has already started")
+    }
+}
+
+impl StdError for GroupPredictionsClosed {
+    fn description(&self) -> &str {
+        "The group phase has already started"
+    }
+}
+
+#[derive(Debug)]
+struct InvalidGroupPrediction;
+
+impl fmt::Display for InvalidGroupPrediction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Winner and runner-up should be two different countries of the group")
+    }
+}
+
+impl StdError for InvalidGroupPrediction {
+    fn description(&self) -> &str {
+        "Winner and runner-up should be two different countries of the group"
+    }
+}
+
+/// Convert the submitted `winner_<group_id>` and `runnerup_<group_id>` fields into predictions,
+/// groups for which not both countries are selected are skipped
+fn parse_group_predictions(
+    user_id: i32,
+    fields: &[(String, String)],
+) -> Result<Vec<UpdatedGroupPrediction>, failure::Error> {
+    let mut selections = BTreeMap::new();
+
+    for (key, value) in fields {
+        let country_id = value.parse::<i32>()?;
+        let country_id = if country_id == 0 {
+            None
+        } else {
+            Some(country_id)
+        };
+
+        if key.starts_with("winner_") {
+            let group_id = key["winner_".len()..].parse::<i32>()?;
+            selections.entry(group_id).or_insert((None, None)).0 = country_id;
+        } else if key.starts_with("runnerup_") {
+            let group_id = key["runnerup_".len()..].parse::<i32>()?;
+            selections.entry(group_id).or_insert((None, None)).1 = country_id;
+        } else {
+            Err(InvalidGroupPrediction)?
+        }
+    }
+
+    let mut result = Vec::with_capacity(selections.len());
+    for (group_id, selection) in selections {
+        if let (Some(winner_id), Some(runnerup_id)) = selection {
+            if winner_id == runnerup_id {
+                Err(InvalidGroupPrediction)?
+            }
+
+            result.push(UpdatedGroupPrediction {
+                group_id,
+                user_id,
+                winner_id,
+                runnerup_id,
+            });
+        }
+    }
+
+    Ok(result)
+}
+
+struct UpdateGroupPredictions {
+    predictions: Vec<UpdatedGroupPrediction>,
+}
+
+impl Message for UpdateGroupPredictions {
+    type Result = Result<(), failure::Error>;
+}
+
+impl Handler<UpdateGroupPredictions> for DbExecutor {
+    type Result = Result<(), failure::Error>;
+
+    fn handle(&mut self, msg: UpdateGroupPredictions, _: &mut Self::Context) -> Self::Result {
+        // The checks are part of the transaction, so the group phase can't start in between
+        self.connection.transaction::<_, failure::Error, _>(|| {
+            let tournament_ids = {
+                use schema::groups::dsl::*;
+
+                let submitted = msg
+                    .predictions
+                    .iter()
+                    .map(|prediction| prediction.group_id)
+                    .collect::<Vec<_>>();
+
+                groups
+                    .filter(group_id.eq_any(submitted))
+                    .select(tournament_id)
+                    .distinct()
+                    .load::<i32>(&self.connection)?
+            };
+            for tournament_id in tournament_ids {
+                if let Some(deadline) = group_phase_start(&self.connection, tournament_id)? {
+                    if deadline <= Utc::now() {
+                        Err(GroupPredictionsClosed)?
+                    }
+                }
+            }
+
+            for prediction in &msg.predictions {
+                use schema::group_memberships::dsl::*;
+
+                let members = group_memberships
+                    .filter(group_id.eq(prediction.group_id))
+                    .filter(country_id.eq_any(vec![prediction.winner_id, prediction.runnerup_id]))
+                    .count()
+                    .get_result::<i64>(&self.connection)?;
+
+                if members != 2 {
+                    Err(InvalidGroupPrediction)?
+                }
+            }
+
+            {
+                use diesel::insert_into;
+                use schema::group_predictions::dsl::*;
+
+                for prediction in &msg.predictions {
+                    insert_into(group_predictions)
+                        .values(prediction)
+                        .on_conflict((group_id, user_id))
+                        .do_update()
+                        .set(prediction)
+                        .execute(&self.connection)?;
+                }
+            }
+
+            Ok(())
+        })
+    }
+}
+
+fn redirect_or_error(result: Result<(), failure::Error>) -> HttpResponse {
+    match result {
+        Ok(()) => HttpResponse::SeeOther().header("Location", "/").finish(),
+        Err(error) => {
+            println!("{:?}", error);
+            if error.downcast_ref::<GroupPredictionsClosed>().is_some() {
+                HttpResponse::Conflict()
+                    .content_type("text/plain; charset=utf-8")
+                    .body(format!("{}", error))
+            } else if error.downcast_ref::<InvalidGroupPrediction>().is_some() {
+                HttpResponse::BadRequest()
+                    .content_type("text/plain; charset=utf-8")
+                    .body(format!("{}", error))
+            } else {
+                HttpResponse::InternalServerError()
+                    .content_type("text/html")
+                    .body("Something went wrong")
+            }
+        }
+    }
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn update(
+    (auth, form, state): (CurrentUser, Form<Vec<(String, String)>>, State<AppState>),
+) -> Either<FutureResponse<HttpResponse>, HttpResponse> {
+    match parse_group_predictions(auth.current_user.user_id, &form.into_inner()) {
+        Ok(predictions) => Either::A(
+            state
+                .db
+                .send(UpdateGroupPredictions { predictions })
+                .from_err()
+                .and_then(|result| Ok(redirect_or_error(result)))
+                .responder(),
+        ),
+        Err(error) => {
+            println!("{:?}", error);
+            Either::B(
+                HttpResponse::BadRequest()
+                    .content_type("text/html")
+                    .body(format!("{}", error)),
+            )
+        }
+    }
+}
@@ -0,0 +1,280 @@
+use models::{Country, Group, GroupMembership, Match, MatchOutcome, MatchParticipant};
+use standings::{compute_standings, GroupMatchResult, TeamStanding};
+use templates::{Context, TEMPLATE_SERVICE};
+use web::{
+    app_state::{AppState, DbExecutor}, auth::CurrentUser, tournaments::viewed_tournament,
+};
+
+use actix::prelude::*;
+use actix_web::{AsyncResponder, HttpResponse, Responder, State};
+use diesel::prelude::*;
+use failure;
+use futures::Future;
+
+use std::collections::HashMap;
+
+/// Number of countries per group that go through to the knockout phase
+const QUALIFIED_PER_GROUP: i16 = 2;
+
+#[derive(Serialize, Debug)]
+pub struct GroupTable {
+    pub group: Group,
+    /// The standing and country of every member, and whether it is qualified for the knockout
+    /// phase
+    pub standings: Vec<(TeamStanding, Country, bool)>,
+    pub complete: bool,
+}
+
+/// Compute the current table of every group of a tournament, based on the outcomes of the group
+/// matches
+pub fn load_group_tables(
+    conn: &PgConnection,
+    for_tournament_id: i32,
+) -> QueryResult<Vec<GroupTable>> {
+    let groups = {
+        use schema::groups::dsl::*;
+
+        groups
+            .filter(tournament_id.eq(for_tournament_id))
+            .order(name.asc())
+            .load::<Group>(conn)?
+    };
+    let group_ids = groups.iter().map(|group| group.group_id).collect::<Vec<_>>();
+
+    let memberships = {
+        use schema::group_memberships::dsl::*;
+
+        group_memberships
+            .filter(group_id.eq_any(&group_ids))
+            .select((
+                country_id,
+                group_id,
+                drawn_place,
+                current_position,
+                qualified_for_knockout,
+            ))
+            .load::<GroupMembership>(conn)?
+    };
+
+    let countries_by_id = {
+        use schema::countries::dsl::*;
+
+        countries
+            .load::<Country>(conn)?
+            .into_iter()
+            .map(|country| (country.country_id, country))
+            .collect::<HashMap<_, _>>()
+    };
+
+    let participants_by_id = {
+        use schema::match_participants::dsl::*;
+
+        match_participants
+            .filter(group_drawn_place.is_not_null())
+            .load::<MatchParticipant>(conn)?
+            .into_iter()
+            .map(|participant| (participant.match_participant_id, participant))
+            .collect::<HashMap<_, _>>()
+    };
+
+    let games = {
+        use schema::matches::dsl::*;
+
+        matches
+            .filter(tournament_id.eq(for_tournament_id))
+            .load::<Match>(conn)?
+    };
+
+    let outcomes_by_match_id = {
+        use schema::match_outcomes::dsl::*;
+
+        match_outcomes
+            .select((
+                match_id,
+                home_score,
+                away_score,
+                time_of_first_goal,
+                home_penalties,
+                away_penalties,
+                duration,
+            ))
+            .load::<MatchOutcome>(conn)?
+            .into_iter()
+            .map(|outcome| (outcome.match_id, outcome))
+            .collect::<HashMap<_, _>>()
+    };
+
+    let mut tables = Vec::with_capacity(groups.len());
+    for group in groups {
+        let teams = memberships
+            .iter()
+            .filter(|membership| membership.group_id == group.group_id)
+            .map(|membership| (membership.country_id, membership.drawn_place))
+            .collect::<Vec<_>>();
+
+        let mut group_matches = 0;
+        let mut results = Vec::new();
+        for game in &games {
+            let (home, away) = match (
+                participants_by_id.get(&game.home_participant_id),
+                participants_by_id.get(&game.away_participant_id),
+            ) {
+                (Some(home), Some(away)) => (home, away),
+                _ => continue,
+            };
+            if home.group_id != Some(group.group_id) {
+                continue;
+            }
+
+            group_matches += 1;
+            if let (Some(home_country_id), Some(away_country_id), Some(outcome)) = (
+                home.country_id,
+                away.country_id,
+                outcomes_by_match_id.get(&game.match_id),
+            ) {
+                results.push(GroupMatchResult {
+                    home_country_id,
+                    away_country_id,
+                    home_score: outcome.home_score,
+                    away_score: outcome.away_score,
+                });
+            }
+        }
+
+        let standings = compute_standings(&teams, &results)
+            .into_iter()
+            .filter_map(|standing| {
+                let qualified = memberships.iter().any(|membership| {
+                    membership.group_id == group.group_id
+                        && membership.country_id == standing.country_id
+                        && membership.qualified_for_knockout
+                });
+                countries_by_id
+                    .get(&standing.country_id)
+                    .cloned()
+                    .map(|country| (standing, country, qualified))
+            })
+            .collect();
+
+        tables.push(GroupTable {
+            group,
+            standings,
+            complete: group_matches > 0 && results.len() == group_matches,
+        });
+    }
+
+    Ok(tables)
+}
+
+/// Recompute the group tables of a tournament and store the current position of every country
+/// in its group.
+///
+/// Once all matches of a group are played the best countries of that group are marked as
+/// qualified for the knockout phase.
+pub fn update_group_standings(
+    conn: &PgConnection,
+    for_tournament_id: i32,
+) -> QueryResult<Vec<GroupTable>> {
+    use diesel::update;
+    use schema::group_memberships::dsl::*;
+
+    let mut tables = load_group_tables(conn, for_tournament_id)?;
+
+    for table in &mut tables {
+        let complete = table.complete;
+        for (standing, _country, qualified) in &mut table.standings {
+            *qualified = complete && standing.position <= QUALIFIED_PER_GROUP;
+
+            update(group_memberships)
+                .filter(group_id.eq(table.group.group_id))
+                .filter(country_id.eq(standing.country_id))
+                .set((
+                    current_position.eq(standing.position),
+                    qualified_for_knockout.eq(*qualified),
+                ))
+                .execute(conn)?;
+        }
+    }
+
+    Ok(tables)
+}
+
+/// Fill in the countries of the knockout participants that come out of a group (as "winner" or
+/// "runnerup") once all matches of that group are played
+pub fn resolve_group_participants(conn: &PgConnection, tables: &[GroupTable]) -> QueryResult<()> {
+    use diesel::update;
+    use schema::match_participants::dsl::*;
+
+    for table in tables.iter().filter(|table| table.complete) {
+        for &(condition, position) in &[("winner", 1), ("runnerup", 2)] {
+            let qualified_country_id = table
+                .standings
+                .iter()
+                .find(|(standing, _country, _qualified)| standing.position == position)
+                .map(|(standing, _country, _qualified)| standing.country_id);
+
+            update(match_participants)
+                .filter(group_id.eq(table.group.group_id))
+                .filter(group_drawn_place.is_null())
+                .filter(result.eq(condition))
+                .set(country_id.eq(qualified_country_id))
+                .execute(conn)?;
+        }
+    }
+
+    Ok(())
+}
+
+struct FetchGroupTables {
+    user_id: i32,
+}
+
+impl Message for FetchGroupTables {
+    type Result = Result<Vec<GroupTable>, failure::Error>;
+}
+
+impl Handler<FetchGroupTables> for DbExecutor {
+    type Result = Result<Vec<GroupTable>, failure::Error>;
+
+    fn handle(&mut self, msg: FetchGroupTables, _ctx: &mut Self::Context) -> Self::Result {
+        let tournament = viewed_tournament(&self.connection, msg.user_id)?;
+
+        Ok(load_group_tables(&self.connection, tournament.tournament_id)?)
+    }
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn index((auth, state): (CurrentUser, State<AppState>)) -> impl Responder {
+    state
+        .db
+        .send(FetchGroupTables {
+            user_id: auth.current_user.user_id,
+        })
+        .and_then(move |res| {
+            Ok(match res {
+                Ok(tables) => {
+                    let mut context = Context::new();
+                    context.add("current_user", &auth.current_user);
+                    context.add("groups", &tables);
+
+                    let rendered = TEMPLATE_SERVICE.render("groups/index.html", &context);
+                    match rendered {
+                        Ok(body) => HttpResponse::Ok().content_type("text/html").body(body),
+                        Err(error) => {
+                            println!("{:?}", error);
+                            HttpResponse::InternalServerError()
+                                .content_type("text/html")
+                                .body("Something went wrong")
+                        }
+                    }
+                }
+                Err(error) => {
+                    println!("{:?}", error);
+                    HttpResponse::InternalServerError()
+                        .content_type("text/html")
+                        .body("Something went very wrong")
+                }
+            })
+        })
+        .responder()
+}
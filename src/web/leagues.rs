@@ -0,0 +1,336 @@
+use models::{League, NewLeague};
+use templates::{Context, TEMPLATE_SERVICE};
+use web::{
+    app_state::{AppState, DbExecutor}, auth::CurrentUser,
+};
+
+use actix::prelude::*;
+use actix_web::{AsyncResponder, Form, HttpResponse, Path, Responder, State};
+use diesel::{self, prelude::*};
+use failure;
+use futures::Future;
+use rand::{distributions::Alphanumeric, prelude::*};
+use std::{error::Error as StdError, fmt};
+
+const INVITE_CODE_LENGTH: usize = 10;
+
+/// Optional league filter for the leaderboards, e.g. `/scores?league=3`
+#[derive(Deserialize, Clone, Debug)]
+pub struct LeagueFilter {
+    pub league: Option<i32>,
+}
+
+/// All leagues the given user is a member of
+pub fn fetch_user_leagues(conn: &PgConnection, current_user_id: i32) -> QueryResult<Vec<League>> {
+    use schema::{league_memberships, leagues};
+
+    leagues::table
+        .inner_join(league_memberships::table)
+        .filter(league_memberships::user_id.eq(current_user_id))
+        .select(leagues::all_columns)
+        .order(leagues::name.asc())
+        .load(conn)
+}
+
+pub struct FetchUserLeagues {
+    pub user_id: i32,
+}
+
+impl Message for FetchUserLeagues {
+    type Result = Result<Vec<League>, failure::Error>;
+}
+
+impl Handler<FetchUserLeagues> for DbExecutor {
+    type Result = Result<Vec<League>, failure::Error>;
+
+    fn handle(&mut self, msg: FetchUserLeagues, _: &mut Self::Context) -> Self::Result {
+        Ok(fetch_user_leagues(&self.connection, msg.user_id)?)
+    }
+}
+
+struct FetchLeagueOverview {
+    user_id: i32,
+}
+
+impl Message for FetchLeagueOverview {
+    type Result = Result<Vec<(League, usize)>, failure::Error>;
+}
+
+impl Handler<FetchLeagueOverview> for DbExecutor {
+    type Result = Result<Vec<(League, usize)>, failure::Error>;
+
+    fn handle(&mut self, msg: FetchLeagueOverview, _: &mut Self::Context) -> Self::Result {
+        let user_leagues = fetch_user_leagues(&self.connection, msg.user_id)?;
+
+        let league_ids = user_leagues
+            .iter()
+            .map(|league| league.league_id)
+            .collect::<Vec<_>>();
+        let members = {
+            use schema::league_memberships::dsl::*;
+
+            league_memberships
+                .filter(league_id.eq_any(league_ids))
+                .select(league_id)
+                .load::<i32>(&self.connection)?
+        };
+
+        Ok(user_leagues
+            .into_iter()
+            .map(|league| {
+                let member_count = members.iter().filter(|&&id| id == league.league_id).count();
+                (league, member_count)
+            })
+            .collect())
+    }
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn index((auth, state): (CurrentUser, State<AppState>)) -> impl Responder {
+    state
+        .db
+        .send(FetchLeagueOverview {
+            user_id: auth.current_user.user_id,
+        })
+        .and_then(move |result| match result {
+            Ok(user_leagues) => {
+                let mut context = Context::new();
+                context.add("current_user", &auth.current_user);
+                context.add("leagues", &user_leagues);
+
+                let rendered = TEMPLATE_SERVICE.render("leagues/index.html", &context);
+
+                match rendered {
+                    Ok(body) => Ok(HttpResponse::Ok().content_type("text/html").body(body)),
+                    Err(error) => {
+                        println!("{:?}", error);
+                        Ok(HttpResponse::InternalServerError()
+                            .content_type("text/html")
+                            .body("Something went wrong"))
+                    }
+                }
+            }
+            Err(error) => {
+                println!("{:?}", error);
+                Ok(HttpResponse::InternalServerError()
+                    .content_type("text/html")
+                    .body("Something went wrong"))
+            }
+        })
+        .responder()
+}
+
+#[derive(Debug)]
+struct LeagueNotFound;
+
+impl fmt::Display for LeagueNotFound {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "There is no league with that invite code")
+    }
+}
+
+impl StdError for LeagueNotFound {
+    fn description(&self) -> &str {
+        "There is no league with that invite code"
+    }
+}
+
+#[derive(Debug)]
+struct EmptyLeagueName;
+
+impl fmt::Display for EmptyLeagueName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "A league needs a name")
+    }
+}
+
+impl StdError for EmptyLeagueName {
+    fn description(&self) -> &str {
+        "A league needs a name"
+    }
+}
+
+fn redirect_or_error(result: Result<(), failure::Error>) -> HttpResponse {
+    match result {
+        Ok(()) => HttpResponse::SeeOther()
+            .header("Location", "/leagues")
+            .finish(),
+        Err(error) => {
+            println!("{:?}", error);
+            if error.downcast_ref::<LeagueNotFound>().is_some()
+                || error.downcast_ref::<EmptyLeagueName>().is_some()
+            {
+                HttpResponse::BadRequest()
+                    .content_type("text/plain; charset=utf-8")
+                    .body(format!("{}", error))
+            } else {
+                HttpResponse::InternalServerError()
+                    .content_type("text/html")
+                    .body("Something went wrong")
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NewLeagueForm {
+    name: String,
+}
+
+struct CreateLeague {
+    user_id: i32,
+    name: String,
+}
+
+impl Message for CreateLeague {
+    type Result = Result<(), failure::Error>;
+}
+
+impl Handler<CreateLeague> for DbExecutor {
+    type Result = Result<(), failure::Error>;
+
+    fn handle(&mut self, msg: CreateLeague, _: &mut Self::Context) -> Self::Result {
+        use diesel::insert_into;
+        use schema::{league_memberships, leagues};
+
+        let name = msg.name.trim();
+        if name.is_empty() {
+            Err(EmptyLeagueName)?
+        }
+
+        let invite_code = thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(INVITE_CODE_LENGTH)
+            .collect::<String>();
+
+        self.connection
+            .transaction::<_, diesel::result::Error, _>(|| {
+                let league = insert_into(leagues::table)
+                    .values(&NewLeague {
+                        name,
+                        invite_code: &invite_code,
+                        created_by: msg.user_id,
+                    })
+                    .get_result::<League>(&self.connection)?;
+
+                insert_into(league_memberships::table)
+                    .values((
+                        league_memberships::league_id.eq(league.league_id),
+                        league_memberships::user_id.eq(msg.user_id),
+                    ))
+                    .execute(&self.connection)?;
+
+                Ok(())
+            })?;
+
+        Ok(())
+    }
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn create(
+    (auth, form, state): (CurrentUser, Form<NewLeagueForm>, State<AppState>),
+) -> impl Responder {
+    state
+        .db
+        .send(CreateLeague {
+            user_id: auth.current_user.user_id,
+            name: form.into_inner().name,
+        })
+        .and_then(|result| Ok(redirect_or_error(result)))
+        .responder()
+}
+
+#[derive(Deserialize, Debug)]
+pub struct JoinLeagueForm {
+    invite_code: String,
+}
+
+struct JoinLeague {
+    user_id: i32,
+    invite_code: String,
+}
+
+impl Message for JoinLeague {
+    type Result = Result<(), failure::Error>;
+}
+
+impl Handler<JoinLeague> for DbExecutor {
+    type Result = Result<(), failure::Error>;
+
+    fn handle(&mut self, msg: JoinLeague, _: &mut Self::Context) -> Self::Result {
+        use diesel::insert_into;
+        use schema::{league_memberships, leagues};
+
+        let league = leagues::table
+            .filter(leagues::invite_code.eq(msg.invite_code.trim()))
+            .first::<League>(&self.connection)
+            .optional()?;
+
+        match league {
+            Some(league) => {
+                insert_into(league_memberships::table)
+                    .values((
+                        league_memberships::league_id.eq(league.league_id),
+                        league_memberships::user_id.eq(msg.user_id),
+                    ))
+                    .on_conflict_do_nothing()
+                    .execute(&self.connection)?;
+
+                Ok(())
+            }
+            None => Err(LeagueNotFound)?,
+        }
+    }
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn join(
+    (auth, form, state): (CurrentUser, Form<JoinLeagueForm>, State<AppState>),
+) -> impl Responder {
+    state
+        .db
+        .send(JoinLeague {
+            user_id: auth.current_user.user_id,
+            invite_code: form.into_inner().invite_code,
+        })
+        .and_then(|result| Ok(redirect_or_error(result)))
+        .responder()
+}
+
+struct LeaveLeague {
+    user_id: i32,
+    league_id: i32,
+}
+
+impl Message for LeaveLeague {
+    type Result = Result<(), failure::Error>;
+}
+
+impl Handler<LeaveLeague> for DbExecutor {
+    type Result = Result<(), failure::Error>;
+
+    fn handle(&mut self, msg: LeaveLeague, _: &mut Self::Context) -> Self::Result {
+        use schema::league_memberships::dsl::*;
+
+        diesel::delete(
+            league_memberships
+                .filter(league_id.eq(msg.league_id))
+                .filter(user_id.eq(msg.user_id)),
+        ).execute(&self.connection)?;
+
+        Ok(())
+    }
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn leave((auth, path, state): (CurrentUser, Path<(i32,)>, State<AppState>)) -> impl Responder {
+    state
+        .db
+        .send(LeaveLeague {
+            user_id: auth.current_user.user_id,
+            league_id: path.0,
+        })
+        .and_then(|result| Ok(redirect_or_error(result)))
+        .responder()
+}
@@ -1,62 +1,85 @@
+use audit::{self, Change};
+use lock::{PredictionsLocked, RejectionReason};
 use models::{
-    Location, Match, MatchOutcome, MatchPrediction, MatchWithAllInfo, UpdatedPrediction, User,
-    UserMatchPoints,
+    Location, MatchOutcome, MatchPrediction, MatchWithAllInfo, PublicUser, Stage,
+    UpdatedPrediction, User, UserMatchPoints,
 };
 use templates::{Context, TEMPLATE_SERVICE};
 use web::app_state::DbExecutor;
+use web::tournaments::viewed_tournament;
 use web::{app_state::AppState, auth::CurrentUser};
 
 use actix::prelude::*;
 use actix_web::{
-    self, dev::AsyncResult, error::ResponseError, AsyncResponder, Form, FromRequest,
-    FutureResponse, HttpRequest, HttpResponse, Path, Responder,
+    self, dev::AsyncResult, error::ResponseError, http::StatusCode, AsyncResponder, Either, Form,
+    FromRequest, FutureResponse, HttpRequest, HttpResponse, Path, Responder,
 };
 use chrono::Utc;
 use diesel::{self, prelude::*};
 use failure;
-use futures::Future;
+use futures::{future, Future};
 use rand::{distributions::Uniform, prelude::*};
+use std::collections::BTreeMap;
 use std::{cmp, error::Error as StdError, fmt};
 
-fn insert_predictions(values: &Vec<UpdatedPrediction>, conn: &PgConnection) -> QueryResult<usize> {
-    use diesel::pg::upsert::excluded;
+/// Insert or replace the prediction, and record the change in the audit log. Should be called
+/// within a transaction.
+fn save_prediction(prediction: &UpdatedPrediction, conn: &PgConnection) -> QueryResult<()> {
     use schema::match_predictions::dsl::*;
 
-    diesel::insert_into(match_predictions)
-        .values(values)
+    let before = match_predictions
+        .filter(match_id.eq(prediction.match_id))
+        .filter(user_id.eq(prediction.user_id))
+        .for_update()
+        .first::<MatchPrediction>(conn)
+        .optional()?;
+
+    let after = diesel::insert_into(match_predictions)
+        .values(prediction)
         .on_conflict((match_id, user_id))
         .do_update()
-        .set((
-            home_score.eq(excluded(home_score)),
-            away_score.eq(excluded(away_score)),
-            time_of_first_goal.eq(excluded(time_of_first_goal)),
-            home_penalties.eq(excluded(home_penalties)),
-            away_penalties.eq(excluded(away_penalties)),
-            duration.eq(excluded(duration)),
-            source.eq(excluded(source)),
-        ))
-        .execute(conn)
-}
-
-struct FetchPredictionInfo {
-    user_id: i32,
-    match_id: i32,
+        .set(prediction)
+        .get_result::<MatchPrediction>(conn)?;
+
+    audit::record(
+        conn,
+        &Change {
+            subject: audit::MATCH_PREDICTION,
+            actor_id: Some(prediction.user_id),
+            user_id: Some(prediction.user_id),
+            match_id: Some(prediction.match_id),
+            source: &prediction.source,
+            before: before.as_ref(),
+            after: Some(&after),
+        },
+    )
+}
+
+fn insert_predictions(values: &[UpdatedPrediction], conn: &PgConnection) -> QueryResult<()> {
+    conn.transaction(|| {
+        for prediction in values {
+            save_prediction(prediction, conn)?;
+        }
+
+        Ok(())
+    })
+}
+
+pub struct FetchPredictionInfo {
+    pub user_id: i32,
+    pub match_id: i32,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-struct PredictionInfo {
-    match_with_info: MatchWithAllInfo,
-    location: Location,
-    prediction: Option<MatchPrediction>,
-    points: Option<UserMatchPoints>,
-    outcome: Option<MatchOutcome>,
-    other_predictions: Vec<(User, Option<MatchPrediction>, Option<UserMatchPoints>)>,
-}
-
-impl PredictionInfo {
-    fn in_future(&self) -> bool {
-        self.match_with_info.time > Utc::now()
-    }
+pub struct PredictionInfo {
+    pub match_with_info: MatchWithAllInfo,
+    pub location: Location,
+    pub prediction: Option<MatchPrediction>,
+    pub points: Option<UserMatchPoints>,
+    pub outcome: Option<MatchOutcome>,
+    pub other_predictions: Vec<(PublicUser, Option<MatchPrediction>, Option<UserMatchPoints>)>,
+    /// Whether the prediction can't be changed anymore
+    pub locked: bool,
 }
 
 impl Message for FetchPredictionInfo {
@@ -103,7 +126,9 @@ impl Handler<FetchPredictionInfo> for DbExecutor {
                 .first(&self.connection)?
         };
 
-        let (outcome, other_predictions) = if match_info.time < Utc::now() {
+        // Once nobody can change their prediction anymore, they can be shown to everyone
+        let locked = self.lock_policy.is_locked(match_info.time);
+        let (outcome, other_predictions) = if locked {
             let others = {
                 use schema::users;
                 let (match_predictions_join, prediction_present) = {
@@ -142,7 +167,13 @@ impl Handler<FetchPredictionInfo> for DbExecutor {
                     "{}",
                     diesel::debug_query::<diesel::pg::Pg, _>(&query).to_string()
                 ); */
-                query.load(&self.connection)?
+                query
+                    .load::<(User, Option<MatchPrediction>, Option<UserMatchPoints>)>(
+                        &self.connection,
+                    )?
+                    .into_iter()
+                    .map(|(user, prediction, points)| (PublicUser::from(user), prediction, points))
+                    .collect()
             };
             let outcome = {
                 use schema::match_outcomes::dsl::*;
@@ -173,6 +204,7 @@ impl Handler<FetchPredictionInfo> for DbExecutor {
             location,
             outcome,
             other_predictions,
+            locked,
         })
     }
 }
@@ -197,7 +229,7 @@ pub fn edit(
                     context.add("location", &info.location);
                     context.add("prediction", &info.prediction);
 
-                    let rendered = if info.in_future() {
+                    let rendered = if !info.locked {
                         TEMPLATE_SERVICE.render("predictions/edit.html", &context)
                     } else {
                         context.add("outcome", &info.outcome);
@@ -224,36 +256,101 @@ pub fn edit(
         .responder()
 }
 
+#[derive(Deserialize, Debug, Clone)]
+pub struct PredictionForm {
+    home_score: i16,
+    away_score: i16,
+    time_of_first_goal: i16,
+
+    #[serde(default)]
+    home_penalties: String,
+    #[serde(default)]
+    away_penalties: String,
+    #[serde(default)]
+    duration: String,
+}
+
+fn parse_optional(value: &str) -> Result<Option<i32>, failure::Error> {
+    if value.trim() == "" {
+        Ok(None)
+    } else {
+        Ok(Some(value.trim().parse()?))
+    }
+}
+
+impl PredictionForm {
+    fn to_prediction_input(&self) -> Result<PredictionInput, failure::Error> {
+        Ok(PredictionInput {
+            home_score: self.home_score,
+            away_score: self.away_score,
+            time_of_first_goal: self.time_of_first_goal,
+
+            home_penalties: parse_optional(&self.home_penalties)?,
+            away_penalties: parse_optional(&self.away_penalties)?,
+            duration: parse_optional(&self.duration)?,
+        })
+    }
+}
+
+/// A prediction as entered by a user, the knockout specific fields are ignored for group matches
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PredictionInput {
+    pub home_score: i16,
+    pub away_score: i16,
+    pub time_of_first_goal: i16,
+
+    #[serde(default)]
+    pub home_penalties: Option<i32>,
+    #[serde(default)]
+    pub away_penalties: Option<i32>,
+    #[serde(default)]
+    pub duration: Option<i32>,
+}
+
 #[derive(Debug)]
-struct TooLateToPredict;
+pub struct InvalidPrediction;
 
-impl fmt::Display for TooLateToPredict {
+impl fmt::Display for InvalidPrediction {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Too late to predict this match")
+        write!(
+            f,
+            "Knock-out predictions need a duration and either both or no penalty scores"
+        )
     }
 }
 
-impl StdError for TooLateToPredict {
+impl StdError for InvalidPrediction {
     fn description(&self) -> &str {
-        "Too late to predict this match"
+        "Knock-out predictions need a duration and either both or no penalty scores"
     }
 }
 
-#[derive(Deserialize, Debug, Clone)]
-pub struct PredictionForm {
-    home_score: i16,
-    away_score: i16,
-    time_of_first_goal: i16,
-
-    home_penalties: String,
-    away_penalties: String,
-    duration: String,
+/// The penalties and duration to store for a prediction of the match, group matches have
+/// neither of them
+fn knockout_fields(
+    stage: &Stage,
+    home_penalties: Option<i32>,
+    away_penalties: Option<i32>,
+    duration: Option<i32>,
+) -> Result<(Option<i32>, Option<i32>, Option<i32>), InvalidPrediction> {
+    if stage.is_knockout() {
+        match (home_penalties, away_penalties, duration) {
+            (home, away, Some(duration)) if home.is_some() == away.is_some() => {
+                Ok((home, away, Some(duration)))
+            }
+            _ => Err(InvalidPrediction),
+        }
+    } else {
+        Ok((None, None, None))
+    }
 }
 
-struct UpdatePredictionInfo {
-    user_id: i32,
-    match_id: i32,
-    prediction: PredictionForm,
+pub struct UpdatePredictionInfo {
+    pub user_id: i32,
+    pub match_id: i32,
+    pub prediction: PredictionInput,
+    /// How the prediction was made, e.g. `manual` or `api`
+    pub source: String,
 }
 
 impl Message for UpdatePredictionInfo {
@@ -264,28 +361,22 @@ impl Handler<UpdatePredictionInfo> for DbExecutor {
     type Result = Result<(), failure::Error>;
 
     fn handle(&mut self, msg: UpdatePredictionInfo, _: &mut Self::Context) -> Self::Result {
-        let match_info = {
-            use schema::matches::dsl::*;
+        let stage = {
+            use schema::{matches, stages};
 
-            matches
-                .filter(match_id.eq(msg.match_id))
-                .first::<Match>(&self.connection)?
+            matches::table
+                .inner_join(stages::table)
+                .filter(matches::columns::match_id.eq(msg.match_id))
+                .select(stages::all_columns)
+                .first::<Stage>(&self.connection)?
         };
 
-        let (home_penalties, away_penalties) =
-            if match_info.stage_id > 1 && msg.prediction.home_penalties == "" {
-                (None, None)
-            } else {
-                (
-                    Some(msg.prediction.home_penalties.parse()?),
-                    Some(msg.prediction.away_penalties.parse()?),
-                )
-            };
-        let duration = if match_info.stage_id > 1 {
-            Some(msg.prediction.duration.parse()?)
-        } else {
-            None
-        };
+        let (home_penalties, away_penalties, duration) = knockout_fields(
+            &stage,
+            msg.prediction.home_penalties,
+            msg.prediction.away_penalties,
+            msg.prediction.duration,
+        )?;
 
         let prediction = UpdatedPrediction {
             user_id: msg.user_id,
@@ -296,26 +387,41 @@ impl Handler<UpdatePredictionInfo> for DbExecutor {
 
             time_of_first_goal: msg.prediction.time_of_first_goal,
 
-            source: "manual".to_string(),
+            source: msg.source,
 
             home_penalties,
             away_penalties,
             duration,
         };
 
-        if match_info.time >= Utc::now() {
-            use diesel::insert_into;
-            use schema::match_predictions::dsl::*;
+        self.connection.transaction::<_, failure::Error, _>(|| {
+            self.lock_policy.ensure_open(&self.connection, &[msg.match_id])?;
+            save_prediction(&prediction, &self.connection)?;
 
-            insert_into(match_predictions)
-                .values(&prediction)
-                .on_conflict((match_id, user_id))
-                .do_update()
-                .set(&prediction)
-                .execute(&self.connection)?;
             Ok(())
-        } else {
-            Err(TooLateToPredict)?
+        })
+    }
+}
+
+/// Back to the dashboard when the prediction was saved, explain what went wrong otherwise
+fn redirect_or_error(result: Result<(), failure::Error>) -> HttpResponse {
+    match result {
+        Ok(()) => HttpResponse::SeeOther().header("Location", "/").finish(),
+        Err(error) => {
+            println!("{:?}", error);
+            if error.downcast_ref::<PredictionsLocked>().is_some() {
+                HttpResponse::Conflict()
+                    .content_type("text/plain; charset=utf-8")
+                    .body(format!("{}", error))
+            } else if error.downcast_ref::<InvalidPrediction>().is_some() {
+                HttpResponse::BadRequest()
+                    .content_type("text/plain; charset=utf-8")
+                    .body(format!("{}", error))
+            } else {
+                HttpResponse::InternalServerError()
+                    .content_type("text/html")
+                    .body("Something went wrong")
+            }
         }
     }
 }
@@ -328,29 +434,34 @@ pub fn update(
         Form<PredictionForm>,
         HttpRequest<AppState>,
     ),
-) -> FutureResponse<HttpResponse> {
-    req.state()
-        .db
-        .send(UpdatePredictionInfo {
-            user_id: auth.current_user.user_id,
-            match_id: path.0,
-            prediction: form.into_inner(),
-        })
-        .from_err()
-        .and_then(|result| match result {
-            Ok(_) => Ok(HttpResponse::SeeOther().header("Location", "/").finish()),
-            Err(error) => {
-                println!("{:?}", error);
-                Ok(HttpResponse::InternalServerError()
+) -> Either<FutureResponse<HttpResponse>, HttpResponse> {
+    match form.to_prediction_input() {
+        Ok(prediction) => Either::A(
+            req.state()
+                .db
+                .send(UpdatePredictionInfo {
+                    user_id: auth.current_user.user_id,
+                    match_id: path.0,
+                    prediction,
+                    source: "manual".to_string(),
+                })
+                .from_err()
+                .and_then(|result| Ok(redirect_or_error(result)))
+                .responder(),
+        ),
+        Err(error) => {
+            println!("{:?}", error);
+            Either::B(
+                HttpResponse::BadRequest()
                     .content_type("text/html")
-                    .body("Something went wrong"))
-            }
-        })
-        .responder()
+                    .body(format!("{}", error)),
+            )
+        }
+    }
 }
 
-struct FetchBulkPredictionInfo {
-    user_id: i32,
+pub struct FetchBulkPredictionInfo {
+    pub user_id: i32,
 }
 
 impl Message for FetchBulkPredictionInfo {
@@ -364,7 +475,10 @@ impl Handler<FetchBulkPredictionInfo> for DbExecutor {
         use schema::full_match_infos::dsl::*;
         use schema::match_predictions;
 
+        let tournament = viewed_tournament(&self.connection, msg.user_id)?;
+
         Ok(full_match_infos
+            .filter(tournament_id.eq(tournament.tournament_id))
             .left_outer_join(
                 match_predictions::table.on(match_id
                     .eq(match_predictions::match_id)
@@ -372,12 +486,128 @@ impl Handler<FetchBulkPredictionInfo> for DbExecutor {
             )
             .filter(home_country_name.is_not_null())
             .filter(away_country_name.is_not_null())
-            .filter(time.gt(Utc::now()))
+            .filter(time.gt(self.lock_policy.open_kickoffs_after()))
             .order(time.asc())
             .load::<(MatchWithAllInfo, Option<MatchPrediction>)>(&self.connection)?)
     }
 }
 
+/// One row of the bulk prediction form as it was submitted, so it can be shown again when
+/// something is wrong with it
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct BulkPredictionRow {
+    pub match_id: i32,
+    pub home_score: String,
+    pub away_score: String,
+    pub time_of_first_goal: String,
+    pub home_penalties: String,
+    pub away_penalties: String,
+    pub duration: String,
+}
+
+fn optional_to_string(value: Option<i32>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+impl BulkPredictionRow {
+    fn from_prediction(prediction: &MatchPrediction) -> BulkPredictionRow {
+        BulkPredictionRow {
+            match_id: prediction.match_id,
+            home_score: prediction.home_score.to_string(),
+            away_score: prediction.away_score.to_string(),
+            time_of_first_goal: prediction.time_of_first_goal.to_string(),
+            home_penalties: optional_to_string(prediction.home_penalties),
+            away_penalties: optional_to_string(prediction.away_penalties),
+            duration: optional_to_string(prediction.duration),
+        }
+    }
+
+    /// Whether the score and time of first goal are left empty
+    fn is_missing_score(&self) -> bool {
+        self.home_score.trim() == "" && self.away_score.trim() == ""
+            && self.time_of_first_goal.trim() == ""
+    }
+
+    /// Matches that the user didn't fill in at all are left alone. The duration doesn't count, a
+    /// knock-out row always submits one.
+    fn is_blank(&self) -> bool {
+        self.is_missing_score() && self.home_penalties.trim() == ""
+            && self.away_penalties.trim() == ""
+    }
+
+    fn to_item(&self) -> Result<MatchPredictionItem, failure::Error> {
+        Ok(MatchPredictionItem {
+            match_id: self.match_id,
+            home_score: self.home_score.trim().parse()?,
+            away_score: self.away_score.trim().parse()?,
+            time_of_first_goal: self.time_of_first_goal.trim().parse()?,
+
+            home_penalties: parse_optional(&self.home_penalties)?,
+            away_penalties: parse_optional(&self.away_penalties)?,
+            duration: parse_optional(&self.duration)?,
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct BulkEditRow<'a> {
+    #[serde(rename = "match")]
+    game: &'a MatchWithAllInfo,
+    prediction: Option<&'a MatchPrediction>,
+    values: BulkPredictionRow,
+    error: Option<&'a String>,
+}
+
+/// Render the bulk form, `submitted` and `errors` are only given when the form is shown again
+/// because some rows were invalid
+fn render_bulk_edit(
+    status: StatusCode,
+    current_user: &User,
+    matches: &[(MatchWithAllInfo, Option<MatchPrediction>)],
+    submitted: &[BulkPredictionRow],
+    errors: &BTreeMap<i32, String>,
+) -> HttpResponse {
+    let rows = matches
+        .iter()
+        .map(|(game, prediction)| BulkEditRow {
+            game,
+            prediction: prediction.as_ref(),
+            values: submitted
+                .iter()
+                .find(|row| row.match_id == game.match_id)
+                .cloned()
+                .or_else(|| prediction.as_ref().map(BulkPredictionRow::from_prediction))
+                .unwrap_or_default(),
+            error: errors.get(&game.match_id),
+        })
+        .collect::<Vec<_>>();
+    // E.g. matches that were locked while the form was being filled in
+    let other_errors = errors
+        .iter()
+        .filter(|(id, _)| !matches.iter().any(|(game, _)| game.match_id == **id))
+        .map(|(id, error)| format!("Match {}: {}", id, error))
+        .collect::<Vec<_>>();
+
+    let mut context = Context::new();
+    context.add("current_user", current_user);
+    context.add("rows", &rows);
+    context.add("other_errors", &other_errors);
+
+    let rendered = TEMPLATE_SERVICE.render("predictions/bulk_edit.html", &context);
+
+    match rendered {
+        Ok(body) => HttpResponse::build(status)
+            .content_type("text/html")
+            .body(body),
+        Err(error) => {
+            println!("{:?}", error);
+            HttpResponse::InternalServerError()
+                .content_type("text/html")
+                .body("Something went wrong")
+        }
+    }
+}
+
 #[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
 pub fn bulk_edit((auth, req): (CurrentUser, HttpRequest<AppState>)) -> impl Responder {
     req.state()
@@ -386,23 +616,13 @@ pub fn bulk_edit((auth, req): (CurrentUser, HttpRequest<AppState>)) -> impl Resp
             user_id: auth.current_user.user_id,
         })
         .and_then(move |result| match result {
-            Ok(matches) => {
-                let mut context = Context::new();
-                context.add("current_user", &auth.current_user);
-                context.add("matches", &matches);
-
-                let rendered = TEMPLATE_SERVICE.render("predictions/bulk_edit.html", &context);
-
-                match rendered {
-                    Ok(body) => Ok(HttpResponse::Ok().content_type("text/html").body(body)),
-                    Err(error) => {
-                        println!("{:?}", error);
-                        Ok(HttpResponse::InternalServerError()
-                            .content_type("text/html")
-                            .body("Something went wrong"))
-                    }
-                }
-            }
+            Ok(matches) => Ok(render_bulk_edit(
+                StatusCode::OK,
+                &auth.current_user,
+                &matches,
+                &[],
+                &BTreeMap::new(),
+            )),
             Err(error) => {
                 println!("{:?}", error);
                 Ok(HttpResponse::InternalServerError()
@@ -413,17 +633,49 @@ pub fn bulk_edit((auth, req): (CurrentUser, HttpRequest<AppState>)) -> impl Resp
         .responder()
 }
 
-#[derive(Debug)]
+#[derive(Deserialize, Debug)]
 pub struct MatchPredictionItem {
-    match_id: i32,
-    home_score: i16,
-    away_score: i16,
-    time_of_first_goal: i16,
+    pub match_id: i32,
+    pub home_score: i16,
+    pub away_score: i16,
+    pub time_of_first_goal: i16,
+
+    #[serde(default)]
+    pub home_penalties: Option<i32>,
+    #[serde(default)]
+    pub away_penalties: Option<i32>,
+    #[serde(default)]
+    pub duration: Option<i32>,
 }
 
-struct BulkUpdatePredictions {
-    user_id: i32,
-    match_predictions: Vec<MatchPredictionItem>,
+/// What is wrong with the predictions of a bulk update, per match
+#[derive(Debug, Default)]
+pub struct BulkPredictionErrors {
+    pub errors: BTreeMap<i32, String>,
+}
+
+impl fmt::Display for BulkPredictionErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let messages = self
+            .errors
+            .iter()
+            .map(|(id, error)| format!("match {}: {}", id, error))
+            .collect::<Vec<_>>();
+
+        write!(f, "Invalid predictions: {}", messages.join(", "))
+    }
+}
+
+impl StdError for BulkPredictionErrors {
+    fn description(&self) -> &str {
+        "Invalid predictions"
+    }
+}
+
+pub struct BulkUpdatePredictions {
+    pub user_id: i32,
+    pub match_predictions: Vec<MatchPredictionItem>,
+    pub source: String,
 }
 
 impl Message for BulkUpdatePredictions {
@@ -434,41 +686,75 @@ impl Handler<BulkUpdatePredictions> for DbExecutor {
     type Result = Result<(), failure::Error>;
 
     fn handle(&mut self, msg: BulkUpdatePredictions, _: &mut Self::Context) -> Self::Result {
-        use diesel::{self, insert_into};
-        use schema::match_predictions::dsl::*;
+        let match_ids = msg
+            .match_predictions
+            .iter()
+            .map(|prediction| prediction.match_id)
+            .collect::<Vec<_>>();
 
         // Update all predictions, or predict none
-        self.connection
-            .transaction::<_, diesel::result::Error, _>(|| {
-                for prediction in &msg.match_predictions {
-                    let full_prediction = UpdatedPrediction {
-                        user_id: msg.user_id,
-                        match_id: prediction.match_id,
-
-                        home_score: prediction.home_score,
-                        away_score: prediction.away_score,
+        self.connection.transaction::<_, failure::Error, _>(|| {
+            self.lock_policy.ensure_open(&self.connection, &match_ids)?;
 
-                        time_of_first_goal: prediction.time_of_first_goal,
+            let match_stages = {
+                use schema::{matches, stages};
 
-                        source: "manual".to_string(),
-
-                        home_penalties: None,
-                        away_penalties: None,
-                        duration: None,
-                    };
+                matches::table
+                    .inner_join(stages::table)
+                    .filter(matches::columns::match_id.eq_any(&match_ids))
+                    .select((matches::columns::match_id, stages::all_columns))
+                    .load::<(i32, Stage)>(&self.connection)?
+            };
 
-                    insert_into(match_predictions)
-                        .values(&full_prediction)
-                        .on_conflict((user_id, match_id))
-                        .do_update()
-                        .set(&full_prediction)
-                        .execute(&self.connection)?;
+            let mut errors = BulkPredictionErrors::default();
+            let mut full_predictions = Vec::new();
+            for prediction in &msg.match_predictions {
+                let (_, stage) = match_stages
+                    .iter()
+                    .find(|(id, _)| *id == prediction.match_id)
+                    .ok_or(diesel::result::Error::NotFound)?;
+
+                match knockout_fields(
+                    stage,
+                    prediction.home_penalties,
+                    prediction.away_penalties,
+                    prediction.duration,
+                ) {
+                    Ok((home_penalties, away_penalties, duration)) => {
+                        full_predictions.push(UpdatedPrediction {
+                            user_id: msg.user_id,
+                            match_id: prediction.match_id,
+
+                            home_score: prediction.home_score,
+                            away_score: prediction.away_score,
+
+                            time_of_first_goal: prediction.time_of_first_goal,
+
+                            source: msg.source.clone(),
+
+                            home_penalties,
+                            away_penalties,
+                            duration,
+                        })
+                    }
+                    Err(error) => {
+                        errors
+                            .errors
+                            .insert(prediction.match_id, format!("{}", error));
+                    }
                 }
+            }
 
-                Ok(())
-            })?;
+            if !errors.errors.is_empty() {
+                return Err(errors.into());
+            }
 
-        Ok(())
+            for prediction in &full_predictions {
+                save_prediction(prediction, &self.connection)?;
+            }
+
+            Ok(())
+        })
     }
 }
 
@@ -495,92 +781,90 @@ impl ResponseError for ParseError {
     }
 }
 
-impl FromRequest<AppState> for Vec<MatchPredictionItem> {
+/// The bulk prediction form, every row starts with its `match_id` field. Penalties and duration
+/// are only part of the rows of knock-out matches.
+pub struct BulkPredictionForm {
+    pub rows: Vec<BulkPredictionRow>,
+}
+
+impl FromRequest<AppState> for BulkPredictionForm {
     type Config = ();
     type Result = AsyncResult<Self, actix_web::Error>;
 
     fn from_request(req: &HttpRequest<AppState>, _cfg: &Self::Config) -> Self::Result {
         let fut = Form::<Vec<(String, String)>>::extract(req).and_then(|tuples_form| {
-            let tuples = tuples_form.into_inner();
-            let mut result = Vec::new();
-
-            fn current_to_prediction(
-                val: (String, String, String, String),
-            ) -> Result<MatchPredictionItem, failure::Error> {
-                Ok(MatchPredictionItem {
-                    match_id: val.0.parse()?,
-                    home_score: val.1.parse()?,
-                    away_score: val.2.parse()?,
-                    time_of_first_goal: val.3.parse()?,
-                })
-            }
-            fn process_current(
-                result: &mut Vec<MatchPredictionItem>,
-                val: (
-                    Option<String>,
-                    Option<String>,
-                    Option<String>,
-                    Option<String>,
-                ),
-            ) -> Result<(), failure::Error> {
-                if val.0 == None || val.1 == None || val.2 == None || val.3 == None {
-                    Err(ParseError)?
-                } else {
-                    if let Ok(prediction) = current_to_prediction((
-                        val.0.unwrap(),
-                        val.1.unwrap(),
-                        val.2.unwrap(),
-                        val.3.unwrap(),
-                    )) {
-                        result.push(prediction);
-                    }
-
-                    Ok(())
+            let mut rows: Vec<BulkPredictionRow> = Vec::new();
+
+            for (key, val) in tuples_form.into_inner() {
+                if key == "match_id" {
+                    rows.push(BulkPredictionRow {
+                        match_id: val.parse().map_err(|_| ParseError)?,
+                        ..BulkPredictionRow::default()
+                    });
+                    continue;
                 }
-            }
 
-            if tuples.len() % 4 == 0 {
-                let mut current = (None, None, None, None);
-                for (key, val) in &tuples {
-                    match key.as_str() {
-                        "match_id" => current.0 = Some(val.clone()),
-                        "home_score" => current.1 = Some(val.clone()),
-                        "away_score" => current.2 = Some(val.clone()),
-                        "time_of_first_goal" => {
-                            current.3 = Some(val.clone());
-                            process_current(&mut result, current)?;
-                            current = (None, None, None, None)
-                        }
-                        &_ => Err(ParseError)?,
-                    };
-                }
-                Ok(result)
-            } else {
-                println!(
-                    "There are {} tuples, which is not divisible by 4",
-                    tuples.len()
-                );
-                Err(ParseError)?
+                let row = rows.last_mut().ok_or(ParseError)?;
+                match key.as_str() {
+                    "home_score" => row.home_score = val,
+                    "away_score" => row.away_score = val,
+                    "time_of_first_goal" => row.time_of_first_goal = val,
+                    "home_penalties" => row.home_penalties = val,
+                    "away_penalties" => row.away_penalties = val,
+                    "duration" => row.duration = val,
+                    &_ => Err(ParseError)?,
+                };
             }
+
+            Ok(BulkPredictionForm { rows })
         });
 
         AsyncResult::async(Box::new(fut))
     }
 }
 
-#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
-pub fn bulk_update(
-    (auth, form, req): (CurrentUser, Vec<MatchPredictionItem>, HttpRequest<AppState>),
+/// Translate the errors of a bulk update into a message per match, `None` if they can't be
+/// shown next to the predictions
+fn bulk_errors(error: &failure::Error) -> Option<BTreeMap<i32, String>> {
+    if let Some(invalid) = error.downcast_ref::<BulkPredictionErrors>() {
+        Some(invalid.errors.clone())
+    } else if let Some(locked) = error.downcast_ref::<PredictionsLocked>() {
+        Some(
+            locked
+                .rejections
+                .iter()
+                .map(|rejection| {
+                    let message = match rejection.reason {
+                        RejectionReason::Locked => "Too late, this match is locked",
+                        RejectionReason::UnknownMatch => "This match doesn't exist",
+                    };
+                    (rejection.match_id, message.to_string())
+                })
+                .collect(),
+        )
+    } else {
+        None
+    }
+}
+
+/// Show the bulk form again, with the submitted values and what is wrong with them
+fn render_bulk_errors(
+    db: &Addr<DbExecutor>,
+    current_user: User,
+    submitted: Vec<BulkPredictionRow>,
+    errors: BTreeMap<i32, String>,
 ) -> FutureResponse<HttpResponse> {
-    req.state()
-        .db
-        .send(BulkUpdatePredictions {
-            user_id: auth.current_user.user_id,
-            match_predictions: form,
-        })
-        .from_err()
-        .and_then(|result| match result {
-            Ok(_) => Ok(HttpResponse::SeeOther().header("Location", "/").finish()),
+    db.send(FetchBulkPredictionInfo {
+        user_id: current_user.user_id,
+    }).from_err()
+        .and_then(move |result| match result {
+            Ok(matches) => Ok(render_bulk_edit(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                &current_user,
+                &matches,
+                &submitted,
+                &errors,
+            )),
             Err(error) => {
                 println!("{:?}", error);
                 Ok(HttpResponse::InternalServerError()
@@ -591,6 +875,55 @@ pub fn bulk_update(
         .responder()
 }
 
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn bulk_update(
+    (auth, form, req): (CurrentUser, BulkPredictionForm, HttpRequest<AppState>),
+) -> FutureResponse<HttpResponse> {
+    let db = req.state().db.clone();
+    let rows = form.rows;
+
+    let mut errors = BTreeMap::new();
+    let mut match_predictions = Vec::new();
+    for row in rows.iter().filter(|row| !row.is_blank()) {
+        if row.is_missing_score() {
+            errors.insert(
+                row.match_id,
+                "Penalties need a score and time of first goal as well".to_string(),
+            );
+            continue;
+        }
+        match row.to_item() {
+            Ok(item) => match_predictions.push(item),
+            Err(_) => {
+                errors.insert(
+                    row.match_id,
+                    "Scores, times and penalties need to be numbers".to_string(),
+                );
+            }
+        }
+    }
+
+    if !errors.is_empty() {
+        return render_bulk_errors(&db, auth.current_user, rows, errors);
+    }
+
+    db.send(BulkUpdatePredictions {
+        user_id: auth.current_user.user_id,
+        match_predictions,
+        source: "manual".to_string(),
+    }).from_err()
+        .and_then(move |result| match result {
+            Ok(()) => future::Either::A(future::ok(redirect_or_error(Ok(())))),
+            Err(error) => match bulk_errors(&error) {
+                Some(errors) => {
+                    future::Either::B(render_bulk_errors(&db, auth.current_user, rows, errors))
+                }
+                None => future::Either::A(future::ok(redirect_or_error(Err(error)))),
+            },
+        })
+        .responder()
+}
+
 struct UpdateVeryLucky {
     user_id: i32,
 }
@@ -602,11 +935,16 @@ const GOAL_POSSIBILITIES: [i16; 25] = [
     0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 4, 4, 5, 6, 7,
 ];
 
-fn generate_random_score<T: Rng>(rng: &mut T, user_id: i32, match_id: i32) -> UpdatedPrediction {
+fn generate_random_score<T: Rng>(
+    rng: &mut T,
+    user_id: i32,
+    match_id: i32,
+    stage: &Stage,
+) -> UpdatedPrediction {
     let home_score = rng.choose(&GOAL_POSSIBILITIES).unwrap_or(&0).to_owned();
     let away_score = rng.choose(&GOAL_POSSIBILITIES).unwrap_or(&0).to_owned();
     let time_of_first_goal = if home_score > 0 || away_score > 0 {
-        if match_id > 48 {
+        if stage.is_knockout() {
             rng.sample(Uniform::from(1..121))
         } else {
             rng.sample(Uniform::from(1..91))
@@ -615,8 +953,7 @@ fn generate_random_score<T: Rng>(rng: &mut T, user_id: i32, match_id: i32) -> Up
         0
     };
 
-    // Dirty hack
-    let (home_penalties, away_penalties, duration) = if match_id <= 48 {
+    let (home_penalties, away_penalties, duration) = if !stage.is_knockout() {
         println!("Predicting group match");
         (None, None, None)
     } else {
@@ -668,29 +1005,43 @@ impl Handler<UpdateVeryLucky> for DbExecutor {
     fn handle(&mut self, msg: UpdateVeryLucky, _: &mut Self::Context) -> Self::Result {
         // For each match the user didn't predict him/herself and that hasn't happened yet
         let mut rng = thread_rng();
-        let to_be_updated = {
-            use schema::match_predictions::dsl::*;
-            use schema::matches;
+        let tournament = viewed_tournament(&self.connection, msg.user_id)?;
+
+        self.connection.transaction::<_, failure::Error, _>(|| {
+            let to_be_updated = {
+                use schema::match_predictions::dsl::*;
+                use schema::{matches, stages};
+
+                matches::table
+                    .inner_join(stages::table)
+                    .left_join(
+                        match_predictions.on((matches::columns::match_id.eq(match_id))
+                            .and(user_id.eq(msg.user_id))),
+                    )
+                    .filter(matches::columns::tournament_id.eq(tournament.tournament_id))
+                    .filter(matches::columns::time.gt(self.lock_policy.open_kickoffs_after()))
+                    // Only when there is no prediction, or it was made through I feel lucky
+                    .filter(source.is_null().or(source.eq("lucky")))
+                    .select((matches::columns::match_id, stages::all_columns))
+                    .load::<(i32, Stage)>(&self.connection)?
+            };
 
-            matches::table
-                .left_join(
-                match_predictions
-                    .on((matches::columns::match_id.eq(match_id)).and(user_id.eq(msg.user_id))),
-            )
-            .filter(matches::columns::time.ge(Utc::now()))
-            .filter(source.is_null().or(source.eq("lucky"))) // Only when there is no prediction, or it was made through I feel lucky
-            .select((matches::columns::match_id,))
-            .load::<(i32,)>(&self.connection)?
-        };
+            // A match can lock between the query above and the write
+            let match_ids = to_be_updated
+                .iter()
+                .map(|&(match_id, _)| match_id)
+                .collect::<Vec<_>>();
+            self.lock_policy.ensure_open(&self.connection, &match_ids)?;
 
-        let values = to_be_updated
-            .iter()
-            .map(|(m,)| generate_random_score(&mut rng, msg.user_id, *m))
-            .collect::<Vec<_>>();
+            let values = to_be_updated
+                .iter()
+                .map(|(m, stage)| generate_random_score(&mut rng, msg.user_id, *m, stage))
+                .collect::<Vec<_>>();
 
-        insert_predictions(&values, &self.connection)?;
+            insert_predictions(&values, &self.connection)?;
 
-        Ok(())
+            Ok(())
+        })
     }
 }
 
@@ -701,15 +1052,7 @@ pub fn very_lucky((auth, req): (CurrentUser, HttpRequest<AppState>)) -> impl Res
         .send(UpdateVeryLucky {
             user_id: auth.current_user.user_id,
         })
-        .and_then(|result| match result {
-            Ok(_) => Ok(HttpResponse::SeeOther().header("Location", "/").finish()),
-            Err(error) => {
-                println!("{:?}", error);
-                Ok(HttpResponse::InternalServerError()
-                    .content_type("text/html")
-                    .body("Something went wrong"))
-            }
-        })
+        .and_then(|result| Ok(redirect_or_error(result)))
         .responder()
 }
 
@@ -725,22 +1068,30 @@ impl Handler<UpdateLucky> for DbExecutor {
     type Result = Result<(), failure::Error>;
 
     fn handle(&mut self, msg: UpdateLucky, _: &mut Self::Context) -> Self::Result {
-        // For each match the user didn't predict him/herself and that hasn't happened yet
         let mut rng = thread_rng();
-        let values = vec![generate_random_score(&mut rng, msg.user_id, msg.match_id)];
 
-        let _match_valid = {
-            use schema::matches::dsl::*;
+        self.connection.transaction::<_, failure::Error, _>(|| {
+            self.lock_policy.ensure_open(&self.connection, &[msg.match_id])?;
 
-            matches
-                .filter(time.ge(Utc::now()))
-                .select((match_id,))
-                .first::<(i32,)>(&self.connection)?
-        };
+            let stage = {
+                use schema::{matches, stages};
 
-        insert_predictions(&values, &self.connection)?;
+                matches::table
+                    .inner_join(stages::table)
+                    .filter(matches::columns::match_id.eq(msg.match_id))
+                    .select(stages::all_columns)
+                    .first::<Stage>(&self.connection)?
+            };
+            let values = vec![generate_random_score(
+                &mut rng,
+                msg.user_id,
+                msg.match_id,
+                &stage,
+            )];
+            insert_predictions(&values, &self.connection)?;
 
-        Ok(())
+            Ok(())
+        })
     }
 }
 
@@ -754,15 +1105,7 @@ pub fn lucky(
             user_id: auth.current_user.user_id,
             match_id: path.0,
         })
-        .and_then(|result| match result {
-            Ok(_) => Ok(HttpResponse::SeeOther().header("Location", "/").finish()),
-            Err(error) => {
-                println!("{:?}", error);
-                Ok(HttpResponse::InternalServerError()
-                    .content_type("text/html")
-                    .body("Something went wrong"))
-            }
-        })
+        .and_then(|result| Ok(redirect_or_error(result)))
         .responder()
 }
 
@@ -796,7 +1139,10 @@ impl Handler<FetchAllPredictionInfo> for DbExecutor {
         use schema::match_outcomes;
         use schema::match_predictions;
 
+        let tournament = viewed_tournament(&self.connection, msg.user_id)?;
+
         Ok(full_match_infos
+            .filter(tournament_id.eq(tournament.tournament_id))
             .filter(time.le(Utc::now()))
             .left_join(match_outcomes::table.on(match_outcomes::columns::match_id.eq(match_id)))
             .left_join(
@@ -855,3 +1201,33 @@ pub fn index((auth, req): (CurrentUser, HttpRequest<AppState>)) -> impl Responde
         })
         .responder()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::BulkPredictionRow;
+
+    #[test]
+    fn untouched_knockout_row_is_blank() {
+        let row = BulkPredictionRow {
+            match_id: 1,
+            duration: "90".to_string(),
+            ..Default::default()
+        };
+
+        assert!(row.is_blank());
+    }
+
+    #[test]
+    fn row_with_only_penalties_is_not_blank() {
+        let row = BulkPredictionRow {
+            match_id: 1,
+            home_penalties: "4".to_string(),
+            away_penalties: "3".to_string(),
+            duration: "120".to_string(),
+            ..Default::default()
+        };
+
+        assert!(!row.is_blank());
+        assert!(row.is_missing_score());
+    }
+}
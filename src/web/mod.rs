@@ -1,8 +1,20 @@
 pub mod admin;
+pub mod api;
 pub mod app_state;
 pub mod auth;
 pub mod dashboard;
 pub mod favourites;
+pub mod group_predictions;
+pub mod groups;
+pub mod leagues;
 pub mod match_predictions;
+pub mod password_reset;
+pub mod profiles;
+pub mod redirects;
 pub mod rules;
+pub mod score_history;
 pub mod scores;
+pub mod settings;
+pub mod tournaments;
+pub mod unsubscribe;
+pub mod validation;
@@ -0,0 +1,268 @@
+use mailer::Email;
+use models::{NewPasswordResetToken, PasswordResetToken, User};
+use templates::{Context, TEMPLATE_SERVICE};
+use web::app_state::{AppState, DbExecutor};
+use web::auth::hash_token;
+use web::validation::{password_problem, FieldErrors};
+
+use actix::prelude::*;
+use actix_web::{AsyncResponder, Either, Form, FutureResponse, HttpResponse, Path, State};
+use bcrypt::{hash, DEFAULT_COST};
+use chrono::{Duration, Utc};
+use diesel::{self, prelude::*};
+use failure;
+use futures::Future;
+use rand::{distributions::Alphanumeric, prelude::*};
+use std::{error::Error as StdError, fmt};
+
+const RESET_TOKEN_LENGTH: usize = 40;
+
+/// How long a link in a password reset email can be used
+fn reset_token_validity() -> Duration {
+    Duration::hours(2)
+}
+
+fn render(template: &str, context: &Context) -> HttpResponse {
+    match TEMPLATE_SERVICE.render(template, context) {
+        Ok(body) => HttpResponse::Ok().content_type("text/html").body(body),
+        Err(error) => {
+            println!("{}", error);
+            HttpResponse::InternalServerError()
+                .content_type("text/html")
+                .body("Something went wrong")
+        }
+    }
+}
+
+pub fn new() -> HttpResponse {
+    render("password_reset/new.html", &Context::new())
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PasswordResetRequestForm {
+    email: String,
+}
+
+struct RequestPasswordReset {
+    email: String,
+}
+
+impl Message for RequestPasswordReset {
+    type Result = Result<(), failure::Error>;
+}
+
+impl Handler<RequestPasswordReset> for DbExecutor {
+    type Result = Result<(), failure::Error>;
+
+    fn handle(&mut self, msg: RequestPasswordReset, _: &mut Self::Context) -> Self::Result {
+        use schema::{password_reset_tokens, users};
+
+        let user = users::table
+            .filter(users::email.eq(msg.email.trim()))
+            .first::<User>(&self.connection)
+            .optional()?;
+
+        // Don't tell whether someone has an account, just don't send anything
+        let user = match user {
+            Some(user) => user,
+            None => return Ok(()),
+        };
+
+        let token = thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(RESET_TOKEN_LENGTH)
+            .collect::<String>();
+
+        diesel::insert_into(password_reset_tokens::table)
+            .values(&NewPasswordResetToken {
+                user_id: user.user_id,
+                token_hash: &hash_token(&token),
+                expires_at: (Utc::now() + reset_token_validity()).naive_utc(),
+            })
+            .execute(&self.connection)?;
+
+        self.mailer.send(&Email {
+            to: user.email.clone(),
+            subject: "Reset your password".to_string(),
+            body: format!(
+                "Someone (hopefully you) asked to reset the password of your account.\n\n\
+                 You can choose a new password at {}/password_reset/{} during the next {} hours.\n\n\
+                 If you didn't ask for this, you can ignore this email.",
+                self.base_url,
+                token,
+                reset_token_validity().num_hours()
+            ),
+        })?;
+
+        Ok(())
+    }
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn create(
+    (form, state): (Form<PasswordResetRequestForm>, State<AppState>),
+) -> FutureResponse<HttpResponse> {
+    state
+        .db
+        .send(RequestPasswordReset {
+            email: form.into_inner().email,
+        })
+        .from_err()
+        .and_then(|result| match result {
+            Ok(()) => Ok(render("password_reset/sent.html", &Context::new())),
+            Err(error) => {
+                println!("{:?}", error);
+                Ok(HttpResponse::InternalServerError()
+                    .content_type("text/html")
+                    .body("Something went wrong"))
+            }
+        })
+        .responder()
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn edit(path: Path<(String,)>) -> HttpResponse {
+    let mut context = Context::new();
+    context.add("token", &path.0);
+
+    render("password_reset/edit.html", &context)
+}
+
+#[derive(Debug)]
+pub struct InvalidResetToken;
+
+impl fmt::Display for InvalidResetToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "This password reset link is invalid, expired or already used")
+    }
+}
+
+impl StdError for InvalidResetToken {
+    fn description(&self) -> &str {
+        "This password reset link is invalid, expired or already used"
+    }
+}
+
+#[derive(Debug)]
+pub struct PasswordMismatch;
+
+impl fmt::Display for PasswordMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The passwords don't match")
+    }
+}
+
+impl StdError for PasswordMismatch {
+    fn description(&self) -> &str {
+        "The passwords don't match"
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NewPasswordForm {
+    password: String,
+    password_confirmation: String,
+}
+
+struct ResetPassword {
+    token: String,
+    password: String,
+}
+
+impl Message for ResetPassword {
+    type Result = Result<(), failure::Error>;
+}
+
+impl Handler<ResetPassword> for DbExecutor {
+    type Result = Result<(), failure::Error>;
+
+    fn handle(&mut self, msg: ResetPassword, _: &mut Self::Context) -> Self::Result {
+        use schema::password_reset_tokens::dsl::*;
+        use schema::users;
+
+        let now = Utc::now().naive_utc();
+        let encrypted_password = hash(&msg.password, DEFAULT_COST)?;
+
+        self.connection.transaction::<_, failure::Error, _>(|| {
+            let reset_token = password_reset_tokens
+                .filter(token_hash.eq(hash_token(&msg.token)))
+                .filter(used_at.is_null())
+                .filter(expires_at.gt(now))
+                .for_update()
+                .first::<PasswordResetToken>(&self.connection)
+                .optional()?
+                .ok_or(InvalidResetToken)?;
+
+            let user = users::table
+                .filter(users::user_id.eq(reset_token.user_id))
+                .first::<User>(&self.connection)?;
+            let mut errors = FieldErrors::new();
+            errors.add("password", password_problem(&msg.password, &user.email));
+            if !errors.is_empty() {
+                Err(errors)?
+            }
+
+            diesel::update(users::table.filter(users::user_id.eq(reset_token.user_id)))
+                .set(users::encrypted_password.eq(&encrypted_password))
+                .execute(&self.connection)?;
+
+            // Any other link that was sent can't be used anymore either
+            diesel::update(
+                password_reset_tokens
+                    .filter(user_id.eq(reset_token.user_id))
+                    .filter(used_at.is_null()),
+            ).set(used_at.eq(now))
+                .execute(&self.connection)?;
+
+            Ok(())
+        })
+    }
+}
+
+fn render_edit(token: &str, error: &failure::Error) -> HttpResponse {
+    let mut context = Context::new();
+    context.add("token", &token);
+    context.add("error", &format!("{}", error));
+
+    render("password_reset/edit.html", &context)
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn update(
+    (path, form, state): (Path<(String,)>, Form<NewPasswordForm>, State<AppState>),
+) -> Either<FutureResponse<HttpResponse>, HttpResponse> {
+    let token = path.0.clone();
+    let form = form.into_inner();
+
+    if form.password != form.password_confirmation {
+        return Either::B(render_edit(&token, &PasswordMismatch.into()));
+    }
+
+    Either::A(
+        state
+            .db
+            .send(ResetPassword {
+                token: token.clone(),
+                password: form.password,
+            })
+            .from_err()
+            .and_then(move |result| match result {
+                Ok(()) => Ok(HttpResponse::SeeOther()
+                    .header("Location", "/login")
+                    .finish()),
+                Err(error) => {
+                    println!("{:?}", error);
+                    if error.downcast_ref::<InvalidResetToken>().is_some()
+                        || error.downcast_ref::<FieldErrors>().is_some()
+                    {
+                        Ok(render_edit(&token, &error))
+                    } else {
+                        Ok(HttpResponse::InternalServerError()
+                            .content_type("text/html")
+                            .body("Something went wrong"))
+                    }
+                }
+            })
+            .responder(),
+    )
+}
@@ -0,0 +1,343 @@
+//! What everyone can see of a participant: their predictions of the matches that can't be
+//! predicted anymore, their favourites of the phases that are closed, and some statistics. Like
+//! `other_predictions` on a match, nothing is shown that could still be copied.
+
+use models::{
+    Country, Favourite, MatchOutcome, MatchPrediction, MatchWithAllInfo, Tournament,
+    UserMatchPoints,
+};
+use ranking::{rank, tiebreak_stats, Position};
+use templates::{Context, TEMPLATE_SERVICE};
+use web::app_state::{AppState, DbExecutor};
+use web::auth::CurrentUser;
+use web::favourites::{phase_schedules, PhaseSchedule};
+use web::tournaments::viewed_tournament;
+
+use actix::prelude::*;
+use actix_web::{AsyncResponder, FutureResponse, HttpResponse, Path, State};
+use chrono::Utc;
+use diesel::{self, dsl::count_star, prelude::*};
+use failure;
+use futures::Future;
+use std::collections::HashMap;
+
+#[derive(Serialize, Debug)]
+pub struct ProfilePrediction {
+    pub game: MatchWithAllInfo,
+    pub stage: String,
+    pub prediction: MatchPrediction,
+    pub outcome: Option<MatchOutcome>,
+    pub points: Option<UserMatchPoints>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct StagePoints {
+    pub description: String,
+    pub points: i32,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ScoreLine {
+    pub home_score: i16,
+    pub away_score: i16,
+    pub times: usize,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct ProfileStats {
+    /// Predictions of matches with a known outcome
+    pub finished: usize,
+    pub exact_scores: usize,
+    /// The percentage of finished predictions with the exact score
+    pub exact_score_rate: Option<f64>,
+    /// In minutes, over the finished predictions
+    pub average_first_goal_error: Option<f64>,
+    /// In the order of the stages of the tournament
+    pub points_by_stage: Vec<StagePoints>,
+    pub most_predicted: Option<ScoreLine>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Profile {
+    pub user_id: i32,
+    pub display_name: String,
+    pub tournament: Tournament,
+    pub score: Option<i32>,
+    /// Like on the leaderboard, after the tiebreakers
+    pub position: Option<Position>,
+    /// Newest first
+    pub predictions: Vec<ProfilePrediction>,
+    /// The number of predictions that are hidden until their matches lock
+    pub hidden_predictions: i64,
+    /// The favourites of the phases that are closed
+    pub favourites: Vec<(PhaseSchedule, Vec<Option<Country>>)>,
+    pub stats: ProfileStats,
+}
+
+fn stats(predictions: &[ProfilePrediction], stage_order: &[String]) -> ProfileStats {
+    let finished = predictions
+        .iter()
+        .filter_map(|entry| entry.outcome.as_ref().map(|outcome| (&entry.prediction, outcome)))
+        .collect::<Vec<_>>();
+    let exact_scores = finished
+        .iter()
+        .filter(|&&(prediction, outcome)| {
+            prediction.home_score == outcome.home_score
+                && prediction.away_score == outcome.away_score
+        })
+        .count();
+    let first_goal_errors = finished
+        .iter()
+        .map(|&(prediction, outcome)| {
+            (i32::from(prediction.time_of_first_goal) - i32::from(outcome.time_of_first_goal))
+                .abs()
+        })
+        .sum::<i32>();
+
+    let mut stage_points = HashMap::new();
+    for entry in predictions {
+        if let Some(ref points) = entry.points {
+            *stage_points.entry(entry.stage.as_str()).or_insert(0) += points.total;
+        }
+    }
+
+    let mut score_lines = HashMap::new();
+    for entry in predictions {
+        *score_lines
+            .entry((entry.prediction.home_score, entry.prediction.away_score))
+            .or_insert(0) += 1;
+    }
+    // The most recent one wins a tie, as `predictions` is ordered newest first
+    let mut most_predicted: Option<ScoreLine> = None;
+    for entry in predictions {
+        let line = (entry.prediction.home_score, entry.prediction.away_score);
+        let times = score_lines[&line];
+        if most_predicted.as_ref().map_or(true, |best| times > best.times) {
+            most_predicted = Some(ScoreLine {
+                home_score: line.0,
+                away_score: line.1,
+                times,
+            });
+        }
+    }
+
+    ProfileStats {
+        finished: finished.len(),
+        exact_scores,
+        exact_score_rate: if finished.is_empty() {
+            None
+        } else {
+            Some(100.0 * exact_scores as f64 / finished.len() as f64)
+        },
+        average_first_goal_error: if finished.is_empty() {
+            None
+        } else {
+            Some(f64::from(first_goal_errors) / finished.len() as f64)
+        },
+        points_by_stage: stage_order
+            .iter()
+            .filter_map(|description| {
+                stage_points
+                    .get(description.as_str())
+                    .map(|&points| StagePoints {
+                        description: description.clone(),
+                        points,
+                    })
+            })
+            .collect(),
+        most_predicted,
+    }
+}
+
+pub struct FetchProfile {
+    /// The user who is looking, the profile is shown for the tournament they are looking at
+    pub viewer_id: i32,
+    pub user_id: i32,
+}
+
+impl Message for FetchProfile {
+    type Result = Result<Profile, failure::Error>;
+}
+
+impl Handler<FetchProfile> for DbExecutor {
+    type Result = Result<Profile, failure::Error>;
+
+    fn handle(&mut self, msg: FetchProfile, _: &mut Self::Context) -> Self::Result {
+        use schema::{
+            countries, favourites, full_match_infos, match_outcomes, match_predictions,
+            matches, stages, tournament_scores, user_match_points, users,
+        };
+
+        let tournament = viewed_tournament(&self.connection, msg.viewer_id)?;
+        let (user_id, display_name, login) = users::table
+            .find(msg.user_id)
+            .select((users::user_id, users::display_name, users::login))
+            .first::<(i32, Option<String>, String)>(&self.connection)?;
+
+        let scores = tournament_scores::table
+            .filter(tournament_scores::tournament_id.eq(tournament.tournament_id))
+            .select((tournament_scores::user_id, tournament_scores::score))
+            .load::<(i32, i32)>(&self.connection)?;
+        let tiebreaks = tiebreak_stats(&self.connection, tournament.tournament_id, None)?;
+        let (position, score) = rank(
+            scores,
+            |&(other_id, score)| (other_id, i64::from(score)),
+            &tiebreaks,
+            &self.scoring_rules.tiebreakers,
+        ).into_iter()
+            .find(|&(_, (other_id, _))| other_id == user_id)
+            .map_or((None, None), |(position, (_, score))| {
+                (Some(position), Some(score))
+            });
+
+        // Matches lock at (or a while before) kickoff, the predictions of the others stay hidden
+        let locked_before = self.lock_policy.open_kickoffs_after();
+        let stages_by_match = matches::table
+            .inner_join(stages::table)
+            .filter(matches::tournament_id.eq(tournament.tournament_id))
+            .select((matches::match_id, stages::description))
+            .load::<(i32, String)>(&self.connection)?
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+        let stage_order = stages::table
+            .filter(stages::tournament_id.eq(tournament.tournament_id))
+            .order(stages::stage_number)
+            .select(stages::description)
+            .load::<String>(&self.connection)?;
+
+        let predictions = full_match_infos::table
+            .filter(full_match_infos::tournament_id.eq(tournament.tournament_id))
+            .filter(full_match_infos::time.le(locked_before))
+            .inner_join(
+                match_predictions::table.on(match_predictions::match_id
+                    .eq(full_match_infos::match_id)
+                    .and(match_predictions::user_id.eq(user_id))),
+            )
+            .left_join(
+                match_outcomes::table.on(match_outcomes::match_id.eq(full_match_infos::match_id)),
+            )
+            .left_join(
+                user_match_points::table.on(user_match_points::match_id
+                    .eq(full_match_infos::match_id)
+                    .and(user_match_points::user_id.eq(user_id))),
+            )
+            .select((
+                full_match_infos::all_columns,
+                match_predictions::all_columns,
+                (
+                    match_outcomes::match_id,
+                    match_outcomes::home_score,
+                    match_outcomes::away_score,
+                    match_outcomes::time_of_first_goal,
+                    match_outcomes::home_penalties,
+                    match_outcomes::away_penalties,
+                    match_outcomes::duration,
+                ).nullable(),
+                user_match_points::all_columns.nullable(),
+            ))
+            .order((full_match_infos::time.desc(), full_match_infos::match_id.desc()))
+            .load::<(
+                MatchWithAllInfo,
+                MatchPrediction,
+                Option<MatchOutcome>,
+                Option<UserMatchPoints>,
+            )>(&self.connection)?
+            .into_iter()
+            .map(|(game, prediction, outcome, points)| ProfilePrediction {
+                stage: stages_by_match
+                    .get(&game.match_id)
+                    .cloned()
+                    .unwrap_or_default(),
+                game,
+                prediction,
+                outcome,
+                points,
+            })
+            .collect::<Vec<_>>();
+
+        let hidden_predictions = match_predictions::table
+            .inner_join(matches::table)
+            .filter(match_predictions::user_id.eq(user_id))
+            .filter(matches::tournament_id.eq(tournament.tournament_id))
+            .filter(matches::time.gt(locked_before))
+            .select(count_star())
+            .first::<i64>(&self.connection)?;
+
+        let chosen = favourites::table
+            .filter(favourites::user_id.eq(user_id))
+            .filter(favourites::tournament_id.eq(tournament.tournament_id))
+            .left_join(countries::table)
+            .order((favourites::phase, favourites::choice))
+            .load::<(Favourite, Option<Country>)>(&self.connection)?;
+        let now = Utc::now();
+        let favourites = phase_schedules(&self.connection, tournament.tournament_id)?
+            .into_iter()
+            .filter(|schedule| schedule.closes_at.map_or(false, |closes_at| closes_at <= now))
+            .map(|schedule| {
+                let picks = chosen
+                    .iter()
+                    .filter(|(favourite, _)| favourite.phase == schedule.phase)
+                    .map(|(_, country)| country.clone())
+                    .collect();
+                (schedule, picks)
+            })
+            .collect();
+
+        Ok(Profile {
+            user_id,
+            display_name: display_name.unwrap_or(login),
+            stats: stats(&predictions, &stage_order),
+            tournament,
+            score,
+            position,
+            predictions,
+            hidden_predictions,
+            favourites,
+        })
+    }
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn show(
+    (auth, path, state): (CurrentUser, Path<(i32,)>, State<AppState>),
+) -> FutureResponse<HttpResponse> {
+    state
+        .db
+        .send(FetchProfile {
+            viewer_id: auth.current_user.user_id,
+            user_id: path.0,
+        })
+        .from_err()
+        .and_then(move |result| {
+            Ok(match result {
+                Ok(profile) => {
+                    let mut context = Context::new();
+                    context.add("current_user", &auth.current_user);
+                    context.add("profile", &profile);
+
+                    match TEMPLATE_SERVICE.render("users/show.html", &context) {
+                        Ok(body) => HttpResponse::Ok().content_type("text/html").body(body),
+                        Err(error) => {
+                            println!("{:?}", error);
+                            HttpResponse::InternalServerError()
+                                .content_type("text/html")
+                                .body("Something went wrong")
+                        }
+                    }
+                }
+                Err(error) => {
+                    println!("{:?}", error);
+                    if let Some(diesel::result::Error::NotFound) = error.downcast_ref() {
+                        HttpResponse::NotFound()
+                            .content_type("text/html")
+                            .body("There is no such user")
+                    } else {
+                        HttpResponse::InternalServerError()
+                            .content_type("text/html")
+                            .body("Something went wrong")
+                    }
+                }
+            })
+        })
+        .responder()
+}
@@ -0,0 +1,68 @@
+//! The page someone originally requested is carried through the login and registration flow in a
+//! `next` parameter. It is signed with the cookie secret, so the login page can't be used to
+//! redirect people to arbitrary (external) pages.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use url::form_urlencoded;
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn signature(key: &[u8], path: &str) -> String {
+    let mut mac = HmacSha256::new_varkey(key).expect("HMAC accepts keys of any length");
+    mac.input(b"next:");
+    mac.input(path.as_bytes());
+
+    format!("{:x}", mac.result().code())
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Only paths on this site can be redirected to, `//example.com` would be a different site
+fn is_local_path(path: &str) -> bool {
+    path.starts_with('/')
+        && !path.starts_with("//")
+        && !path.starts_with("/\\")
+        && !path.chars().any(char::is_control)
+}
+
+/// Sign the given path, `None` if it is not a path that should be redirected to after login
+pub fn sign_next(key: &[u8], path: &str) -> Option<String> {
+    if is_local_path(path) && path != "/login" && path != "/register" {
+        Some(format!("{}:{}", signature(key, path), path))
+    } else {
+        None
+    }
+}
+
+/// The path of a signed `next` parameter, if the signature is valid
+pub fn verify_next(key: &[u8], next: &str) -> Option<String> {
+    let mut parts = next.splitn(2, ':');
+
+    match (parts.next(), parts.next()) {
+        (Some(given_signature), Some(path))
+            if is_local_path(path)
+                && constant_time_eq(
+                    given_signature.as_bytes(),
+                    signature(key, path).as_bytes(),
+                ) =>
+        {
+            Some(path.to_string())
+        }
+        _ => None,
+    }
+}
+
+/// `base` with the signed `next` parameter added, if there is one
+pub fn with_next(base: &str, next: Option<&str>) -> String {
+    match next {
+        Some(next) if !next.is_empty() => format!(
+            "{}?next={}",
+            base,
+            form_urlencoded::byte_serialize(next.as_bytes()).collect::<String>()
+        ),
+        _ => base.to_string(),
+    }
+}
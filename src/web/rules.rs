@@ -1,8 +1,27 @@
-use actix_web::{HttpResponse, Responder};
+use actix_web::{HttpResponse, Responder, State};
 use templates::{Context, TEMPLATE_SERVICE};
+use web::app_state::AppState;
 
-pub fn show() -> impl Responder {
-    let context = Context::new();
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn show(state: State<AppState>) -> impl Responder {
+    let rules = &state.scoring_rules;
+
+    let mut context = Context::new();
+    context.add("rules", rules);
+    context.add("exact_score_points", &rules.exact_score_points());
+    context.add("exact_penalties_points", &rules.exact_penalties_points());
+    context.add(
+        "time_of_first_goal_window",
+        &rules.time_of_first_goal_window(),
+    );
+    context.add(
+        "tiebreakers",
+        &rules
+            .tiebreakers
+            .iter()
+            .map(|tiebreaker| tiebreaker.description())
+            .collect::<Vec<_>>(),
+    );
 
     let rendered = TEMPLATE_SERVICE.render("rules.html", &context);
     match rendered {
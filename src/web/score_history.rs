@@ -0,0 +1,366 @@
+//! How the leaderboard developed over a tournament: the points and rank of every user after
+//! each match day, the day being the UTC date of the kickoff. Group points count from the day
+//! the last match of the group was played, like on the leaderboard of `/scores`.
+
+use ranking::{rank, tiebreak_stats, Tiebreaker};
+use web::app_state::DbExecutor;
+use web::tournaments::viewed_tournament;
+
+use actix::prelude::*;
+use chrono::{DateTime, NaiveDate, Utc};
+use diesel::prelude::*;
+use failure;
+use std::collections::HashMap;
+
+const CHART_WIDTH: f64 = 800.0;
+const CHART_HEIGHT: f64 = 300.0;
+const CHART_PADDING: f64 = 30.0;
+
+mod group_completions {
+    use chrono::{DateTime, Utc};
+    use diesel::sql_types::*;
+
+    #[derive(QueryableByName)]
+    pub struct GroupCompletion {
+        #[sql_type = "Integer"]
+        pub group_id: i32,
+        #[sql_type = "Timestamptz"]
+        pub completed_at: DateTime<Utc>,
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct MatchDayStanding {
+    pub date: NaiveDate,
+    pub points: i32,
+    pub rank: usize,
+    /// Whether other users are level on points and all tiebreakers
+    pub shared: bool,
+    /// Positions gained since the previous match day, negative when the user dropped
+    pub delta: i32,
+}
+
+#[derive(Serialize, Debug)]
+pub struct UserHistory {
+    pub user_id: i32,
+    pub display_name: String,
+    /// One entry for every match day of the history
+    pub match_days: Vec<MatchDayStanding>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ScoreHistory {
+    pub match_days: Vec<NaiveDate>,
+    /// Ordered by the rank after the last match day
+    pub users: Vec<UserHistory>,
+}
+
+impl ScoreHistory {
+    /// The positions every user gained on the last match day, by user id
+    pub fn latest_deltas(&self) -> HashMap<i32, i32> {
+        self.users
+            .iter()
+            .filter_map(|user| {
+                user.match_days
+                    .last()
+                    .map(|standing| (user.user_id, standing.delta))
+            })
+            .collect()
+    }
+}
+
+/// The history of the users that took part in the tournament, only the members of `league_id`
+/// when it is given, ranked among themselves. Every match day the `tiebreakers` look at the
+/// matches up to that day.
+pub fn score_history(
+    conn: &PgConnection,
+    for_tournament_id: i32,
+    league_id: Option<i32>,
+    tiebreakers: &[Tiebreaker],
+) -> Result<ScoreHistory, failure::Error> {
+    use diesel::sql_query;
+    use diesel::sql_types::Integer;
+    use schema::{
+        groups, league_memberships, match_outcomes, matches, tournament_scores,
+        user_group_points, user_match_points, users,
+    };
+
+    let mut players = users::table
+        .inner_join(tournament_scores::table)
+        .filter(tournament_scores::tournament_id.eq(for_tournament_id))
+        .select((users::user_id, users::display_name, users::login))
+        .order(users::user_id)
+        .into_boxed();
+    if let Some(league_id) = league_id {
+        players = players.filter(
+            users::user_id.eq_any(
+                league_memberships::table
+                    .filter(league_memberships::league_id.eq(league_id))
+                    .select(league_memberships::user_id),
+            ),
+        );
+    }
+    let players = players.load::<(i32, Option<String>, String)>(conn)?;
+
+    let played = matches::table
+        .inner_join(match_outcomes::table)
+        .filter(matches::tournament_id.eq(for_tournament_id))
+        .select((matches::match_id, matches::time))
+        .order(matches::time)
+        .load::<(i32, DateTime<Utc>)>(conn)?;
+    let match_day = |time: &DateTime<Utc>| time.naive_utc().date();
+    let mut match_days = played
+        .iter()
+        .map(|&(_, ref time)| match_day(time))
+        .collect::<Vec<_>>();
+    match_days.dedup();
+
+    let day_of_match = played
+        .iter()
+        .map(|&(match_id, ref time)| (match_id, match_day(time)))
+        .collect::<HashMap<_, _>>();
+    let match_ids = played.iter().map(|&(match_id, _)| match_id).collect::<Vec<_>>();
+    let match_points = user_match_points::table
+        .filter(user_match_points::match_id.eq_any(&match_ids))
+        .select((
+            user_match_points::user_id,
+            user_match_points::match_id,
+            user_match_points::total,
+        ))
+        .load::<(i32, i32, i32)>(conn)?;
+
+    let day_of_group = sql_query(
+        "
+        SELECT participants.group_id, max(matches.time) AS completed_at
+        FROM matches
+             INNER JOIN match_participants AS participants
+               ON matches.home_participant_id = participants.match_participant_id
+        WHERE participants.group_drawn_place IS NOT NULL
+          AND matches.tournament_id = $1
+        GROUP BY participants.group_id
+        ",
+    ).bind::<Integer, _>(for_tournament_id)
+        .load::<group_completions::GroupCompletion>(conn)?
+        .into_iter()
+        .map(|group| (group.group_id, match_day(&group.completed_at)))
+        .collect::<HashMap<_, _>>();
+    let group_points = user_group_points::table
+        .inner_join(groups::table)
+        .filter(groups::tournament_id.eq(for_tournament_id))
+        .select((
+            user_group_points::user_id,
+            user_group_points::group_id,
+            user_group_points::total,
+        ))
+        .load::<(i32, i32, i32)>(conn)?;
+
+    // The points earned by every user on every match day. Points of a group that completes on
+    // a day without finished matches count on the next match day.
+    let earned = {
+        let counting_day =
+            |day: NaiveDate| match_days.iter().find(|&&other| other >= day).cloned();
+        let mut earned = HashMap::new();
+        for &(user_id, match_id, total) in &match_points {
+            if let Some(day) = day_of_match.get(&match_id).and_then(|&day| counting_day(day)) {
+                *earned.entry((user_id, day)).or_insert(0) += total;
+            }
+        }
+        for &(user_id, group_id, total) in &group_points {
+            if let Some(day) = day_of_group.get(&group_id).and_then(|&day| counting_day(day)) {
+                *earned.entry((user_id, day)).or_insert(0) += total;
+            }
+        }
+        earned
+    };
+
+    let mut points = players
+        .iter()
+        .map(|&(user_id, _, _)| (user_id, 0))
+        .collect::<HashMap<_, _>>();
+    let mut previous_ranks: Option<HashMap<i32, usize>> = None;
+    let mut standings = HashMap::new();
+    for day in &match_days {
+        for (user_id, total) in points.iter_mut() {
+            *total += earned.get(&(*user_id, *day)).cloned().unwrap_or(0);
+        }
+
+        let last_kickoff = played
+            .iter()
+            .map(|&(_, time)| time)
+            .filter(|time| match_day(time) == *day)
+            .last();
+        let stats = tiebreak_stats(conn, for_tournament_id, last_kickoff)?;
+        let ranked = rank(
+            points.iter().map(|(&user_id, &total)| (user_id, total)).collect(),
+            |&(user_id, total)| (user_id, i64::from(total)),
+            &stats,
+            tiebreakers,
+        );
+
+        let mut current_ranks = HashMap::new();
+        for (position, (user_id, total)) in ranked {
+            let delta = previous_ranks
+                .as_ref()
+                .map_or(0, |previous| previous[&user_id] as i32 - position.rank as i32);
+            standings
+                .entry(user_id)
+                .or_insert_with(Vec::new)
+                .push(MatchDayStanding {
+                    date: *day,
+                    points: total,
+                    rank: position.rank,
+                    shared: position.shared,
+                    delta,
+                });
+            current_ranks.insert(user_id, position.rank);
+        }
+        previous_ranks = Some(current_ranks);
+    }
+
+    let mut users = players
+        .into_iter()
+        .map(|(user_id, display_name, login)| UserHistory {
+            user_id,
+            display_name: display_name.unwrap_or(login),
+            match_days: standings.remove(&user_id).unwrap_or_default(),
+        })
+        .collect::<Vec<_>>();
+    users.sort_by_key(|user| user.match_days.last().map(|standing| standing.rank));
+
+    Ok(ScoreHistory { match_days, users })
+}
+
+pub struct FetchScoreHistory {
+    pub user_id: i32,
+    pub league_id: Option<i32>,
+}
+
+impl Message for FetchScoreHistory {
+    type Result = Result<ScoreHistory, failure::Error>;
+}
+
+impl Handler<FetchScoreHistory> for DbExecutor {
+    type Result = Result<ScoreHistory, failure::Error>;
+
+    fn handle(&mut self, msg: FetchScoreHistory, _: &mut Self::Context) -> Self::Result {
+        let tournament = viewed_tournament(&self.connection, msg.user_id)?;
+
+        score_history(
+            &self.connection,
+            tournament.tournament_id,
+            msg.league_id,
+            &self.scoring_rules.tiebreakers,
+        )
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct ChartLabel {
+    pub x: f64,
+    pub y: f64,
+    pub text: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ChartLine {
+    pub name: String,
+    pub color: String,
+    /// The `points` attribute of an SVG polyline
+    pub points: String,
+}
+
+/// A line chart that is rendered as SVG by the template
+#[derive(Serialize, Debug)]
+pub struct Chart {
+    pub width: f64,
+    pub height: f64,
+    pub x_labels: Vec<ChartLabel>,
+    pub y_labels: Vec<ChartLabel>,
+    pub lines: Vec<ChartLine>,
+}
+
+fn chart<F>(history: &ScoreHistory, y_labels: Vec<(f64, String)>, value: F) -> Chart
+where
+    F: Fn(&MatchDayStanding) -> f64,
+{
+    let days = history.match_days.len();
+    let x = |index: usize| {
+        if days > 1 {
+            CHART_PADDING + index as f64 * (CHART_WIDTH - 2.0 * CHART_PADDING) / (days - 1) as f64
+        } else {
+            CHART_WIDTH / 2.0
+        }
+    };
+    // `value` is between 0 (bottom) and 1 (top)
+    let y = |fraction: f64| {
+        CHART_HEIGHT - CHART_PADDING - fraction * (CHART_HEIGHT - 2.0 * CHART_PADDING)
+    };
+
+    Chart {
+        width: CHART_WIDTH,
+        height: CHART_HEIGHT,
+        x_labels: history
+            .match_days
+            .iter()
+            .enumerate()
+            .map(|(index, day)| ChartLabel {
+                x: x(index),
+                y: CHART_HEIGHT - CHART_PADDING / 3.0,
+                text: day.format("%d/%m").to_string(),
+            })
+            .collect(),
+        y_labels: y_labels
+            .into_iter()
+            .map(|(fraction, text)| ChartLabel {
+                x: CHART_PADDING / 3.0,
+                y: y(fraction),
+                text,
+            })
+            .collect(),
+        lines: history
+            .users
+            .iter()
+            .enumerate()
+            .map(|(index, user)| ChartLine {
+                name: user.display_name.clone(),
+                // Spread the hues, so neighbouring users get clearly different colours
+                color: format!("hsl({}, 60%, 45%)", (index * 137) % 360),
+                points: user
+                    .match_days
+                    .iter()
+                    .enumerate()
+                    .map(|(index, standing)| format!("{:.1},{:.1}", x(index), y(value(standing))))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            })
+            .collect(),
+    }
+}
+
+/// The points of every user over the tournament
+pub fn points_chart(history: &ScoreHistory) -> Chart {
+    let max = history
+        .users
+        .iter()
+        .flat_map(|user| user.match_days.iter().map(|standing| standing.points))
+        .max()
+        .unwrap_or(0)
+        .max(1);
+
+    chart(
+        history,
+        vec![(0.0, "0".to_string()), (1.0, max.to_string())],
+        |standing| f64::from(standing.points) / f64::from(max),
+    )
+}
+
+/// The position of every user over the tournament, the leader at the top
+pub fn positions_chart(history: &ScoreHistory) -> Chart {
+    let last = history.users.len().max(2);
+
+    chart(
+        history,
+        vec![(1.0, "1".to_string()), (0.0, last.to_string())],
+        |standing| (last - standing.rank) as f64 / (last - 1) as f64,
+    )
+}
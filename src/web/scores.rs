@@ -1,10 +1,13 @@
+use ranking::{rank, tiebreak_stats, Position};
 use templates::{Context, TEMPLATE_SERVICE};
+use web::score_history::{points_chart, positions_chart, FetchScoreHistory};
+use web::tournaments::viewed_tournament;
 use web::{
-    app_state::{AppState, DbExecutor}, auth::CurrentUser,
+    app_state::{AppState, DbExecutor}, auth::CurrentUser, leagues::FetchUserLeagues,
 };
 
 use actix::prelude::*;
-use actix_web::{AsyncResponder, HttpResponse, Query, Responder, State};
+use actix_web::{AsyncResponder, FutureResponse, HttpResponse, Query, State};
 use futures::Future;
 
 use chrono::{DateTime, NaiveDateTime, Utc};
@@ -16,6 +19,8 @@ mod user_points {
 
     #[derive(QueryableByName, Serialize, Deserialize)]
     pub struct UserPoints {
+        #[sql_type = "Integer"]
+        pub user_id: i32,
         #[sql_type = "Text"]
         pub display_name: String,
         #[sql_type = "BigInt"]
@@ -25,46 +30,105 @@ mod user_points {
         #[sql_type = "BigInt"]
         pub time_of_first_goal: i64,
         #[sql_type = "BigInt"]
+        pub group_predictions: i64,
+        #[sql_type = "BigInt"]
         pub score: i64,
     }
 }
 
+/// A row of the leaderboard, in the order of the tiebreakers
+#[derive(Serialize)]
+pub struct RankedUserPoints {
+    pub position: Position,
+    #[serde(flatten)]
+    pub points: user_points::UserPoints,
+}
+
+/// Order the leaderboard by score and the tiebreakers, looking only at the matches up to `up_to`
+/// like the points do
+fn rank_leaders(
+    db: &DbExecutor,
+    leaders: Vec<user_points::UserPoints>,
+    tournament_id: i32,
+    up_to: Option<DateTime<Utc>>,
+) -> Result<Vec<RankedUserPoints>, failure::Error> {
+    let stats = tiebreak_stats(&db.connection, tournament_id, up_to)?;
+
+    Ok(rank(
+        leaders,
+        |points| (points.user_id, points.score),
+        &stats,
+        &db.scoring_rules.tiebreakers,
+    ).into_iter()
+        .map(|(position, points)| RankedUserPoints { position, points })
+        .collect())
+}
+
 #[derive(Deserialize, Clone)]
 pub struct FetchLeaderBoard {
-    up_to: Option<i64>,
+    pub up_to: Option<i64>,
+    pub league: Option<i32>,
+    /// The leaderboard is the one of the tournament this user is looking at
+    #[serde(skip)]
+    pub user_id: i32,
 }
 
 impl Message for FetchLeaderBoard {
-    type Result = Result<(Vec<user_points::UserPoints>, Option<DateTime<Utc>>), failure::Error>;
+    type Result = Result<(Vec<RankedUserPoints>, Option<DateTime<Utc>>), failure::Error>;
 }
 
 impl Handler<FetchLeaderBoard> for DbExecutor {
-    type Result = Result<(Vec<user_points::UserPoints>, Option<DateTime<Utc>>), failure::Error>;
+    type Result = Result<(Vec<RankedUserPoints>, Option<DateTime<Utc>>), failure::Error>;
 
     fn handle(&mut self, msg: FetchLeaderBoard, _: &mut Self::Context) -> Self::Result {
         use diesel::sql_query;
-        use diesel::sql_types::Nullable;
-        use diesel::sql_types::Timestamptz;
+        use diesel::sql_types::{Integer, Nullable, Timestamptz};
+
+        let tournament = viewed_tournament(&self.connection, msg.user_id)?;
 
         if let Some(up_to) = msg.up_to {
             let up_to_chrono =
                 DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(up_to, 0), Utc);
+            // Group points only count once the last match of the group has been played
             let leaders = sql_query(
                 "
-            SELECT users.display_name,
+            SELECT users.user_id,
+                   users.display_name,
                    sum(prediction) as prediction,
                    sum(favourites) as favourites,
                    sum(time_of_first_goal) as time_of_first_goal,
-                   sum(total) as score
+                   coalesce(group_points.total, 0) as group_predictions,
+                   sum(user_match_points.total) + coalesce(group_points.total, 0) as score
             FROM users
                  INNER JOIN user_match_points ON users.user_id = user_match_points.user_id
                  INNER JOIN matches ON user_match_points.match_id = matches.match_id
+                 LEFT JOIN (
+                     SELECT user_id, sum(total) as total
+                     FROM user_group_points
+                     WHERE group_id IN (
+                         SELECT participants.group_id
+                         FROM matches
+                              INNER JOIN match_participants AS participants
+                                ON matches.home_participant_id = participants.match_participant_id
+                         WHERE participants.group_drawn_place IS NOT NULL
+                           AND matches.tournament_id = $3
+                         GROUP BY participants.group_id
+                         HAVING max(matches.time) <= $1
+                     )
+                     GROUP BY user_id
+                 ) AS group_points ON users.user_id = group_points.user_id
             WHERE matches.time <= $1
-            GROUP BY users.user_id
-            ORDER BY sum(total) DESC
+              AND matches.tournament_id = $3
+              AND ($2::integer IS NULL OR users.user_id IN (
+                  SELECT user_id FROM league_memberships WHERE league_id = $2
+              ))
+            GROUP BY users.user_id, group_points.total
+            ORDER BY sum(user_match_points.total) + coalesce(group_points.total, 0) DESC
             ",
             ).bind::<Timestamptz, _>(up_to_chrono)
-                .load(&self.connection)?;
+                .bind::<Nullable<Integer>, _>(msg.league)
+                .bind::<Integer, _>(tournament.tournament_id)
+                .load::<user_points::UserPoints>(&self.connection)?;
 
             let time = {
                 use diesel::dsl::sql;
@@ -74,25 +138,43 @@ impl Handler<FetchLeaderBoard> for DbExecutor {
                 matches
                     .select(sql::<Nullable<Timestamptz>>("max(time) as time"))
                     .inner_join(match_outcomes)
+                    .filter(tournament_id.eq(tournament.tournament_id))
                     .filter(time.lt(up_to_chrono))
                     .first(&self.connection)?
             };
 
+            let leaders =
+                rank_leaders(self, leaders, tournament.tournament_id, Some(up_to_chrono))?;
             Ok((leaders, time))
         } else {
             let leaders = sql_query(
                 "
-            SELECT users.display_name,
+            SELECT users.user_id,
+                   users.display_name,
                    sum(prediction) as prediction,
                    sum(favourites) as favourites,
                    sum(time_of_first_goal) as time_of_first_goal,
-                   sum(total) as score
+                   coalesce(group_points.total, 0) as group_predictions,
+                   sum(user_match_points.total) + coalesce(group_points.total, 0) as score
             FROM users
                  INNER JOIN user_match_points ON users.user_id = user_match_points.user_id
-            GROUP BY users.user_id
-            ORDER BY sum(total) DESC
+                 INNER JOIN matches ON user_match_points.match_id = matches.match_id
+                 LEFT JOIN (
+                     SELECT user_id, sum(total) as total
+                     FROM user_group_points
+                     WHERE group_id IN (SELECT group_id FROM groups WHERE tournament_id = $2)
+                     GROUP BY user_id
+                 ) AS group_points ON users.user_id = group_points.user_id
+            WHERE matches.tournament_id = $2
+              AND ($1::integer IS NULL OR users.user_id IN (
+                  SELECT user_id FROM league_memberships WHERE league_id = $1
+              ))
+            GROUP BY users.user_id, group_points.total
+            ORDER BY sum(user_match_points.total) + coalesce(group_points.total, 0) DESC
             ",
-            ).load(&self.connection)?;
+            ).bind::<Nullable<Integer>, _>(msg.league)
+                .bind::<Integer, _>(tournament.tournament_id)
+                .load::<user_points::UserPoints>(&self.connection)?;
 
             let time = {
                 use diesel::dsl::sql;
@@ -102,9 +184,11 @@ impl Handler<FetchLeaderBoard> for DbExecutor {
                 matches
                     .select(sql::<Nullable<Timestamptz>>("max(time) as time"))
                     .inner_join(match_outcomes)
+                    .filter(tournament_id.eq(tournament.tournament_id))
                     .first(&self.connection)?
             };
 
+            let leaders = rank_leaders(self, leaders, tournament.tournament_id, None)?;
             Ok((leaders, time))
         }
     }
@@ -113,17 +197,46 @@ impl Handler<FetchLeaderBoard> for DbExecutor {
 #[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
 pub fn index(
     (auth, query, state): (CurrentUser, Query<FetchLeaderBoard>, State<AppState>),
-) -> impl Responder {
-    let data = query.into_inner();
+) -> FutureResponse<HttpResponse> {
+    let mut data = query.into_inner();
+    data.user_id = auth.current_user.user_id;
+    let db = state.db.clone();
 
     state
         .db
-        .send(data.clone())
-        .and_then(move |res| {
+        .send(FetchUserLeagues {
+            user_id: auth.current_user.user_id,
+        })
+        .from_err()
+        .and_then(move |leagues| {
+            // Only show leaderboards of leagues the user is a member of
+            let leagues = leagues.unwrap_or_else(|error| {
+                println!("{:?}", error);
+                Vec::new()
+            });
+            let current_league = leagues
+                .iter()
+                .find(|league| Some(league.league_id) == data.league)
+                .cloned();
+            data.league = current_league.as_ref().map(|league| league.league_id);
+
+            let history = db.send(FetchScoreHistory {
+                user_id: data.user_id,
+                league_id: data.league,
+            });
+
+            db.send(data.clone())
+                .from_err()
+                .join(history.from_err())
+                .map(move |(res, history)| (res, history, data, leagues, current_league))
+        })
+        .and_then(move |(res, history, data, leagues, current_league)| {
             Ok(match res {
                 Ok((leader_board, previous)) => {
                     let mut context = Context::new();
                     context.add("current_user", &auth.current_user);
+                    context.add("leagues", &leagues);
+                    context.add("current_league", &current_league);
                     context.add("leader_board", &leader_board);
                     context.add(
                         "current",
@@ -132,6 +245,16 @@ pub fn index(
                         }),
                     );
                     context.add("previous", &previous);
+                    // The charts are a nice extra, the leaderboard is still shown without them
+                    let charts = match history {
+                        Ok(history) => Some((points_chart(&history), positions_chart(&history))),
+                        Err(error) => {
+                            println!("{:?}", error);
+                            None
+                        }
+                    };
+                    context.add("points_chart", &charts.as_ref().map(|charts| &charts.0));
+                    context.add("positions_chart", &charts.as_ref().map(|charts| &charts.1));
 
                     let rendered = TEMPLATE_SERVICE.render("scores/index.html", &context);
                     match rendered {
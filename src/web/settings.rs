@@ -0,0 +1,440 @@
+use models::{ApiToken, NewApiToken, User};
+use templates::{Context, TEMPLATE_SERVICE};
+use web::password_reset::PasswordMismatch;
+use web::validation::{password_problem, FieldErrors, RegistrationPolicy};
+use web::{
+    app_state::{AppState, DbExecutor}, auth::{display_name_taken, hash_token, CurrentUser},
+};
+
+use actix::prelude::*;
+use actix_web::{AsyncResponder, Form, HttpResponse, Path, Responder, State};
+use bcrypt::{hash, verify, DEFAULT_COST};
+use diesel::{self, prelude::*};
+use failure;
+use futures::Future;
+use rand::{distributions::Alphanumeric, prelude::*};
+use std::{error::Error as StdError, fmt};
+
+const API_TOKEN_LENGTH: usize = 40;
+
+fn fetch_api_tokens(conn: &PgConnection, current_user_id: i32) -> QueryResult<Vec<ApiToken>> {
+    use schema::api_tokens::dsl::*;
+
+    api_tokens
+        .filter(user_id.eq(current_user_id))
+        .order(created_at.asc())
+        .load(conn)
+}
+
+/// Render the settings page, `new_token` is only passed right after it was created, as we only
+/// store its hash
+fn render_settings(
+    current_user: &User,
+    tokens: &[ApiToken],
+    new_token: Option<&str>,
+) -> HttpResponse {
+    let mut context = Context::new();
+    context.add("current_user", current_user);
+    context.add("api_tokens", &tokens);
+    context.add("new_token", &new_token);
+
+    let rendered = TEMPLATE_SERVICE.render("settings/index.html", &context);
+
+    match rendered {
+        Ok(body) => HttpResponse::Ok().content_type("text/html").body(body),
+        Err(error) => {
+            println!("{:?}", error);
+            HttpResponse::InternalServerError()
+                .content_type("text/html")
+                .body("Something went wrong")
+        }
+    }
+}
+
+struct FetchApiTokens {
+    user_id: i32,
+}
+
+impl Message for FetchApiTokens {
+    type Result = Result<Vec<ApiToken>, failure::Error>;
+}
+
+impl Handler<FetchApiTokens> for DbExecutor {
+    type Result = Result<Vec<ApiToken>, failure::Error>;
+
+    fn handle(&mut self, msg: FetchApiTokens, _: &mut Self::Context) -> Self::Result {
+        Ok(fetch_api_tokens(&self.connection, msg.user_id)?)
+    }
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn index((auth, state): (CurrentUser, State<AppState>)) -> impl Responder {
+    state
+        .db
+        .send(FetchApiTokens {
+            user_id: auth.current_user.user_id,
+        })
+        .and_then(move |result| match result {
+            Ok(tokens) => Ok(render_settings(&auth.current_user, &tokens, None)),
+            Err(error) => {
+                println!("{:?}", error);
+                Ok(HttpResponse::InternalServerError()
+                    .content_type("text/html")
+                    .body("Something went wrong"))
+            }
+        })
+        .responder()
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NewApiTokenForm {
+    name: String,
+}
+
+struct CreateApiToken {
+    user_id: i32,
+    name: String,
+}
+
+impl Message for CreateApiToken {
+    type Result = Result<(String, Vec<ApiToken>), failure::Error>;
+}
+
+impl Handler<CreateApiToken> for DbExecutor {
+    type Result = Result<(String, Vec<ApiToken>), failure::Error>;
+
+    fn handle(&mut self, msg: CreateApiToken, _: &mut Self::Context) -> Self::Result {
+        use schema::api_tokens;
+
+        let token = thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(API_TOKEN_LENGTH)
+            .collect::<String>();
+
+        diesel::insert_into(api_tokens::table)
+            .values(&NewApiToken {
+                user_id: msg.user_id,
+                name: msg.name.trim(),
+                token_hash: &hash_token(&token),
+            })
+            .execute(&self.connection)?;
+
+        Ok((token, fetch_api_tokens(&self.connection, msg.user_id)?))
+    }
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn create_token(
+    (auth, form, state): (CurrentUser, Form<NewApiTokenForm>, State<AppState>),
+) -> impl Responder {
+    state
+        .db
+        .send(CreateApiToken {
+            user_id: auth.current_user.user_id,
+            name: form.into_inner().name,
+        })
+        .and_then(move |result| match result {
+            Ok((token, tokens)) => Ok(render_settings(&auth.current_user, &tokens, Some(&token))),
+            Err(error) => {
+                println!("{:?}", error);
+                Ok(HttpResponse::InternalServerError()
+                    .content_type("text/html")
+                    .body("Something went wrong"))
+            }
+        })
+        .responder()
+}
+
+struct RevokeApiToken {
+    user_id: i32,
+    api_token_id: i32,
+}
+
+impl Message for RevokeApiToken {
+    type Result = Result<(), failure::Error>;
+}
+
+impl Handler<RevokeApiToken> for DbExecutor {
+    type Result = Result<(), failure::Error>;
+
+    fn handle(&mut self, msg: RevokeApiToken, _: &mut Self::Context) -> Self::Result {
+        use schema::api_tokens::dsl::*;
+
+        diesel::delete(
+            api_tokens
+                .filter(api_token_id.eq(msg.api_token_id))
+                .filter(user_id.eq(msg.user_id)),
+        ).execute(&self.connection)?;
+
+        Ok(())
+    }
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn revoke_token(
+    (auth, path, state): (CurrentUser, Path<(i32,)>, State<AppState>),
+) -> impl Responder {
+    state
+        .db
+        .send(RevokeApiToken {
+            user_id: auth.current_user.user_id,
+            api_token_id: path.0,
+        })
+        .and_then(|result| match result {
+            Ok(()) => Ok(HttpResponse::SeeOther()
+                .header("Location", "/settings")
+                .finish()),
+            Err(error) => {
+                println!("{:?}", error);
+                Ok(HttpResponse::InternalServerError()
+                    .content_type("text/html")
+                    .body("Something went wrong"))
+            }
+        })
+        .responder()
+}
+
+#[derive(Debug)]
+struct EmailTaken;
+
+impl fmt::Display for EmailTaken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "There already is an account with that email address")
+    }
+}
+
+impl StdError for EmailTaken {
+    fn description(&self) -> &str {
+        "There already is an account with that email address"
+    }
+}
+
+#[derive(Debug)]
+struct WrongPassword;
+
+impl fmt::Display for WrongPassword {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The current password is not correct")
+    }
+}
+
+impl StdError for WrongPassword {
+    fn description(&self) -> &str {
+        "The current password is not correct"
+    }
+}
+
+fn redirect_or_error(result: Result<(), failure::Error>) -> HttpResponse {
+    match result {
+        Ok(()) => HttpResponse::SeeOther()
+            .header("Location", "/settings")
+            .finish(),
+        Err(error) => {
+            println!("{:?}", error);
+            if error.downcast_ref::<EmailTaken>().is_some()
+                || error.downcast_ref::<WrongPassword>().is_some()
+                || error.downcast_ref::<PasswordMismatch>().is_some()
+                || error.downcast_ref::<FieldErrors>().is_some()
+            {
+                HttpResponse::BadRequest()
+                    .content_type("text/plain; charset=utf-8")
+                    .body(format!("{}", error))
+            } else {
+                HttpResponse::InternalServerError()
+                    .content_type("text/html")
+                    .body("Something went wrong")
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AccountForm {
+    display_name: String,
+    email: String,
+    /// Used to mention the user in Slack, without the @
+    #[serde(default)]
+    slack_handle: String,
+}
+
+struct UpdateAccount {
+    user_id: i32,
+    account: AccountForm,
+    policy: RegistrationPolicy,
+}
+
+impl Message for UpdateAccount {
+    type Result = Result<(), failure::Error>;
+}
+
+impl Handler<UpdateAccount> for DbExecutor {
+    type Result = Result<(), failure::Error>;
+
+    fn handle(&mut self, msg: UpdateAccount, _: &mut Self::Context) -> Self::Result {
+        use diesel::result::{DatabaseErrorKind, Error::DatabaseError};
+        use schema::users::dsl::*;
+
+        let new_email = msg.account.email.trim();
+        let new_display_name = msg.account.display_name.trim();
+        let new_slack_handle = msg.account.slack_handle.trim().trim_left_matches('@');
+        let new_slack_handle = if new_slack_handle.is_empty() {
+            None
+        } else {
+            Some(new_slack_handle)
+        };
+
+        let mut errors = FieldErrors::new();
+        errors.add("email", msg.policy.email_problem(new_email));
+        if new_display_name.is_empty() {
+            errors.add("display_name", Some("Please choose a name".to_string()));
+        } else if display_name_taken(&self.connection, new_display_name, Some(msg.user_id))? {
+            errors.add(
+                "display_name",
+                Some("This name is already taken".to_string()),
+            );
+        }
+        if !errors.is_empty() {
+            Err(errors)?
+        }
+
+        // The email address is also what people log in with
+        let result = diesel::update(users.filter(user_id.eq(msg.user_id)))
+            .set((
+                email.eq(new_email),
+                login.eq(new_email),
+                display_name.eq(new_display_name),
+                slack_handle.eq(new_slack_handle),
+            ))
+            .execute(&self.connection);
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => Err(EmailTaken)?,
+            Err(error) => Err(error)?,
+        }
+    }
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn update_account(
+    (auth, form, state): (CurrentUser, Form<AccountForm>, State<AppState>),
+) -> impl Responder {
+    state
+        .db
+        .send(UpdateAccount {
+            user_id: auth.current_user.user_id,
+            account: form.into_inner(),
+            policy: state.registration_policy.clone(),
+        })
+        .and_then(|result| Ok(redirect_or_error(result)))
+        .responder()
+}
+
+/// An unchecked checkbox isn't submitted at all
+#[derive(Deserialize, Debug)]
+pub struct RemindersForm {
+    #[serde(default)]
+    wants_reminder_emails: Option<String>,
+}
+
+struct UpdateReminders {
+    user_id: i32,
+    wants_reminder_emails: bool,
+}
+
+impl Message for UpdateReminders {
+    type Result = Result<(), failure::Error>;
+}
+
+impl Handler<UpdateReminders> for DbExecutor {
+    type Result = Result<(), failure::Error>;
+
+    fn handle(&mut self, msg: UpdateReminders, _: &mut Self::Context) -> Self::Result {
+        use schema::users::dsl::*;
+
+        diesel::update(users.filter(user_id.eq(msg.user_id)))
+            .set(wants_reminder_emails.eq(msg.wants_reminder_emails))
+            .execute(&self.connection)?;
+
+        Ok(())
+    }
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn update_reminders(
+    (auth, form, state): (CurrentUser, Form<RemindersForm>, State<AppState>),
+) -> impl Responder {
+    state
+        .db
+        .send(UpdateReminders {
+            user_id: auth.current_user.user_id,
+            wants_reminder_emails: form.wants_reminder_emails.is_some(),
+        })
+        .and_then(|result| Ok(redirect_or_error(result)))
+        .responder()
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChangePasswordForm {
+    current_password: String,
+    password: String,
+    password_confirmation: String,
+}
+
+struct ChangePassword {
+    user_id: i32,
+    passwords: ChangePasswordForm,
+}
+
+impl Message for ChangePassword {
+    type Result = Result<(), failure::Error>;
+}
+
+impl Handler<ChangePassword> for DbExecutor {
+    type Result = Result<(), failure::Error>;
+
+    fn handle(&mut self, msg: ChangePassword, _: &mut Self::Context) -> Self::Result {
+        use schema::users::dsl::*;
+
+        if msg.passwords.password != msg.passwords.password_confirmation {
+            Err(PasswordMismatch)?
+        }
+
+        let user = users
+            .filter(user_id.eq(msg.user_id))
+            .first::<User>(&self.connection)?;
+
+        let mut errors = FieldErrors::new();
+        errors.add(
+            "password",
+            password_problem(&msg.passwords.password, &user.email),
+        );
+        if !errors.is_empty() {
+            Err(errors)?
+        }
+
+        if !verify(&msg.passwords.current_password, &user.encrypted_password)? {
+            Err(WrongPassword)?
+        }
+
+        diesel::update(users.filter(user_id.eq(msg.user_id)))
+            .set(encrypted_password.eq(hash(&msg.passwords.password, DEFAULT_COST)?))
+            .execute(&self.connection)?;
+
+        Ok(())
+    }
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn change_password(
+    (auth, form, state): (CurrentUser, Form<ChangePasswordForm>, State<AppState>),
+) -> impl Responder {
+    state
+        .db
+        .send(ChangePassword {
+            user_id: auth.current_user.user_id,
+            passwords: form.into_inner(),
+        })
+        .and_then(|result| Ok(redirect_or_error(result)))
+        .responder()
+}
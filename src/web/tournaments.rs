@@ -0,0 +1,242 @@
+//! Every World Cup and Euro is a tournament of its own. Users keep one account across
+//! tournaments and look at one tournament at a time: the current one, unless they switched to
+//! an archived one.
+
+use models::Tournament;
+use templates::{Context, TEMPLATE_SERVICE};
+use web::{
+    app_state::{AppState, DbExecutor}, auth::CurrentUser,
+};
+
+use actix::prelude::*;
+use actix_web::{AsyncResponder, HttpResponse, Path, Responder, State};
+use diesel::{self, prelude::*};
+use failure;
+use futures::Future;
+use std::collections::HashMap;
+
+/// The newest tournament that isn't archived, or the newest one when all of them are
+pub fn current_tournament(conn: &PgConnection) -> QueryResult<Tournament> {
+    use schema::tournaments::dsl::*;
+
+    tournaments
+        .order((is_archived.asc(), created_at.desc(), tournament_id.desc()))
+        .first(conn)
+}
+
+/// The tournament the user is looking at
+pub fn viewed_tournament(conn: &PgConnection, for_user_id: i32) -> QueryResult<Tournament> {
+    use schema::{tournaments, users};
+
+    let selected = users::table
+        .find(for_user_id)
+        .select(users::tournament_id)
+        .first::<Option<i32>>(conn)?;
+
+    match selected {
+        Some(id) => tournaments::table.find(id).first(conn),
+        None => current_tournament(conn),
+    }
+}
+
+/// The points of one user over all tournaments, `scores` has an entry for every tournament of
+/// the overview, in the same order
+#[derive(Serialize, Debug)]
+pub struct AllTimeScore {
+    pub user_id: i32,
+    pub display_name: String,
+    pub scores: Vec<Option<i32>>,
+    pub total: i32,
+    /// The number of tournaments in which the user played
+    pub tournaments: usize,
+    /// The number of tournaments the user won, shared first places included
+    pub wins: usize,
+}
+
+#[derive(Serialize)]
+pub struct TournamentOverview {
+    pub tournaments: Vec<Tournament>,
+    pub viewed: Tournament,
+    pub all_time: Vec<AllTimeScore>,
+}
+
+fn all_time_scores(
+    conn: &PgConnection,
+    tournaments: &[Tournament],
+) -> QueryResult<Vec<AllTimeScore>> {
+    use schema::{tournament_scores, users};
+
+    let scores = tournament_scores::table
+        .inner_join(users::table)
+        .select((
+            users::user_id,
+            users::display_name,
+            users::login,
+            tournament_scores::tournament_id,
+            tournament_scores::score,
+        ))
+        .load::<(i32, Option<String>, String, i32, i32)>(conn)?;
+
+    let mut best = HashMap::new();
+    for &(_, _, _, tournament_id, score) in &scores {
+        let top = best.entry(tournament_id).or_insert(score);
+        if score > *top {
+            *top = score;
+        }
+    }
+
+    let mut all_time = HashMap::new();
+    for (user_id, display_name, login, tournament_id, score) in scores {
+        let entry = all_time.entry(user_id).or_insert_with(|| AllTimeScore {
+            user_id,
+            display_name: display_name.unwrap_or(login),
+            scores: vec![None; tournaments.len()],
+            total: 0,
+            tournaments: 0,
+            wins: 0,
+        });
+        if let Some(index) = tournaments
+            .iter()
+            .position(|tournament| tournament.tournament_id == tournament_id)
+        {
+            entry.scores[index] = Some(score);
+        }
+        entry.total += score;
+        entry.tournaments += 1;
+        if best.get(&tournament_id) == Some(&score) {
+            entry.wins += 1;
+        }
+    }
+
+    let mut all_time = all_time.into_iter().map(|(_, score)| score).collect::<Vec<_>>();
+    all_time.sort_by(|a, b| {
+        b.total
+            .cmp(&a.total)
+            .then(a.display_name.cmp(&b.display_name))
+    });
+
+    Ok(all_time)
+}
+
+pub struct FetchTournamentOverview {
+    pub user_id: i32,
+}
+
+impl Message for FetchTournamentOverview {
+    type Result = Result<TournamentOverview, failure::Error>;
+}
+
+impl Handler<FetchTournamentOverview> for DbExecutor {
+    type Result = Result<TournamentOverview, failure::Error>;
+
+    fn handle(&mut self, msg: FetchTournamentOverview, _: &mut Self::Context) -> Self::Result {
+        use schema::tournaments::dsl::*;
+
+        let all_tournaments = tournaments
+            .order((created_at.asc(), tournament_id.asc()))
+            .load::<Tournament>(&self.connection)?;
+
+        Ok(TournamentOverview {
+            all_time: all_time_scores(&self.connection, &all_tournaments)?,
+            viewed: viewed_tournament(&self.connection, msg.user_id)?,
+            tournaments: all_tournaments,
+        })
+    }
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn index((auth, state): (CurrentUser, State<AppState>)) -> impl Responder {
+    state
+        .db
+        .send(FetchTournamentOverview {
+            user_id: auth.current_user.user_id,
+        })
+        .and_then(move |result| match result {
+            Ok(overview) => {
+                let mut context = Context::new();
+                context.add("current_user", &auth.current_user);
+                context.add("tournaments", &overview.tournaments);
+                context.add("viewed", &overview.viewed);
+                context.add("all_time", &overview.all_time);
+
+                let rendered = TEMPLATE_SERVICE.render("tournaments/index.html", &context);
+
+                match rendered {
+                    Ok(body) => Ok(HttpResponse::Ok().content_type("text/html").body(body)),
+                    Err(error) => {
+                        println!("{:?}", error);
+                        Ok(HttpResponse::InternalServerError()
+                            .content_type("text/html")
+                            .body("Something went wrong"))
+                    }
+                }
+            }
+            Err(error) => {
+                println!("{:?}", error);
+                Ok(HttpResponse::InternalServerError()
+                    .content_type("text/html")
+                    .body("Something went wrong"))
+            }
+        })
+        .responder()
+}
+
+struct SelectTournament {
+    user_id: i32,
+    tournament_id: i32,
+}
+
+impl Message for SelectTournament {
+    type Result = Result<(), failure::Error>;
+}
+
+impl Handler<SelectTournament> for DbExecutor {
+    type Result = Result<(), failure::Error>;
+
+    fn handle(&mut self, msg: SelectTournament, _: &mut Self::Context) -> Self::Result {
+        use schema::{tournaments, users};
+
+        let selected = tournaments::table
+            .find(msg.tournament_id)
+            .first::<Tournament>(&self.connection)?;
+        // Users that look at the current tournament move on to the next one once it starts
+        let current = current_tournament(&self.connection)?;
+        let choice = if selected.tournament_id == current.tournament_id {
+            None
+        } else {
+            Some(selected.tournament_id)
+        };
+
+        diesel::update(users::table.find(msg.user_id))
+            .set(users::tournament_id.eq(choice))
+            .execute(&self.connection)?;
+
+        Ok(())
+    }
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn select((auth, path, state): (CurrentUser, Path<(i32,)>, State<AppState>)) -> impl Responder {
+    state
+        .db
+        .send(SelectTournament {
+            user_id: auth.current_user.user_id,
+            tournament_id: path.0,
+        })
+        .and_then(|result| match result {
+            Ok(()) => Ok(HttpResponse::SeeOther().header("Location", "/").finish()),
+            Err(error) => {
+                println!("{:?}", error);
+                if let Some(diesel::result::Error::NotFound) = error.downcast_ref() {
+                    Ok(HttpResponse::NotFound()
+                        .content_type("text/html")
+                        .body("There is no such tournament"))
+                } else {
+                    Ok(HttpResponse::InternalServerError()
+                        .content_type("text/html")
+                        .body("Something went wrong"))
+                }
+            }
+        })
+        .responder()
+}
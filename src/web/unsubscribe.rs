@@ -0,0 +1,105 @@
+//! The unsubscribe links in reminder emails, they work without logging in. Opening the link only
+//! asks for confirmation, so mail scanners that follow links don't unsubscribe anyone.
+
+use templates::{Context, TEMPLATE_SERVICE};
+use web::app_state::{AppState, DbExecutor};
+use web::auth::hash_token;
+
+use actix::prelude::*;
+use actix_web::{AsyncResponder, FutureResponse, HttpResponse, Path, State};
+use diesel::{self, prelude::*};
+use failure;
+use futures::Future;
+use std::{error::Error as StdError, fmt};
+
+fn render(template: &str, context: &Context) -> HttpResponse {
+    match TEMPLATE_SERVICE.render(template, context) {
+        Ok(body) => HttpResponse::Ok().content_type("text/html").body(body),
+        Err(error) => {
+            println!("{}", error);
+            HttpResponse::InternalServerError()
+                .content_type("text/html")
+                .body("Something went wrong")
+        }
+    }
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn show(path: Path<(String,)>) -> HttpResponse {
+    let mut context = Context::new();
+    context.add("token", &path.0);
+
+    render("unsubscribe/show.html", &context)
+}
+
+#[derive(Debug)]
+pub struct InvalidUnsubscribeToken;
+
+impl fmt::Display for InvalidUnsubscribeToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "This unsubscribe link is invalid")
+    }
+}
+
+impl StdError for InvalidUnsubscribeToken {
+    fn description(&self) -> &str {
+        "This unsubscribe link is invalid"
+    }
+}
+
+struct Unsubscribe {
+    token: String,
+}
+
+impl Message for Unsubscribe {
+    type Result = Result<(), failure::Error>;
+}
+
+impl Handler<Unsubscribe> for DbExecutor {
+    type Result = Result<(), failure::Error>;
+
+    fn handle(&mut self, msg: Unsubscribe, _: &mut Self::Context) -> Self::Result {
+        use schema::{unsubscribe_tokens, users};
+
+        let user_id = unsubscribe_tokens::table
+            .filter(unsubscribe_tokens::token_hash.eq(hash_token(&msg.token)))
+            .select(unsubscribe_tokens::user_id)
+            .first::<i32>(&self.connection)
+            .optional()?
+            .ok_or(InvalidUnsubscribeToken)?;
+
+        diesel::update(users::table.find(user_id))
+            .set(users::wants_reminder_emails.eq(false))
+            .execute(&self.connection)?;
+
+        Ok(())
+    }
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn unsubscribe(
+    (path, state): (Path<(String,)>, State<AppState>),
+) -> FutureResponse<HttpResponse> {
+    state
+        .db
+        .send(Unsubscribe {
+            token: path.0.clone(),
+        })
+        .from_err()
+        .and_then(|result| match result {
+            Ok(()) => Ok(render("unsubscribe/done.html", &Context::new())),
+            Err(error) => {
+                println!("{:?}", error);
+                if let Some(invalid) = error.downcast_ref::<InvalidUnsubscribeToken>() {
+                    let mut context = Context::new();
+                    context.add("error", &invalid.to_string());
+                    Ok(render("unsubscribe/done.html", &context))
+                } else {
+                    Ok(HttpResponse::InternalServerError()
+                        .content_type("text/html")
+                        .body("Something went wrong"))
+                }
+            }
+        })
+        .responder()
+}
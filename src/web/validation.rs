@@ -0,0 +1,130 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::{error::Error as StdError, fmt};
+
+const MIN_PASSWORD_LENGTH: usize = 8;
+
+/// Who is allowed to register, configured through the environment:
+///
+/// - `REGISTRATION_EMAIL_DOMAINS`: comma separated list of domains, e.g. `example.com,example.org`
+/// - `REGISTRATION_INVITE_CODE`: code that has to be entered to be able to register
+#[derive(Clone, Debug, Default)]
+pub struct RegistrationPolicy {
+    pub allowed_email_domains: Vec<String>,
+    pub invite_code: Option<String>,
+}
+
+impl RegistrationPolicy {
+    pub fn from_env() -> RegistrationPolicy {
+        RegistrationPolicy {
+            allowed_email_domains: env::var("REGISTRATION_EMAIL_DOMAINS")
+                .map(|domains| {
+                    domains
+                        .split(',')
+                        .map(|domain| domain.trim().to_lowercase())
+                        .filter(|domain| !domain.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            invite_code: env::var("REGISTRATION_INVITE_CODE")
+                .ok()
+                .filter(|code| !code.is_empty()),
+        }
+    }
+
+    pub fn email_problem(&self, email: &str) -> Option<String> {
+        if !valid_email(email) {
+            return Some("This is not a valid email address".to_string());
+        }
+
+        let domain = email.rsplit('@').next().unwrap_or("").to_lowercase();
+        if !self.allowed_email_domains.is_empty()
+            && !self.allowed_email_domains.iter().any(|allowed| *allowed == domain)
+        {
+            Some(format!(
+                "Only email addresses of {} can be used",
+                self.allowed_email_domains.join(", ")
+            ))
+        } else {
+            None
+        }
+    }
+
+    pub fn invite_code_problem(&self, invite_code: &str) -> Option<String> {
+        match self.invite_code {
+            Some(ref expected) if expected != invite_code.trim() => {
+                Some("This invite code is not valid".to_string())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A deliberately simple check, the only real test is whether mail arrives
+pub fn valid_email(email: &str) -> bool {
+    let mut parts = email.split('@');
+
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(local), Some(domain), None) => {
+            !local.is_empty()
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !email.chars().any(char::is_whitespace)
+        }
+        _ => false,
+    }
+}
+
+/// The password policy, returns what is wrong with the password
+pub fn password_problem(password: &str, email: &str) -> Option<String> {
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        Some(format!(
+            "Passwords need at least {} characters",
+            MIN_PASSWORD_LENGTH
+        ))
+    } else if !password.chars().any(char::is_alphabetic)
+        || !password.chars().any(|c| !c.is_alphabetic())
+    {
+        Some("Passwords need both letters and digits or symbols".to_string())
+    } else if password.to_lowercase() == email.to_lowercase() {
+        Some("Your password can't be your email address".to_string())
+    } else {
+        None
+    }
+}
+
+/// Validation messages per form field, so they can be shown next to the field
+#[derive(Debug, Default, Serialize)]
+pub struct FieldErrors {
+    pub errors: BTreeMap<&'static str, String>,
+}
+
+impl FieldErrors {
+    pub fn new() -> FieldErrors {
+        FieldErrors::default()
+    }
+
+    pub fn add(&mut self, field: &'static str, message: Option<String>) {
+        if let Some(message) = message {
+            self.errors.entry(field).or_insert(message);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+impl fmt::Display for FieldErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let messages = self.errors.values().cloned().collect::<Vec<_>>();
+        write!(f, "{}", messages.join(", "))
+    }
+}
+
+impl StdError for FieldErrors {
+    fn description(&self) -> &str {
+        "Invalid form"
+    }
+}